@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use tokio::sync::RwLock;
+
+use crate::items::{format_amount, Catalogue};
+use crate::store::{data_lock, read_json, sidecar_path, write_json};
+
+/// Most units of one item in a cart
+pub(crate) const MAX_QUANTITY: u64 = 99;
+/// Most different items in a cart
+pub(crate) const MAX_LINES: usize = 50;
+
+/// Units of one item in a cart
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct CartLine {
+  pub item_id: u64,
+  pub quantity: u64,
+  /// Unit price when the line was last changed, to tell the customer when
+  /// the price changed since. Changing the quantity accepts the new price.
+  pub price_minor: Option<i64>,
+  pub added_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct Cart {
+  pub currency: String,
+  pub region: String,
+  pub lines: Vec<CartLine>,
+  pub created_at: String,
+  /// Carts expire a while after their last change
+  pub updated_at: String,
+}
+
+impl Cart {
+  pub(crate) fn new(currency: &str, region: &str) -> Self {
+    let now = Utc::now().to_rfc3339();
+    Self {
+      currency: currency.to_string(),
+      region: region.to_string(),
+      lines: Vec::new(),
+      created_at: now.clone(),
+      updated_at: now,
+    }
+  }
+
+  /// `None` when the TTL is too long to ever run out
+  pub(crate) fn expires_at(&self, ttl: Duration) -> Option<DateTime<Utc>> {
+    let updated_at = DateTime::parse_from_rfc3339(&self.updated_at).ok()?;
+    updated_at.with_timezone(&Utc).checked_add_signed(chrono::Duration::from_std(ttl).ok()?)
+  }
+
+  pub(crate) fn is_expired(&self, ttl: Duration, now: DateTime<Utc>) -> bool {
+    self.expires_at(ttl).is_some_and(|expires_at| expires_at <= now)
+  }
+
+  pub(crate) fn line(&self, item_id: u64) -> Option<&CartLine> {
+    self.lines.iter().find(|l| l.item_id == item_id)
+  }
+
+  /// Sets the quantity of the item, adding a line when it isn't in the cart yet
+  pub(crate) fn set_quantity(&mut self, item_id: u64, quantity: u64, price_minor: Option<i64>) {
+    match self.lines.iter_mut().find(|l| l.item_id == item_id) {
+      Some(line) => {
+        line.quantity = quantity;
+        line.price_minor = price_minor;
+      }
+      None => self.lines.push(CartLine { item_id, quantity, price_minor, added_at: Utc::now().to_rfc3339() }),
+    }
+  }
+
+  /// Removes the item, returns whether it was in the cart
+  pub(crate) fn remove(&mut self, item_id: u64) -> bool {
+    let before = self.lines.len();
+    self.lines.retain(|l| l.item_id != item_id);
+    self.lines.len() != before
+  }
+
+  pub(crate) fn touch(&mut self) {
+    self.updated_at = Utc::now().to_rfc3339();
+  }
+
+  /// Adds the lines of another cart, quantities of the same item add up.
+  /// Prices seen in another currency or region are not kept.
+  pub(crate) fn merge(&mut self, other: Cart) {
+    let same_prices = self.currency == other.currency && self.region == other.region;
+    for line in other.lines {
+      let full = self.lines.len() >= MAX_LINES;
+      match self.lines.iter_mut().find(|l| l.item_id == line.item_id) {
+        Some(own) => own.quantity = own.quantity.saturating_add(line.quantity).min(MAX_QUANTITY),
+        None if !full => self.lines.push(CartLine {
+          price_minor: line.price_minor.filter(|_| same_prices),
+          ..line
+        }),
+        None => {}
+      }
+    }
+  }
+
+  /// Moves the lines of merged items to the item they were merged into,
+  /// quantities of the same item add up
+  pub(crate) fn follow_merges(&mut self, catalogue: &Catalogue) {
+    for line in std::mem::take(&mut self.lines) {
+      let item_id = catalogue.resolve(line.item_id);
+      match self.lines.iter_mut().find(|l| l.item_id == item_id) {
+        Some(own) => own.quantity = own.quantity.saturating_add(line.quantity).min(MAX_QUANTITY),
+        None => self.lines.push(CartLine { item_id, ..line }),
+      }
+    }
+  }
+}
+
+/// Carts by owner, see `user_key` and `anonymous_key`
+pub(crate) type Carts = BTreeMap<String, Cart>;
+
+pub(crate) fn user_key(sub: &str) -> String {
+  format!("user:{}", sub)
+}
+
+pub(crate) fn anonymous_key(token: &str) -> String {
+  format!("anonymous:{}", token)
+}
+
+/// Lock of the carts file, apart from the lock of the items so that changing a
+/// cart never holds up changes to the items
+pub(crate) fn carts_lock(data_path: &str) -> Arc<RwLock<()>> {
+  data_lock(&sidecar_path(data_path, "carts"))
+}
+
+pub(crate) fn read_carts(data_path: &str) -> std::io::Result<Carts> {
+  read_json(&sidecar_path(data_path, "carts"))
+}
+
+pub(crate) fn write_carts(data_path: &str, carts: &Carts) -> std::io::Result<()> {
+  write_json(&sidecar_path(data_path, "carts"), carts)
+}
+
+fn problem(code: &str, msg: String) -> Value {
+  json!({ "code": code, "msg": msg })
+}
+
+/// The cart checked against the catalogue as it is now: every line shows its
+/// current price and what stands in the way of buying it
+pub(crate) fn cart_view(cart: &Cart, catalogue: &Catalogue, ttl: Duration) -> Value {
+  let mut cart = cart.clone();
+  cart.follow_merges(catalogue);
+  let mut lines = Vec::new();
+  let mut total_minor: i64 = 0;
+  let mut valid = true;
+  for line in cart.lines.iter() {
+    let mut res = json!({ "item_id": line.item_id, "quantity": line.quantity });
+    let mut problems = Vec::new();
+
+    match catalogue.offer(line.item_id, &cart.region, &cart.currency) {
+      Some(offer) => {
+        res["name"] = offer.item.get("name").cloned().unwrap_or(Value::Null);
+        if let Some(msg) = offer.not_for_sale {
+          problems.push(problem("not_for_sale", msg));
+        }
+        if let Some(available) = offer.available {
+          res["available"] = json!(available);
+          if available < line.quantity {
+            problems.push(problem("insufficient_stock", format!("Only {} available", available)));
+          }
+        }
+        match offer.price_minor {
+          Some(price_minor) => {
+            let line_total = price_minor * line.quantity as i64;
+            total_minor += line_total;
+            res["unit_price"] = json!(format_amount(price_minor, &cart.currency));
+            res["unit_price_minor"] = json!(price_minor);
+            res["total"] = json!(format_amount(line_total, &cart.currency));
+            res["total_minor"] = json!(line_total);
+            if let Some(seen) = line.price_minor.filter(|seen| *seen != price_minor) {
+              let mut changed = problem("price_changed", format!("Price changed from {}", format_amount(seen, &cart.currency)));
+              changed["previous_unit_price"] = json!(format_amount(seen, &cart.currency));
+              problems.push(changed);
+            }
+          }
+          None => problems.push(problem("no_price", format!("Item has no price in {}", cart.currency))),
+        }
+      }
+      None => problems.push(problem("unavailable", "Item no longer exists".to_string())),
+    }
+
+    valid &= problems.is_empty();
+    res["problems"] = json!(problems);
+    lines.push(res);
+  }
+
+  json!({
+    "currency": cart.currency,
+    "region": cart.region,
+    "lines": lines,
+    "item_count": cart.lines.iter().map(|l| l.quantity).sum::<u64>(),
+    "total": format_amount(total_minor, &cart.currency),
+    "total_minor": total_minor,
+    "valid": valid,
+    "created_at": cart.created_at,
+    "updated_at": cart.updated_at,
+    "expires_at": cart.expires_at(ttl).map(|e| e.to_rfc3339()),
+  })
+}
+
+/// Removes carts that haven't changed for `ttl`, returns how many were removed
+pub async fn expire_carts(data_path: &str, ttl: Duration) -> std::io::Result<usize> {
+  let _guard = carts_lock(data_path).write_owned().await;
+
+  let mut carts = read_carts(data_path)?;
+  let now = Utc::now();
+  let before = carts.len();
+  carts.retain(|_key, cart| !cart.is_expired(ttl, now));
+
+  let expired = before - carts.len();
+  if expired > 0 {
+    write_carts(data_path, &carts)?;
+  }
+  Ok(expired)
+}
@@ -0,0 +1,406 @@
+use std::sync::Arc;
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use poem::http::Method;
+use poem::web::{Data, Path};
+use poem::{get, handler, post, put, http::StatusCode, web::Json, Endpoint, EndpointExt, IntoResponse, Middleware, Request, Response, Result, Route};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use crate::items::{load_items, parse_currency, Catalogue, GLOBAL_REGION};
+use crate::store::data_lock;
+use crate::{error_response_json, response_json, Claims, Config, ErrorResponse, SECRET_KEY};
+pub use cart::expire_carts;
+use cart::{anonymous_key, cart_view, carts_lock, read_carts, user_key, write_carts, Cart, Carts, MAX_LINES, MAX_QUANTITY};
+
+mod cart;
+
+/// Header that carries the token of an anonymous cart
+const CART_TOKEN_HEADER: &str = "X-Cart-Token";
+
+pub fn route(data_path: String) -> Route {
+  Route::new()
+    .at("/", get(get_cart)
+      .put(put_cart)
+      .delete(delete_cart)
+      .with(CartMiddleware::new(data_path.clone().into()))
+    )
+    .at("/anonymous", post(post_anonymous_cart)
+      .with(CartMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items", post(post_cart_item)
+      .with(CartMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:item_id", put(put_cart_item)
+      .delete(delete_cart_item)
+      .with(CartMiddleware::new(data_path.clone().into()))
+    )
+    .at("/merge", post(merge_cart)
+      .with(CartMiddleware::new(data_path.clone().into()))
+    )
+}
+
+#[handler]
+async fn get_cart(req: &Request, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  let carts = load_carts(data_path.as_str(), "get_cart")?;
+  let (_key, cart) = callers_cart(req, &carts, &config)?;
+  Ok(response_json(StatusCode::OK, view(req, &cart, &config)))
+}
+
+/// Changes the currency and region the cart is priced in
+#[handler]
+async fn put_cart(req: &Request, cart_req: Json<CartReq>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  let mut carts = load_carts(data_path.as_str(), "put_cart")?;
+  let (key, mut cart) = callers_cart(req, &carts, &config)?;
+
+  cart.currency = match parse_currency(&cart_req.currency) {
+    Ok(res) => res,
+    Err(msg) => return Err(invalid_cart(msg))
+  };
+  cart.region = cart_req.region.trim().to_lowercase();
+  if cart.region.is_empty() {
+    return Err(invalid_cart("region must not be empty".to_string()))
+  }
+
+  // Prices seen in the old currency say nothing about the new one
+  let catalogue = load_catalogue(req);
+  cart.follow_merges(&catalogue);
+  for line in cart.lines.iter_mut() {
+    line.price_minor = catalogue
+      .offer(line.item_id, &cart.region, &cart.currency)
+      .and_then(|offer| offer.price_minor);
+  }
+
+  save_cart(&mut carts, key, &mut cart, data_path.as_str(), "put_cart")?;
+  Ok(response_json(StatusCode::OK, cart_view(&cart, &catalogue, config.cart_ttl)))
+}
+
+/// Empties the cart
+#[handler]
+async fn delete_cart(req: &Request, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  let mut carts = load_carts(data_path.as_str(), "delete_cart")?;
+  let (key, mut cart) = callers_cart(req, &carts, &config)?;
+
+  cart.lines.clear();
+  save_cart(&mut carts, key, &mut cart, data_path.as_str(), "delete_cart")?;
+  Ok(response_json(StatusCode::OK, view(req, &cart, &config)))
+}
+
+/// Starts a cart for a customer who hasn't logged in, later requests send
+/// the returned `cart_token` in the `X-Cart-Token` header. At most
+/// `Config::max_anonymous_carts` of them are open at once.
+#[handler]
+async fn post_anonymous_cart(req: &Request, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  let mut carts = load_carts(data_path.as_str(), "post_anonymous_cart")?;
+
+  // Expired carts are dropped here already so they don't count
+  let now = chrono::Utc::now();
+  carts.retain(|_key, cart| !cart.is_expired(config.cart_ttl, now));
+  let open = carts.keys().filter(|key| key.starts_with(&anonymous_key(""))).count();
+  if open >= config.max_anonymous_carts {
+    return Err(error_response_json(StatusCode::TOO_MANY_REQUESTS, ErrorResponse {
+      error: "Too many carts".to_string(),
+      msg: "Please try again later or log in".to_string()
+    }))
+  }
+
+  let token = ulid::Ulid::new().to_string();
+  let mut cart = Cart::new(&config.default_currency, GLOBAL_REGION);
+  save_cart(&mut carts, anonymous_key(&token), &mut cart, data_path.as_str(), "post_anonymous_cart")?;
+
+  let mut res = view(req, &cart, &config);
+  res["cart_token"] = json!(token);
+  Ok(response_json(StatusCode::CREATED, res))
+}
+
+/// Adds units of an item, on top of those already in the cart
+#[handler]
+async fn post_cart_item(req: &Request, line_req: Json<CartLineReq>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  let mut carts = load_carts(data_path.as_str(), "post_cart_item")?;
+  let (key, mut cart) = callers_cart(req, &carts, &config)?;
+
+  let item_id = match line_req.item_id {
+    Some(res) => res,
+    None => return Err(invalid_cart("item_id is required".to_string()))
+  };
+  let catalogue = load_catalogue(req);
+  cart.follow_merges(&catalogue);
+  let item_id = catalogue.resolve(item_id);
+  let in_cart = cart.line(item_id).map(|l| l.quantity);
+  if in_cart.is_none() && cart.lines.len() >= MAX_LINES {
+    return Err(invalid_cart(format!("A cart can hold at most {} different items", MAX_LINES)))
+  }
+
+  let quantity = match in_cart.unwrap_or(0).checked_add(line_req.quantity) {
+    Some(res) if line_req.quantity <= MAX_QUANTITY => res,
+    _ => return Err(invalid_cart(format!("quantity must be 1 to {} in total", MAX_QUANTITY)))
+  };
+  let price_minor = check_line(&catalogue, &cart, item_id, line_req.quantity, quantity)?;
+  cart.set_quantity(item_id, quantity, price_minor);
+
+  save_cart(&mut carts, key, &mut cart, data_path.as_str(), "post_cart_item")?;
+  Ok(response_json(StatusCode::OK, cart_view(&cart, &catalogue, config.cart_ttl)))
+}
+
+/// Sets how many units of the item the cart holds, which also accepts its current price
+#[handler]
+async fn put_cart_item(req: &Request, item_id: Path<u64>, line_req: Json<CartLineReq>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  let mut carts = load_carts(data_path.as_str(), "put_cart_item")?;
+  let (key, mut cart) = callers_cart(req, &carts, &config)?;
+
+  let catalogue = load_catalogue(req);
+  cart.follow_merges(&catalogue);
+  let item_id = catalogue.resolve(*item_id);
+  if cart.line(item_id).is_none() {
+    return Err(not_in_cart())
+  }
+
+  let price_minor = check_line(&catalogue, &cart, item_id, line_req.quantity, line_req.quantity)?;
+  cart.set_quantity(item_id, line_req.quantity, price_minor);
+
+  save_cart(&mut carts, key, &mut cart, data_path.as_str(), "put_cart_item")?;
+  Ok(response_json(StatusCode::OK, cart_view(&cart, &catalogue, config.cart_ttl)))
+}
+
+#[handler]
+async fn delete_cart_item(req: &Request, item_id: Path<u64>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  let mut carts = load_carts(data_path.as_str(), "delete_cart_item")?;
+  let (key, mut cart) = callers_cart(req, &carts, &config)?;
+
+  let catalogue = load_catalogue(req);
+  cart.follow_merges(&catalogue);
+  if !cart.remove(catalogue.resolve(*item_id)) {
+    return Err(not_in_cart())
+  }
+
+  save_cart(&mut carts, key, &mut cart, data_path.as_str(), "delete_cart_item")?;
+  Ok(response_json(StatusCode::OK, cart_view(&cart, &catalogue, config.cart_ttl)))
+}
+
+/// Moves the anonymous cart into the cart of the user. Logging in at
+/// `/users/login` doesn't know about carts, so clients call this right after
+/// with the new JWT and the token of the anonymous cart. The anonymous cart
+/// is gone afterwards.
+#[handler]
+async fn merge_cart(req: &Request, merge_req: Json<CartMergeReq>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  if req.extensions().get::<Claims>().is_none() {
+    return Err(unauthorized())
+  }
+
+  let mut carts = load_carts(data_path.as_str(), "merge_cart")?;
+  let anonymous = match carts.remove(&anonymous_key(&merge_req.cart_token)) {
+    Some(res) if !res.is_expired(config.cart_ttl, chrono::Utc::now()) => res,
+    _ => return Err(cart_not_found())
+  };
+  let (key, mut cart) = callers_cart(req, &carts, &config)?;
+
+  cart.merge(anonymous);
+  save_cart(&mut carts, key, &mut cart, data_path.as_str(), "merge_cart")?;
+  Ok(response_json(StatusCode::OK, view(req, &cart, &config)))
+}
+
+/// Checks that `added` more units of the item can go in the cart for a total
+/// of `quantity`, returns the current unit price
+fn check_line(catalogue: &Catalogue, cart: &Cart, item_id: u64, added: u64, quantity: u64) -> Result<Option<i64>> {
+  if added == 0 || quantity > MAX_QUANTITY {
+    return Err(invalid_cart(format!("quantity must be 1 to {} in total", MAX_QUANTITY)))
+  }
+
+  let offer = match catalogue.offer(item_id, &cart.region, &cart.currency) {
+    Some(res) => res,
+    None => return Err(invalid_cart(format!("Item {} does not exist", item_id)))
+  };
+  if let Some(msg) = offer.not_for_sale {
+    return Err(error_response_json(StatusCode::CONFLICT, ErrorResponse {
+      error: "Item can't be bought".to_string(),
+      msg
+    }))
+  }
+  match offer.available {
+    Some(available) if available < quantity => {
+      Err(error_response_json(StatusCode::CONFLICT, json!({
+        "error": "Insufficient stock",
+        "msg": format!("Only {} available", available),
+        "available": available
+      })))
+    }
+    _ => Ok(offer.price_minor)
+  }
+}
+
+/// Key and cart of the caller: the user's cart when logged in, otherwise the
+/// anonymous cart of the `X-Cart-Token` header. Users get an empty cart when
+/// they have none, anonymous carts have to be started first.
+fn callers_cart(req: &Request, carts: &Carts, config: &Config) -> Result<(String, Cart)> {
+  let now = chrono::Utc::now();
+  if let Some(claims) = req.extensions().get::<Claims>() {
+    let key = user_key(&claims.sub);
+    return match carts.get(&key) {
+      Some(cart) if !cart.is_expired(config.cart_ttl, now) => Ok((key, cart.clone())),
+      _ => Ok((key, Cart::new(&config.default_currency, GLOBAL_REGION)))
+    }
+  }
+
+  let key = match req.headers().get(CART_TOKEN_HEADER).and_then(|t| t.to_str().ok()) {
+    Some(token) => anonymous_key(token),
+    None => return Err(unauthorized())
+  };
+  match carts.get(&key) {
+    Some(cart) if !cart.is_expired(config.cart_ttl, now) => Ok((key, cart.clone())),
+    _ => Err(cart_not_found())
+  }
+}
+
+fn load_carts(data_path: &str, handler: &str) -> Result<Carts> {
+  read_carts(data_path).map_err(|_e| error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+    error: format!("Server error {} carts 1", handler),
+    msg: "Please contact support".to_string()
+  }))
+}
+
+/// Stores the cart, which counts as a change that keeps it from expiring
+fn save_cart(carts: &mut Carts, key: String, cart: &mut Cart, data_path: &str, handler: &str) -> Result<()> {
+  cart.touch();
+  carts.insert(key, cart.clone());
+  write_carts(data_path, carts).map_err(|_e| error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+    error: format!("Server error {} carts 2", handler),
+    msg: "Please contact support".to_string()
+  }))
+}
+
+fn load_catalogue(req: &Request) -> Arc<Catalogue> {
+  // Error handling on this already in CartMiddleware
+  req.extensions().get::<Arc<Catalogue>>().unwrap().clone()
+}
+
+fn view(req: &Request, cart: &Cart, config: &Config) -> Value {
+  cart_view(cart, &load_catalogue(req), config.cart_ttl)
+}
+
+fn invalid_cart(msg: String) -> poem::Error {
+  error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+    error: "Invalid cart".to_string(),
+    msg
+  })
+}
+
+fn not_in_cart() -> poem::Error {
+  error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+    error: "Not found".to_string(),
+    msg: "Item is not in the cart".to_string()
+  })
+}
+
+fn cart_not_found() -> poem::Error {
+  error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+    error: "Not found".to_string(),
+    msg: "Cart does not exist or has expired".to_string()
+  })
+}
+
+fn unauthorized() -> poem::Error {
+  error_response_json(StatusCode::UNAUTHORIZED, ErrorResponse {
+    error: "Unauthorized".to_string(),
+    msg: format!("Please log in or send the {} of an anonymous cart", CART_TOKEN_HEADER)
+  })
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CartReq {
+  currency: String,
+  #[serde(default = "global_region")]
+  region: String,
+}
+
+fn global_region() -> String {
+  GLOBAL_REGION.to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CartLineReq {
+  /// Only used when adding, the path names the item otherwise
+  item_id: Option<u64>,
+  #[serde(default = "one")]
+  quantity: u64,
+}
+
+fn one() -> u64 {
+  1
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CartMergeReq {
+  /// Token of the anonymous cart that is merged
+  cart_token: String,
+}
+
+/// Loads the catalogue into the request extensions and the claims of the JWT
+/// when there is one. Unlike `AuthMiddleware` a JWT is never required,
+/// anonymous carts are written without one.
+///
+/// Requests hold the lock of the carts, the items are only read-locked while
+/// the catalogue is loaded so carts never hold up changes to the items.
+struct CartMiddleware {
+  data_path: Arc<String>
+}
+
+impl CartMiddleware {
+  fn new(data_path: Arc<String>) -> Self {
+    Self { data_path }
+  }
+}
+
+impl<E: Endpoint> Middleware<E> for CartMiddleware {
+  type Output = CartMiddlewareImpl<E>;
+
+  fn transform(&self, ep: E) -> Self::Output {
+    CartMiddlewareImpl {
+      inner: ep,
+      data_path: self.data_path.clone()
+    }
+  }
+}
+
+struct CartMiddlewareImpl<E> {
+  inner: E,
+  data_path: Arc<String>,
+}
+
+impl<E: Endpoint> Endpoint for CartMiddlewareImpl<E> {
+  type Output = Response;
+
+  async fn call(&self, mut req: Request) -> Result<Self::Output> {
+    // A token that doesn't check out is an error rather than no token,
+    // the customer would otherwise silently see another cart.
+    // Checked before taking the lock like in `AuthMiddleware`.
+    if let Some(auth_header) = req.headers().get("Authorization") {
+      let token = auth_header.to_str().ok().and_then(|a| a.strip_prefix("Bearer "));
+      let key = DecodingKey::from_secret(SECRET_KEY.as_ref());
+      let claims = token.and_then(|t| decode::<Claims>(t, &key, &Validation::new(Algorithm::HS256)).ok());
+      match claims {
+        Some(token_data) => req.extensions_mut().insert(token_data.claims),
+        None => return Err(unauthorized())
+      };
+    }
+
+    let lock = carts_lock(&self.data_path);
+    let (_read_guard, _write_guard) = if req.method() == Method::GET {
+      (Some(lock.read_owned().await), None)
+    } else {
+      (None, Some(lock.write_owned().await))
+    };
+
+    let catalogue = {
+      let _items_guard = data_lock(&self.data_path).read_owned().await;
+      let items = load_items(&self.data_path)?;
+      // `all_routes` gives every route the config
+      let config = req.data::<Config>().unwrap();
+      Catalogue::load(&items, &self.data_path, config).map_err(|_e| error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error carts catalogue 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))?
+    };
+    req.extensions_mut().insert(Arc::new(catalogue));
+
+    self.inner.call(req).await.map(IntoResponse::into_response)
+  }
+}
@@ -0,0 +1,342 @@
+use std::collections::BTreeMap;
+use poem::web::{Data, Path, Query};
+use poem::{get, handler, put, http::StatusCode, web::Json, EndpointExt, Request, Response, Result, Route};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use crate::items::{page_response, AuthMiddleware};
+use crate::{error_response_json, response_json, Config, ErrorResponse};
+pub(crate) use schema::check_item_attributes;
+pub(crate) use tree::{category_ids_of, read_categories, Categories};
+use schema::{check_schema, Schema};
+use tree::{write_categories, Category};
+
+mod schema;
+mod tree;
+
+pub fn route(data_path: String) -> Route {
+  Route::new()
+    .at("/", get(get_categories)
+      .post(post_category)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/:id", get(get_category)
+      .put(put_category)
+      .delete(delete_category)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/:id/attributes", put(put_category_attributes)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/:id/items", get(get_category_items)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+}
+
+#[handler]
+async fn get_categories(data_path: Data<&String>) -> Result<Response> {
+  let categories = match read_categories(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_categories 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  Ok(response_json(StatusCode::OK, categories.all()))
+}
+
+#[handler]
+async fn get_category(id: Path<u64>, data_path: Data<&String>) -> Result<Response> {
+  let categories = match read_categories(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_category 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  match categories.get(*id) {
+    Some(category) => Ok(response_json(StatusCode::OK, category_json(&categories, category))),
+    None => Err(category_not_found())
+  }
+}
+
+#[handler]
+async fn post_category(category_req: Json<CategoryReq>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  let mut categories = match read_categories(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error post_category 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  if let Some(parent_id) = category_req.parent_id {
+    if categories.get(parent_id).is_none() {
+      return Err(invalid_parent("Parent category does not exist"))
+    }
+  }
+  check_sibling_names(&categories, &category_req, None, &config)?;
+
+  let category = categories.insert(category_req.name.clone(), category_req.parent_id);
+  if write_categories(data_path.as_str(), &categories).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error post_category 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  Ok(response_json(StatusCode::CREATED, category_json(&categories, &category)))
+}
+
+/// Renames the category or moves it, with everything below it, to another parent
+#[handler]
+async fn put_category(req: &Request, id: Path<u64>, category_req: Json<CategoryReq>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let mut categories = match read_categories(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error put_category 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  if categories.get(*id).is_none() {
+    return Err(category_not_found())
+  }
+
+  if let Some(parent_id) = category_req.parent_id {
+    if categories.get(parent_id).is_none() {
+      return Err(invalid_parent("Parent category does not exist"))
+    }
+    if categories.subtree(*id).contains(&parent_id) {
+      return Err(invalid_parent("A category can't be moved below itself"))
+    }
+  }
+  check_sibling_names(&categories, &category_req, Some(*id), &config)?;
+
+  if let Some(category) = categories.get_mut(*id) {
+    category.name = category_req.name.clone();
+    category.parent_id = category_req.parent_id;
+  }
+  // The items below now inherit the attributes of the new parent
+  check_items_match(&categories, items, *id)?;
+  if write_categories(data_path.as_str(), &categories).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error put_category 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  match categories.get(*id) {
+    Some(category) => Ok(response_json(StatusCode::OK, category_json(&categories, category))),
+    None => Err(category_not_found())
+  }
+}
+
+/// Replaces the attribute definitions of the category, the items in it and
+/// the categories below it have to match the new schema
+#[handler]
+async fn put_category_attributes(req: &Request, id: Path<u64>, attributes_req: Json<CategoryAttributesReq>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let mut categories = match read_categories(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error put_category_attributes 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  if let Err(msg) = check_schema(&attributes_req.attributes) {
+    return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+      error: "Invalid schema".to_string(),
+      msg
+    }))
+  }
+
+  match categories.get_mut(*id) {
+    Some(category) => category.attributes = attributes_req.attributes.clone(),
+    None => return Err(category_not_found())
+  }
+  check_items_match(&categories, items, *id)?;
+
+  if write_categories(data_path.as_str(), &categories).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error put_category_attributes 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  match categories.get(*id) {
+    Some(category) => Ok(response_json(StatusCode::OK, category_json(&categories, category))),
+    None => Err(category_not_found())
+  }
+}
+
+/// Items in the category or below it whose attributes don't match the schema
+/// they would have with `categories`
+fn check_items_match(categories: &Categories, items: &[Value], id: u64) -> Result<()> {
+  let subtree = categories.subtree(id);
+  let item_ids: Vec<u64> = items
+    .iter()
+    .filter(|item| category_ids_of(item).iter().any(|c| subtree.contains(c)))
+    .filter(|item| check_item_attributes(categories, item).is_err())
+    .filter_map(|item| item.get("id").and_then(|i| i.as_u64()))
+    .collect();
+
+  match item_ids.is_empty() {
+    true => Ok(()),
+    false => Err(error_response_json(StatusCode::CONFLICT, json!({
+      "error": "Items don't match the schema",
+      "msg": "Please update the attributes of the items first",
+      "item_ids": item_ids
+    })))
+  }
+}
+
+/// Only empty categories can be deleted, items and subcategories have to be moved first
+#[handler]
+async fn delete_category(req: &Request, id: Path<u64>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let mut categories = match read_categories(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error delete_category 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  if categories.get(*id).is_none() {
+    return Err(category_not_found())
+  }
+
+  if categories.children(*id).next().is_some() {
+    return Err(error_response_json(StatusCode::CONFLICT, ErrorResponse {
+      error: "Category is not empty".to_string(),
+      msg: "Please move or delete its subcategories first".to_string()
+    }))
+  }
+
+  let item_count = items.iter().filter(|item| category_ids_of(item).contains(&id)).count();
+  if item_count > 0 {
+    return Err(error_response_json(StatusCode::CONFLICT, ErrorResponse {
+      error: "Category is not empty".to_string(),
+      msg: format!("Please move its {} item(s) to another category first", item_count)
+    }))
+  }
+
+  categories.remove(*id);
+  if write_categories(data_path.as_str(), &categories).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error delete_category 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  Ok(response_json(StatusCode::OK, json!({
+    "message": "Category deleted successfully"
+  })))
+}
+
+/// Items in the category and, unless `descendants=false`, in the categories below it.
+/// Takes the same filter, sort and paging parameters as `GET /items`.
+#[handler]
+async fn get_category_items(req: &Request, id: Path<u64>, params: Query<BTreeMap<String, String>>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let categories = match read_categories(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_category_items 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  if categories.get(*id).is_none() {
+    return Err(category_not_found())
+  }
+
+  let mut params = params.0;
+  let category_ids = match params.remove("descendants").as_deref() {
+    Some("false") => [*id].into(),
+    _ => categories.subtree(*id),
+  };
+
+  let in_category: Vec<Value> = items
+    .iter()
+    .filter(|item| category_ids_of(item).iter().any(|c| category_ids.contains(c)))
+    .cloned()
+    .collect();
+
+  page_response(req, &in_category, &params, data_path.as_str(), &config, "get_category_items")
+}
+
+/// Names only have to be unique among categories with the same parent
+fn check_sibling_names(categories: &Categories, category_req: &CategoryReq, id: Option<u64>, config: &Config) -> Result<()> {
+  let key = config.name_policy.key(&category_req.name);
+  let existing = categories.all().iter().find(|c| {
+    Some(c.id) != id && c.parent_id == category_req.parent_id && config.name_policy.key(&c.name) == key
+  });
+
+  match existing {
+    Some(existing) => Err(error_response_json(StatusCode::CONFLICT, json!({
+      "error": "Category already exists",
+      "msg": "Please use a different name",
+      "id": existing.id
+    }))),
+    None => Ok(())
+  }
+}
+
+fn category_json(categories: &Categories, category: &Category) -> Value {
+  let mut res = json!(category);
+  res["path"] = json!(categories.path(category.id));
+  res
+}
+
+fn category_not_found() -> poem::Error {
+  error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+    error: "Not found".to_string(),
+    msg: "Category does not exist".to_string()
+  })
+}
+
+fn invalid_parent(msg: &str) -> poem::Error {
+  error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+    error: "Invalid parent".to_string(),
+    msg: msg.to_string()
+  })
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CategoryReq {
+  name: String,
+  parent_id: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CategoryAttributesReq {
+  attributes: Schema,
+}
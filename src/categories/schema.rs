@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+
+use super::tree::{category_ids_of, Categories};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AttributeType {
+  String,
+  Integer,
+  Number,
+  Boolean,
+}
+
+impl AttributeType {
+  fn name(&self) -> &'static str {
+    match self {
+      AttributeType::String => "string",
+      AttributeType::Integer => "integer",
+      AttributeType::Number => "number",
+      AttributeType::Boolean => "boolean",
+    }
+  }
+}
+
+/// Definition of one attribute of the items in a category, modelled on JSON
+/// Schema, e.g. `{"type": "integer", "minimum": 1, "required": true}`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct AttributeDef {
+  #[serde(rename = "type")]
+  pub kind: AttributeType,
+  #[serde(default)]
+  pub required: bool,
+  /// The only values allowed
+  #[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
+  pub allowed: Option<Vec<Value>>,
+  /// Bounds of numbers
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub minimum: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub maximum: Option<f64>,
+  /// Longest string in characters
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub max_length: Option<usize>,
+}
+
+/// Attribute definitions by attribute name
+pub(crate) type Schema = BTreeMap<String, AttributeDef>;
+
+/// Checks that the definitions make sense on their own
+pub(crate) fn check_schema(schema: &Schema) -> Result<(), String> {
+  for (name, def) in schema.iter() {
+    let valid_name = !name.is_empty()
+      && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !valid_name {
+      return Err(format!("{} is not a valid attribute name, use a-z, 0-9 and _", name))
+    }
+    if let (Some(minimum), Some(maximum)) = (def.minimum, def.maximum) {
+      if minimum > maximum {
+        return Err(format!("{}: minimum is above maximum", name))
+      }
+    }
+    if let Some(allowed) = &def.allowed {
+      if allowed.is_empty() {
+        return Err(format!("{}: enum needs at least one value", name))
+      }
+      if let Some(value) = allowed.iter().find(|v| !has_type(v, def.kind)) {
+        return Err(format!("{}: enum value {} is not of type {}", name, value, def.kind.name()))
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Attributes the items in the categories can have. Categories inherit the
+/// attributes of the categories above them, a category's own definition wins.
+pub(crate) fn schema_for(categories: &Categories, category_ids: &[u64]) -> Schema {
+  let mut schema = Schema::new();
+  for id in category_ids.iter() {
+    for category in categories.ancestry(*id) {
+      for (name, def) in category.attributes.iter() {
+        schema.entry(name.clone()).or_insert_with(|| def.clone());
+      }
+    }
+  }
+  schema
+}
+
+fn has_type(value: &Value, kind: AttributeType) -> bool {
+  match kind {
+    AttributeType::String => value.is_string(),
+    AttributeType::Integer => value.is_i64() || value.is_u64(),
+    AttributeType::Number => value.is_number(),
+    AttributeType::Boolean => value.is_boolean(),
+  }
+}
+
+/// Checks the attributes of an item against the schema of its categories,
+/// returns every problem found
+pub(crate) fn validate_attributes(schema: &Schema, attributes: &Map<String, Value>) -> Result<(), Vec<String>> {
+  let mut errors = Vec::new();
+  for name in attributes.keys() {
+    if !schema.contains_key(name) {
+      errors.push(format!("{} is not an attribute of the item's categories", name));
+    }
+  }
+
+  for (name, def) in schema.iter() {
+    let value = match attributes.get(name) {
+      Some(res) => res,
+      None => {
+        if def.required {
+          errors.push(format!("{} is required", name));
+        }
+        continue;
+      }
+    };
+
+    if !has_type(value, def.kind) {
+      errors.push(format!("{} must be of type {}", name, def.kind.name()));
+      continue;
+    }
+    if def.allowed.as_ref().is_some_and(|allowed| !allowed.contains(value)) {
+      errors.push(format!("{} must be one of the enum values", name));
+    }
+    if let Some(number) = value.as_f64() {
+      if def.minimum.is_some_and(|minimum| number < minimum) || def.maximum.is_some_and(|maximum| number > maximum) {
+        errors.push(format!("{} is out of range", name));
+      }
+    }
+    if let (Some(text), Some(max_length)) = (value.as_str(), def.max_length) {
+      if text.chars().count() > max_length {
+        errors.push(format!("{} is longer than {} characters", name, max_length));
+      }
+    }
+  }
+
+  match errors.is_empty() {
+    true => Ok(()),
+    false => Err(errors),
+  }
+}
+
+/// Attributes the item was given
+fn attributes_of(item: &Value) -> Map<String, Value> {
+  item.get("attributes").and_then(|a| a.as_object()).cloned().unwrap_or_default()
+}
+
+/// Checks the attributes of the item against the schema of the categories it is in
+pub(crate) fn check_item_attributes(categories: &Categories, item: &Value) -> Result<(), Vec<String>> {
+  validate_attributes(&schema_for(categories, &category_ids_of(item)), &attributes_of(item))
+}
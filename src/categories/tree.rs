@@ -0,0 +1,106 @@
+use std::collections::BTreeSet;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use super::schema::Schema;
+use crate::store::{read_json, sidecar_path, write_json};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct Category {
+  pub id: u64,
+  pub name: String,
+  /// `None` for top level categories
+  pub parent_id: Option<u64>,
+  /// Attributes of the items in the category and the categories below it
+  #[serde(default, skip_serializing_if = "Schema::is_empty")]
+  pub attributes: Schema,
+}
+
+/// Every category, ids of deleted categories are never given again
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(crate) struct Categories {
+  last_id: u64,
+  categories: Vec<Category>,
+}
+
+impl Categories {
+  pub(crate) fn all(&self) -> &[Category] {
+    &self.categories
+  }
+
+  pub(crate) fn get(&self, id: u64) -> Option<&Category> {
+    self.categories.iter().find(|c| c.id == id)
+  }
+
+  pub(crate) fn get_mut(&mut self, id: u64) -> Option<&mut Category> {
+    self.categories.iter_mut().find(|c| c.id == id)
+  }
+
+  pub(crate) fn insert(&mut self, name: String, parent_id: Option<u64>) -> Category {
+    self.last_id += 1;
+    let category = Category { id: self.last_id, name, parent_id, attributes: Schema::new() };
+    self.categories.push(category.clone());
+    category
+  }
+
+  pub(crate) fn remove(&mut self, id: u64) {
+    self.categories.retain(|c| c.id != id);
+  }
+
+  pub(crate) fn children(&self, id: u64) -> impl Iterator<Item = &Category> {
+    self.categories.iter().filter(move |c| c.parent_id == Some(id))
+  }
+
+  /// The category and everything below it
+  pub(crate) fn subtree(&self, id: u64) -> BTreeSet<u64> {
+    let mut ids = BTreeSet::from([id]);
+    let mut pending = vec![id];
+    while let Some(parent) = pending.pop() {
+      for child in self.children(parent) {
+        if ids.insert(child.id) {
+          pending.push(child.id);
+        }
+      }
+    }
+    ids
+  }
+
+  /// Names from the top level category down to this one, e.g. Games > PS5 > RPG
+  pub(crate) fn path(&self, id: u64) -> Vec<String> {
+    let mut path: Vec<String> = self.ancestry(id).iter().map(|c| c.name.clone()).collect();
+    path.reverse();
+    path
+  }
+
+  /// The category and the ones above it, nearest first
+  pub(crate) fn ancestry(&self, id: u64) -> Vec<&Category> {
+    let mut ancestry = Vec::new();
+    let mut current = self.get(id);
+    while let Some(category) = current {
+      ancestry.push(category);
+      // A broken file could hold a cycle, the path can't be longer than the tree
+      if ancestry.len() > self.categories.len() {
+        break;
+      }
+      current = category.parent_id.and_then(|parent| self.get(parent));
+    }
+    ancestry
+  }
+}
+
+pub(crate) fn read_categories(data_path: &str) -> std::io::Result<Categories> {
+  read_json(&sidecar_path(data_path, "categories"))
+}
+
+pub(crate) fn write_categories(data_path: &str, categories: &Categories) -> std::io::Result<()> {
+  write_json(&sidecar_path(data_path, "categories"), categories)
+}
+
+/// Categories the item was put in, sorted
+pub(crate) fn category_ids_of(item: &Value) -> Vec<u64> {
+  item
+    .get("category_ids")
+    .and_then(|c| c.as_array())
+    .map(|ids| ids.iter().filter_map(|id| id.as_u64()).collect())
+    .unwrap_or_default()
+}
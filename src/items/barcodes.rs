@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use serde_json::{json, Value};
+
+/// Most barcodes one item can have, e.g. the JAN of the Japanese release and
+/// the UPC of the US release
+pub(crate) const MAX_BARCODES: usize = 10;
+
+/// Check digit of the first 12 digits of an EAN-13, weighted 1 and 3 from the left
+fn check_digit(digits: &[u32]) -> u32 {
+  let sum: u32 = digits
+    .iter()
+    .enumerate()
+    .map(|(i, d)| if i % 2 == 0 { *d } else { d * 3 })
+    .sum();
+  (10 - sum % 10) % 10
+}
+
+/// Checks an EAN-13, JAN or UPC-A code, spaces and dashes are ignored.
+/// UPC-A codes are kept as EAN-13 with a leading 0, scanners read the same
+/// product either way, e.g. "0 45496 59052 2" is "0045496590522".
+pub(crate) fn normalize_barcode(code: &str) -> Result<String, String> {
+  let code: String = code.chars().filter(|c| *c != ' ' && *c != '-').collect();
+  if !code.chars().all(|c| c.is_ascii_digit()) {
+    return Err(format!("{} is not a barcode, use digits only", code))
+  }
+  let code = match code.len() {
+    12 => format!("0{}", code),
+    13 => code,
+    _ => return Err(format!("{} is not a barcode, use 13 digits for EAN and JAN or 12 for UPC", code)),
+  };
+
+  let digits: Vec<u32> = code.chars().filter_map(|c| c.to_digit(10)).collect();
+  if check_digit(&digits[..12]) != digits[12] {
+    return Err(format!("{} has a wrong check digit", code))
+  }
+  Ok(code)
+}
+
+/// Checks the barcodes given for an item, duplicates are dropped
+pub(crate) fn parse_barcodes(codes: &[String]) -> Result<Vec<String>, String> {
+  let mut barcodes: Vec<String> = Vec::new();
+  for code in codes.iter() {
+    let code = normalize_barcode(code)?;
+    if !barcodes.contains(&code) {
+      barcodes.push(code);
+    }
+  }
+  if barcodes.len() > MAX_BARCODES {
+    return Err(format!("An item can have at most {} barcodes", MAX_BARCODES))
+  }
+  Ok(barcodes)
+}
+
+pub(crate) fn barcodes_of(item: &Value) -> Vec<String> {
+  item
+    .get("barcodes")
+    .and_then(|b| serde_json::from_value(b.clone()).ok())
+    .unwrap_or_default()
+}
+
+/// Sets the barcodes of the item, the field is dropped when there are none
+pub(crate) fn set_barcodes(item: &mut Value, barcodes: &[String]) {
+  if let Some(fields) = item.as_object_mut() {
+    match barcodes.is_empty() {
+      true => fields.remove("barcodes"),
+      false => fields.insert("barcodes".to_string(), json!(barcodes)),
+    };
+  }
+}
+
+/// Barcode to item id, a barcode belongs to one item only
+pub(crate) struct BarcodeIndex {
+  ids: HashMap<String, u64>,
+}
+
+impl BarcodeIndex {
+  pub(crate) fn build(items: &[Value]) -> Self {
+    let mut ids = HashMap::new();
+    for item in items.iter() {
+      let id = match item.get("id").and_then(|id| id.as_u64()) {
+        Some(res) => res,
+        None => continue,
+      };
+      for barcode in barcodes_of(item) {
+        ids.entry(barcode).or_insert(id);
+      }
+    }
+    Self { ids }
+  }
+
+  /// Id of the item with the barcode, which has to be normalized
+  pub(crate) fn find(&self, barcode: &str) -> Option<u64> {
+    self.ids.get(barcode).copied()
+  }
+}
@@ -0,0 +1,124 @@
+use poem::http::StatusCode;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use super::state::{ItemState, OpError};
+use super::{ItemPatchReq, ItemReq};
+use crate::Config;
+
+/// Operations in one request are limited so a single call can't hold the lock for long
+pub(crate) const MAX_OPERATIONS: usize = 1000;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct BulkReq {
+  #[serde(default)]
+  pub mode: BulkMode,
+  pub operations: Vec<BulkOp>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BulkMode {
+  /// Either every operation is applied or none is
+  #[default]
+  Atomic,
+  /// Operations that succeed are applied, the rest are reported
+  BestEffort,
+}
+
+/// `version` works like `If-Match`, the operation fails when the item has moved on
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(crate) enum BulkOp {
+  /// Same fields as `POST /items`
+  Create(ItemReq),
+  /// Same fields as `PATCH /items/:id`
+  Update {
+    id: ItemKey,
+    version: Option<u64>,
+    #[serde(flatten)]
+    changes: ItemPatchReq,
+  },
+  Delete { id: ItemKey, version: Option<u64> },
+}
+
+/// Numeric id, public id or slug
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub(crate) enum ItemKey {
+  Id(u64),
+  Key(String),
+}
+
+impl ItemKey {
+  pub(crate) fn as_key(&self) -> String {
+    match self {
+      ItemKey::Id(id) => id.to_string(),
+      ItemKey::Key(key) => key.clone(),
+    }
+  }
+}
+
+impl ItemState {
+  /// Applies the operation, the state is left untouched when it fails
+  pub(crate) fn apply(&mut self, op: &BulkOp, config: &Config, actor: &Option<String>) -> Result<(StatusCode, Value), OpError> {
+    match op {
+      BulkOp::Create(item_req) => self.create(item_req, config, actor),
+      BulkOp::Update { id, version, changes } => self.update(&id.as_key(), changes, *version, config, actor),
+      BulkOp::Delete { id, version } => self.delete(&id.as_key(), *version, actor),
+    }
+  }
+}
+
+/// Runs the operations against the state, returns the status of the whole request,
+/// the result of every operation and whether anything has to be saved
+pub(crate) fn run(state: &mut ItemState, req: &BulkReq, config: &Config, actor: &Option<String>) -> (StatusCode, Vec<Value>, bool) {
+  let mut results = Vec::new();
+  let mut failed = 0;
+
+  match req.mode {
+    BulkMode::Atomic => {
+      let mut attempt = state.clone();
+      for (index, op) in req.operations.iter().enumerate() {
+        match attempt.apply(op, config, actor) {
+          Ok((status, item)) => results.push(json!({ "status": status.as_u16(), "item": item })),
+          Err(e) => {
+            // Nothing is applied, the operations before were fine and the rest never ran
+            for result in results.iter_mut() {
+              *result = not_applied();
+            }
+            results.push(e.to_json());
+            results.extend((index + 1..req.operations.len()).map(|_| not_applied()));
+            return (StatusCode::UNPROCESSABLE_ENTITY, results, false)
+          }
+        }
+      }
+      *state = attempt;
+    }
+    BulkMode::BestEffort => {
+      for op in req.operations.iter() {
+        match state.apply(op, config, actor) {
+          Ok((status, item)) => results.push(json!({ "status": status.as_u16(), "item": item })),
+          Err(e) => {
+            failed += 1;
+            results.push(e.to_json());
+          }
+        }
+      }
+    }
+  }
+
+  let status = match failed {
+    0 => StatusCode::OK,
+    _ => StatusCode::MULTI_STATUS,
+  };
+  (status, results, failed < req.operations.len())
+}
+
+fn not_applied() -> Value {
+  json!({
+    "status": StatusCode::FAILED_DEPENDENCY.as_u16(),
+    "error": "Not applied",
+    "msg": "Another operation in the request failed",
+  })
+}
@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, HashSet};
+use chrono::Duration;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use super::stock::{Inventory, Reservation, ReservationError};
+
+/// Item in a bundle and how many of it one bundle holds
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct Component {
+  pub item_id: u64,
+  pub quantity: u64,
+}
+
+pub(crate) fn components_of(item: &Value) -> Vec<Component> {
+  item
+    .get("components")
+    .and_then(|c| serde_json::from_value(c.clone()).ok())
+    .unwrap_or_default()
+}
+
+/// Most units of one item in a bundle
+pub(crate) const MAX_COMPONENT_QUANTITY: u64 = 1000;
+
+/// Adds up the quantities of items listed more than once, in id order
+pub(crate) fn combine_components(components: &[Component]) -> Result<Vec<Component>, String> {
+  let mut combined: BTreeMap<u64, u64> = BTreeMap::new();
+  for component in components.iter() {
+    let quantity = combined.entry(component.item_id).or_default();
+    *quantity = match quantity.checked_add(component.quantity) {
+      Some(res) if res <= MAX_COMPONENT_QUANTITY => res,
+      _ => return Err(format!("quantity of item {} must be at most {}", component.item_id, MAX_COMPONENT_QUANTITY)),
+    };
+  }
+  Ok(combined
+    .into_iter()
+    .map(|(item_id, quantity)| Component { item_id, quantity })
+    .collect())
+}
+
+/// Sets the components of the item as combined by `combine_components`, the
+/// field is dropped when there are none and the item is no longer a bundle
+pub(crate) fn set_components(item: &mut Value, components: &[Component]) {
+  if let Some(fields) = item.as_object_mut() {
+    match components.is_empty() {
+      true => fields.remove("components"),
+      false => fields.insert("components".to_string(), json!(components)),
+    };
+  }
+}
+
+fn find(items: &[Value], id: u64) -> Option<&Value> {
+  items.iter().find(|item| item.get("id").and_then(|i| i.as_u64()) == Some(id))
+}
+
+/// Checks that the components exist, have a quantity and don't contain the
+/// bundle itself, directly or through other bundles. Returns them combined.
+pub(crate) fn check_components(items: &[Value], id: u64, components: &[Component]) -> Result<Vec<Component>, String> {
+  for component in components.iter() {
+    if component.quantity == 0 {
+      return Err(format!("quantity of item {} must be positive", component.item_id))
+    }
+    if find(items, component.item_id).is_none() {
+      return Err(format!("item {} does not exist", component.item_id))
+    }
+    if component.item_id == id || contains(items, component.item_id, id, &mut HashSet::new()) {
+      return Err(format!("item {} contains this bundle, bundles can't contain themselves", component.item_id))
+    }
+  }
+  combine_components(components)
+}
+
+/// Whether `target` is a component of `id` at any depth
+fn contains(items: &[Value], id: u64, target: u64, visited: &mut HashSet<u64>) -> bool {
+  if !visited.insert(id) {
+    return false
+  }
+  let components = find(items, id).map(components_of).unwrap_or_default();
+  components
+    .iter()
+    .any(|c| c.item_id == target || contains(items, c.item_id, target, visited))
+}
+
+/// Ids of the bundles that have the item as a direct component
+pub(crate) fn bundles_containing(items: &[Value], id: u64) -> Vec<u64> {
+  items
+    .iter()
+    .filter(|item| components_of(item).iter().any(|c| c.item_id == id))
+    .filter_map(|item| item.get("id").and_then(|i| i.as_u64()))
+    .collect()
+}
+
+/// Ids of the bundles that contain the item at any depth
+pub(crate) fn bundles_above(items: &[Value], id: u64) -> Vec<u64> {
+  let mut found: Vec<u64> = Vec::new();
+  let mut pending = vec![id];
+  while let Some(id) = pending.pop() {
+    for bundle_id in bundles_containing(items, id) {
+      if !found.contains(&bundle_id) {
+        found.push(bundle_id);
+        pending.push(bundle_id);
+      }
+    }
+  }
+  found
+}
+
+/// Complete bundles the stock of the components is enough for, counted over
+/// the same units `reserve_bundle` reserves. A cycle that got into the data
+/// counts as out of stock.
+pub(crate) fn bundle_available(items: &[Value], inventory: &Inventory, id: u64) -> u64 {
+  match stocked_components(items, id, &mut Vec::new()) {
+    Ok(components) => components
+      .iter()
+      .map(|c| inventory.available(c.item_id) / c.quantity.max(1))
+      .min()
+      .unwrap_or(0),
+    Err(_e) => 0,
+  }
+}
+
+/// Units of the items with stock that one bundle is made of, bundles inside
+/// it are opened up. `path` holds the bundles above.
+fn stocked_components(items: &[Value], id: u64, path: &mut Vec<u64>) -> Result<Vec<Component>, String> {
+  if path.contains(&id) {
+    return Err(format!("bundle {} contains itself", id))
+  }
+  path.push(id);
+  let mut stocked = Vec::new();
+  for component in components_of(find(items, id).unwrap_or(&Value::Null)) {
+    if find(items, component.item_id).is_some_and(|i| i.get("components").is_some()) {
+      for inner in stocked_components(items, component.item_id, path)? {
+        let quantity = match inner.quantity.checked_mul(component.quantity) {
+          Some(res) => res,
+          None => return Err(format!("bundle {} holds too many units of item {}", id, inner.item_id)),
+        };
+        stocked.push(Component { item_id: inner.item_id, quantity });
+      }
+    } else {
+      stocked.push(component);
+    }
+  }
+  path.pop();
+
+  let mut combined: BTreeMap<u64, u64> = BTreeMap::new();
+  for component in stocked {
+    let quantity = combined.entry(component.item_id).or_default();
+    *quantity = match quantity.checked_add(component.quantity) {
+      Some(res) => res,
+      None => return Err(format!("bundle {} holds too many units of item {}", id, component.item_id)),
+    };
+  }
+  Ok(combined
+    .into_iter()
+    .map(|(item_id, quantity)| Component { item_id, quantity })
+    .collect())
+}
+
+/// A bundle has no stock of its own, reserving it reserves its components in
+/// the warehouse. Either all of them are reserved or none, when one runs short
+/// the error tells how many complete bundles the warehouse has.
+pub(crate) fn reserve_bundle(
+  items: &[Value],
+  inventory: &mut Inventory,
+  id: u64,
+  warehouse: &str,
+  quantity: u64,
+  ttl: Duration,
+  actor: Option<String>,
+) -> Result<Vec<Reservation>, ReservationError> {
+  let components = stocked_components(items, id, &mut Vec::new()).map_err(ReservationError::Invalid)?;
+  if components.is_empty() {
+    return Err(ReservationError::Invalid("bundle has no components".to_string()))
+  }
+
+  let available = components
+    .iter()
+    .map(|c| inventory.available_in_warehouse(c.item_id, warehouse.trim()) / c.quantity.max(1))
+    .min()
+    .unwrap_or(0);
+  if quantity > available {
+    return Err(ReservationError::Insufficient(available))
+  }
+
+  let mut reservations = Vec::new();
+  for component in components.iter() {
+    let units = match component.quantity.checked_mul(quantity) {
+      Some(res) => res,
+      None => return Err(ReservationError::Invalid(format!("quantity of item {} is too large", component.item_id))),
+    };
+    reservations.push(inventory.reserve(component.item_id, warehouse, units, ttl, actor.clone())?);
+  }
+  Ok(reservations)
+}
+
+/// Adds `available` to a bundle, computed from its components
+pub(crate) fn decorate_bundle(item: &mut Value, items: &[Value], inventory: &Inventory) {
+  let id = match item.get("id").and_then(|i| i.as_u64()) {
+    Some(res) => res,
+    None => return,
+  };
+  if item.get("components").is_some() {
+    item["available"] = json!(bundle_available(items, inventory, id));
+  }
+}
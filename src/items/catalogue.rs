@@ -0,0 +1,78 @@
+use chrono::Utc;
+use serde_json::Value;
+
+use super::decorate_item;
+use super::merge::{read_redirects, Redirects};
+use super::prices::{read_prices, read_rates, read_sales, resolve_price, ExchangeRates, Prices, Sales};
+use super::status::{status_of, ItemStatus};
+use super::stock::{read_inventory, Inventory};
+use super::variants::variants_of;
+use crate::Config;
+
+/// An item as a customer would buy it
+pub(crate) struct Offer {
+  /// The item as shown to clients, with its stock and title
+  pub item: Value,
+  /// Why the item can't be bought, `None` when it can
+  pub not_for_sale: Option<String>,
+  /// Units that can be bought, `None` when the stock isn't tracked
+  pub available: Option<u64>,
+  /// Price of one unit in the minor unit of the currency
+  pub price_minor: Option<i64>,
+}
+
+/// Everything needed to tell what items cost and whether they can be bought,
+/// loaded once for all the items of a request
+pub(crate) struct Catalogue {
+  items: Vec<Value>,
+  inventory: Inventory,
+  prices: Prices,
+  sales: Sales,
+  rates: ExchangeRates,
+  redirects: Redirects,
+}
+
+impl Catalogue {
+  pub(crate) fn load(items: &[Value], data_path: &str, config: &Config) -> std::io::Result<Self> {
+    Ok(Self {
+      items: items.to_vec(),
+      inventory: read_inventory(data_path)?,
+      prices: read_prices(data_path)?,
+      sales: read_sales(data_path)?,
+      rates: read_rates(data_path, &config.exchange_rates_path)?,
+      redirects: read_redirects(data_path)?,
+    })
+  }
+
+  /// Id of the item that lives on for `id`, merged items live on in the item
+  /// they were merged into
+  pub(crate) fn resolve(&self, id: u64) -> u64 {
+    self.redirects.get(&id).copied().unwrap_or(id)
+  }
+
+  /// The item with its availability and price, `None` when it doesn't exist
+  pub(crate) fn offer(&self, id: u64, region: &str, currency: &str) -> Option<Offer> {
+    let id = self.resolve(id);
+    let mut item = self
+      .items
+      .iter()
+      .find(|item| item.get("id").and_then(|i| i.as_u64()) == Some(id))?
+      .clone();
+    decorate_item(&mut item, &self.items, &self.inventory);
+
+    let not_for_sale = match status_of(&item) {
+      _ if variants_of(&self.items, id).next().is_some() => Some("Please choose a variant of the item".to_string()),
+      ItemStatus::Preorder | ItemStatus::Released => None,
+      ItemStatus::Discontinued => Some("Item is discontinued".to_string()),
+      ItemStatus::Draft | ItemStatus::Announced => Some("Item is not on sale yet".to_string()),
+    };
+    let available = item.get("available").and_then(|a| a.as_u64());
+
+    let entries = self.prices.get(&id).map(|e| e.as_slice()).unwrap_or_default();
+    let sales = self.sales.get(&id).map(|s| s.as_slice()).unwrap_or_default();
+    let price_minor = resolve_price(entries, sales, region, currency, &self.rates, Utc::now())
+      .and_then(|price| price.get("amount_minor").and_then(|a| a.as_i64()));
+
+    Some(Offer { item, not_for_sale, available, price_minor })
+  }
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::barcodes::barcodes_of;
+use super::text::{fold, tokenize};
+
+/// Lowest name similarity reported when no threshold is given
+pub(crate) const DEFAULT_THRESHOLD: f64 = 0.8;
+
+/// Two items that are likely the same product
+#[derive(Serialize, Debug)]
+pub(crate) struct Duplicate {
+  pub item_ids: [u64; 2],
+  /// Similarity of the names from 0 to 1, rounded to two decimals
+  pub score: f64,
+  /// What matched: `name`, `sku` or `barcode`
+  pub reasons: Vec<&'static str>,
+}
+
+/// Counts of character pairs
+type Bigrams = HashMap<(char, char), usize>;
+
+/// Character pairs of the folded words, punctuation and spacing don't count,
+/// e.g. "Pokémon: Scarlet" and "pokemon scarlet" have the same pairs
+fn bigrams(name: &str) -> Bigrams {
+  let chars: Vec<char> = tokenize(&fold(name), false).concat().chars().collect();
+  let mut counts = HashMap::new();
+  for pair in chars.windows(2) {
+    *counts.entry((pair[0], pair[1])).or_default() += 1;
+  }
+  counts
+}
+
+/// Pairs of items whose names are at least `threshold` similar or that share
+/// a SKU or barcode, most similar first. Variants have no name of their own,
+/// they only match on SKU or barcode.
+/// Only items that share a SKU, a barcode or a character pair are compared,
+/// the others can't be duplicates.
+pub(crate) fn find_duplicates(items: &[Value], threshold: f64) -> Vec<Duplicate> {
+  let entries: Vec<Entry> = items
+    .iter()
+    .filter_map(|item| {
+      let id = item.get("id").and_then(|i| i.as_u64())?;
+      let name = item.get("name").and_then(|n| n.as_str()).unwrap_or_default();
+      let bigrams = bigrams(name);
+      Some(Entry {
+        id,
+        pairs: bigrams.values().sum(),
+        bigrams,
+        sku: item.get("sku").and_then(|s| s.as_str()).map(|s| s.to_uppercase()),
+        barcodes: barcodes_of(item),
+      })
+    })
+    .collect();
+
+  // Blocks of the entries sharing a key, by position
+  let mut by_pair: HashMap<(char, char), Vec<usize>> = HashMap::new();
+  let mut by_code: HashMap<&str, Vec<usize>> = HashMap::new();
+  for (index, entry) in entries.iter().enumerate() {
+    for pair in entry.bigrams.keys() {
+      by_pair.entry(*pair).or_default().push(index);
+    }
+    for code in entry.sku.iter().chain(entry.barcodes.iter()) {
+      by_code.entry(code.as_str()).or_default().push(index);
+    }
+  }
+
+  let mut duplicates = Vec::new();
+  for (i, a) in entries.iter().enumerate() {
+    // Pairs shared with every later entry that has one in common
+    let mut shared: HashMap<usize, usize> = HashMap::new();
+    for (pair, count) in a.bigrams.iter() {
+      for j in by_pair[pair].iter().filter(|j| **j > i) {
+        *shared.entry(*j).or_default() += (*count).min(entries[*j].bigrams[pair]);
+      }
+    }
+    let mut candidates: Vec<usize> = shared.keys().copied().collect();
+    for code in a.sku.iter().chain(a.barcodes.iter()) {
+      candidates.extend(by_code[code.as_str()].iter().filter(|j| **j > i));
+    }
+    candidates.sort();
+    candidates.dedup();
+
+    for j in candidates {
+      let b = &entries[j];
+      let mut reasons = Vec::new();
+      // Dice coefficient of the character pairs, 1 for the same name
+      let total = a.pairs + b.pairs;
+      let score = match total {
+        0 => 0.0,
+        _ => 2.0 * shared.get(&j).copied().unwrap_or(0) as f64 / total as f64,
+      };
+      if score > 0.0 && score >= threshold {
+        reasons.push("name");
+      }
+      if a.sku.is_some() && a.sku == b.sku {
+        reasons.push("sku");
+      }
+      if a.barcodes.iter().any(|code| b.barcodes.contains(code)) {
+        reasons.push("barcode");
+      }
+      if !reasons.is_empty() {
+        let score = (score * 100.0).round() / 100.0;
+        duplicates.push(Duplicate { item_ids: [a.id, b.id], score, reasons });
+      }
+    }
+  }
+  duplicates.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.item_ids.cmp(&b.item_ids)));
+  duplicates
+}
+
+/// What an item is compared on
+struct Entry {
+  id: u64,
+  bigrams: Bigrams,
+  /// Number of character pairs in the name
+  pairs: usize,
+  sku: Option<String>,
+  barcodes: Vec<String>,
+}
@@ -0,0 +1,40 @@
+use std::collections::BTreeMap;
+use std::ops::{Deref, DerefMut};
+use serde::{Serialize, Deserialize};
+
+/// Entries of a sidecar file per item id, e.g. prices or sales. Ids of removed
+/// entries are never given again, so a client holding one can't end up acting
+/// on a newer entry.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct ItemEntries<T> {
+  last_id: u64,
+  entries: BTreeMap<u64, Vec<T>>,
+}
+
+impl<T> Default for ItemEntries<T> {
+  fn default() -> Self {
+    Self { last_id: 0, entries: BTreeMap::new() }
+  }
+}
+
+impl<T> ItemEntries<T> {
+  /// Takes the id for a new entry of any item
+  pub(crate) fn next_id(&mut self) -> u64 {
+    self.last_id += 1;
+    self.last_id
+  }
+}
+
+impl<T> Deref for ItemEntries<T> {
+  type Target = BTreeMap<u64, Vec<T>>;
+
+  fn deref(&self) -> &Self::Target {
+    &self.entries
+  }
+}
+
+impl<T> DerefMut for ItemEntries<T> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.entries
+  }
+}
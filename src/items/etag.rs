@@ -0,0 +1,107 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use poem::http::{header, HeaderMap, HeaderValue, StatusCode};
+use poem::Response;
+use serde_json::Value;
+
+use super::listing::Page;
+use crate::store::{read_json, sidecar_path, write_json};
+
+/// Version counter per item id, bumped on every change of the item.
+/// Items without an entry (e.g. written before versions existed) are at version 1.
+pub(crate) type Versions = BTreeMap<u64, u64>;
+
+pub(crate) fn read_versions(data_path: &str) -> std::io::Result<Versions> {
+  read_json(&sidecar_path(data_path, "versions"))
+}
+
+pub(crate) fn write_versions(data_path: &str, versions: &Versions) -> std::io::Result<()> {
+  write_json(&sidecar_path(data_path, "versions"), versions)
+}
+
+pub(crate) fn version_of(versions: &Versions, id: u64) -> u64 {
+  *versions.get(&id).unwrap_or(&1)
+}
+
+pub(crate) fn bump_version(versions: &mut Versions, id: u64) -> u64 {
+  let version = version_of(versions, id) + 1;
+  versions.insert(id, version);
+  version
+}
+
+pub(crate) fn item_etag(version: u64) -> String {
+  format!("\"{}\"", version)
+}
+
+/// Tag of an item shown in a locale, the version with a hash of the body
+pub(crate) fn localized_etag(version: u64, item: &Value) -> String {
+  let mut hasher = DefaultHasher::new();
+  item.to_string().hash(&mut hasher);
+  format!("\"{}-{:x}\"", version, hasher.finish())
+}
+
+/// Weak tag for a whole listing, it changes whenever any listed item changes
+/// and whenever the items around the page change its total or next cursor.
+/// The query goes in too, the same page can be reached by different queries.
+pub(crate) fn list_etag(page: &Page, versions: &Versions, params: &BTreeMap<String, String>) -> String {
+  let mut hasher = DefaultHasher::new();
+  for item in page.items.iter() {
+    let id = item.get("id").and_then(|id| id.as_u64()).unwrap_or(0);
+    (id, version_of(versions, id)).hash(&mut hasher);
+  }
+  page.total.hash(&mut hasher);
+  page.next_cursor.hash(&mut hasher);
+  params.hash(&mut hasher);
+  format!("W/\"{:x}\"", hasher.finish())
+}
+
+/// `If-Match` uses strong comparison, a missing header always matches.
+/// Localized tags match the version they were made from.
+pub(crate) fn if_match(headers: &HeaderMap, etag: &str) -> bool {
+  let value = match headers.get(header::IF_MATCH).and_then(|v| v.to_str().ok()) {
+    Some(res) => res,
+    None => return true,
+  };
+
+  value
+    .split(',')
+    .map(|tag| tag.trim())
+    .any(|tag| tag == "*" || (!tag.starts_with("W/") && (tag == etag || localized_from(tag, etag))))
+}
+
+/// Whether `tag` is a `localized_etag` of the version tagged `etag`
+fn localized_from(tag: &str, etag: &str) -> bool {
+  match (tag.strip_suffix('"'), etag.strip_suffix('"')) {
+    (Some(tag), Some(version)) => tag.strip_prefix(version).is_some_and(|rest| rest.starts_with('-')),
+    _ => false,
+  }
+}
+
+/// `If-None-Match` uses weak comparison, a missing header never matches
+pub(crate) fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+  let value = match headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+    Some(res) => res,
+    None => return false,
+  };
+
+  let etag = etag.trim_start_matches("W/");
+  value
+    .split(',')
+    .map(|tag| tag.trim())
+    .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+pub(crate) fn with_etag(mut resp: Response, etag: &str) -> Response {
+  if let Ok(value) = HeaderValue::from_str(etag) {
+    resp.headers_mut().insert(header::ETAG, value);
+  }
+  resp
+}
+
+pub(crate) fn not_modified(etag: &str) -> Response {
+  with_etag(
+    Response::builder().status(StatusCode::NOT_MODIFIED).finish(),
+    etag
+  )
+}
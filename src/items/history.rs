@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::store::{read_json, sidecar_path, write_json};
+
+/// One change of an item, `revision` is the item version after the change
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct Revision {
+  pub revision: u64,
+  pub action: String,
+  /// `None` when the item didn't exist before, e.g. on create
+  pub before: Option<Value>,
+  /// `None` when the item doesn't exist after, e.g. on delete
+  pub after: Option<Value>,
+  pub actor: Option<String>,
+  pub at: String,
+  /// Item the revision was made to, when that item was merged into this one
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub merged_from: Option<u64>,
+}
+
+/// Revisions per item id, oldest first
+pub(crate) type History = BTreeMap<u64, Vec<Revision>>;
+
+pub(crate) fn read_history(data_path: &str) -> std::io::Result<History> {
+  read_json(&sidecar_path(data_path, "history"))
+}
+
+pub(crate) fn write_history(data_path: &str, history: &History) -> std::io::Result<()> {
+  write_json(&sidecar_path(data_path, "history"), history)
+}
+
+/// Appends a revision to the history of the item
+pub(crate) fn record_revision(
+  data_path: &str,
+  id: u64,
+  revision: u64,
+  action: &str,
+  before: Option<Value>,
+  after: Option<Value>,
+  actor: Option<String>,
+) -> std::io::Result<()> {
+  let mut history = read_history(data_path)?;
+  push_revision(&mut history, id, revision, action, before, after, actor);
+  write_history(data_path, &history)
+}
+
+/// Same as `record_revision` for history that is already loaded
+pub(crate) fn push_revision(
+  history: &mut History,
+  id: u64,
+  revision: u64,
+  action: &str,
+  before: Option<Value>,
+  after: Option<Value>,
+  actor: Option<String>,
+) {
+  history.entry(id).or_default().push(Revision {
+    revision,
+    action: action.to_string(),
+    before,
+    after,
+    actor,
+    at: Utc::now().to_rfc3339(),
+    merged_from: None,
+  });
+}
+
+/// Moves the revisions of a merged item to the item it was merged into, they
+/// keep their revision numbers and are marked with the id they were made to
+pub(crate) fn move_history(history: &mut History, from: u64, into: u64) {
+  let moved = match history.remove(&from) {
+    Some(res) => res,
+    None => return,
+  };
+  let revisions = history.entry(into).or_default();
+  revisions.extend(moved.into_iter().map(|r| Revision { merged_from: r.merged_from.or(Some(from)), ..r }));
+  revisions.sort_by(|a, b| a.at.cmp(&b.at));
+}
+
+/// State of the item at `as_of`, `None` when it didn't exist at that time.
+/// Items without recorded revisions are assumed to have never changed.
+pub(crate) fn state_as_of(revisions: &[Revision], current: Option<&Value>, as_of: DateTime<FixedOffset>) -> Option<Value> {
+  let at = |r: &Revision| DateTime::parse_from_rfc3339(&r.at).ok();
+  // Revisions of items merged into it are not states of this item
+  let own: Vec<&Revision> = revisions.iter().filter(|r| r.merged_from.is_none()).collect();
+
+  match own.iter().rev().find(|r| at(r).is_some_and(|at| at <= as_of)) {
+    Some(revision) => revision.after.clone(),
+    None => match own.first() {
+      Some(first) => first.before.clone(),
+      None => current.cloned(),
+    },
+  }
+}
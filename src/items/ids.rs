@@ -0,0 +1,113 @@
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use super::text::fold;
+use crate::store::{read_json, sidecar_path, write_json};
+
+/// Extra identifier given to new items next to the numeric id
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PublicIds {
+  #[default]
+  None,
+  UuidV7,
+  Ulid,
+}
+
+/// Highest id ever handed out, ids of deleted items are never given again
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub(crate) struct Sequence {
+  last_id: u64,
+}
+
+pub(crate) fn read_sequence(data_path: &str) -> std::io::Result<Sequence> {
+  read_json(&sidecar_path(data_path, "sequence"))
+}
+
+pub(crate) fn write_sequence(data_path: &str, sequence: &Sequence) -> std::io::Result<()> {
+  write_json(&sidecar_path(data_path, "sequence"), sequence)
+}
+
+/// Takes the next id, items written without the sequence are accounted for too
+pub(crate) fn next_id(items: &[Value], sequence: &mut Sequence) -> u64 {
+  sequence.last_id = sequence.last_id.max(highest_id(items)) + 1;
+  sequence.last_id
+}
+
+/// Takes the given id, e.g. of an imported item, when it was never handed out.
+/// Ids below it are skipped.
+pub(crate) fn claim_id(items: &[Value], sequence: &mut Sequence, id: u64) -> bool {
+  if id <= sequence.last_id.max(highest_id(items)) {
+    return false
+  }
+  sequence.last_id = id;
+  true
+}
+
+/// Keeps the id of an item leaving the data file from being given again, data
+/// written before the sequence only accounts for the ids still in it
+pub(crate) fn retire_id(sequence: &mut Sequence, id: u64) {
+  sequence.last_id = sequence.last_id.max(id);
+}
+
+fn highest_id(items: &[Value]) -> u64 {
+  items
+    .iter()
+    .filter_map(|item| item.get("id").and_then(|id| id.as_u64()))
+    .max()
+    .unwrap_or(0)
+}
+
+pub(crate) fn new_public_id(kind: PublicIds) -> Option<String> {
+  match kind {
+    PublicIds::None => None,
+    PublicIds::UuidV7 => Some(uuid::Uuid::now_v7().to_string()),
+    PublicIds::Ulid => Some(ulid::Ulid::new().to_string()),
+  }
+}
+
+/// URL friendly form of the name that no other item uses,
+/// e.g. "Pokémon Scarlet" becomes "pokemon-scarlet" or "pokemon-scarlet-2".
+/// Slugs are never only digits so they can't be mistaken for ids.
+/// Items in the trash keep their slug for when they are restored.
+pub(crate) fn new_slug(items: &[Value], trash: &[Value], name: &str) -> String {
+  let mut base = String::new();
+  for c in fold(name).chars() {
+    if c.is_alphanumeric() {
+      base.push(c);
+    } else if !base.is_empty() && !base.ends_with('-') {
+      base.push('-');
+    }
+  }
+  let mut base = base.trim_end_matches('-').to_string();
+  if base.chars().all(|c| c.is_ascii_digit()) {
+    base = format!("item-{}", base).trim_end_matches('-').to_string();
+  }
+
+  let taken = |slug: &str| slug_taken(items, slug) || slug_taken(trash, slug);
+  let mut slug = base.clone();
+  let mut n = 2;
+  while taken(&slug) {
+    slug = format!("{}-{}", base, n);
+    n += 1;
+  }
+  slug
+}
+
+/// Items are addressed by their numeric id, public id or slug
+pub(crate) fn resolve_id(items: &[Value], key: &str) -> Option<u64> {
+  if let Ok(id) = key.parse::<u64>() {
+    return Some(id)
+  }
+
+  items
+    .iter()
+    .find(|item| {
+      item.get("public_id").and_then(|p| p.as_str()) == Some(key)
+        || item.get("slug").and_then(|s| s.as_str()) == Some(key)
+    })
+    .and_then(|item| item.get("id").and_then(|id| id.as_u64()))
+}
+
+pub(crate) fn slug_taken(items: &[Value], slug: &str) -> bool {
+  items.iter().any(|item| item.get("slug").and_then(|s| s.as_str()) == Some(slug))
+}
@@ -0,0 +1,209 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use serde_json::{json, Value};
+
+use super::tags::TagQuery;
+
+/// Query parameters of `GET /items` that are not field filters
+const RESERVED_PARAMS: [&str; 6] = ["limit", "cursor", "sort", "lang", "currency", "price_region"];
+pub(crate) const MAX_LIMIT: usize = 100;
+
+pub(crate) struct Page {
+  pub items: Vec<Value>,
+  pub total: usize,
+  pub next_cursor: Option<String>,
+}
+
+/// Filters, sorts and paginates the items, which are decorated the way they
+/// are shown so stock and prices can be filtered and sorted on.
+///
+/// - `field=value` matches items whose field equals the value, ignoring case
+/// - `attributes.storage_gb_min=512` and other dotted fields reach into objects
+/// - `field_min=n` / `field_max=n` match numeric fields within the range
+/// - `tags=a|b,!c` matches items tagged a or b and not tagged c, see `TagQuery`
+/// - `price` is the amount of the resolved price when it is an object, i.e.
+///   with `currency`, so `price_min=40` and `sort=price` compare amounts
+/// - `sort=field` or `sort=-field` for descending, ties are ordered by id
+/// - `limit=n` with the `cursor` of the previous page, cursors point at the last
+///   item returned so pages stay stable when items are added or removed
+pub(crate) fn list_items(items: &[Value], params: &BTreeMap<String, String>) -> Result<Page, String> {
+  let limit = match params.get("limit") {
+    Some(limit) => match limit.parse::<usize>() {
+      Ok(res) if res > 0 => Some(res.min(MAX_LIMIT)),
+      _ => return Err("limit must be a positive number".to_string()),
+    },
+    None => None,
+  };
+
+  let sort = params.get("sort").map(|s| s.as_str()).unwrap_or("id");
+  let (sort_field, desc) = match sort.strip_prefix('-') {
+    Some(field) => (field, true),
+    None => (sort, false),
+  };
+  if sort_field.is_empty() {
+    return Err("sort must name a field".to_string())
+  }
+
+  let mut filters = Vec::new();
+  for (key, value) in params.iter() {
+    if RESERVED_PARAMS.contains(&key.as_str()) {
+      continue;
+    }
+    filters.push(Filter::parse(key, value)?);
+  }
+
+  let mut filtered: Vec<Value> = items
+    .iter()
+    .filter(|item| filters.iter().all(|f| f.matches(item)))
+    .cloned()
+    .collect();
+  let total = filtered.len();
+
+  filtered.sort_by(|a, b| compare_keys(&sort_key(a, sort_field), &sort_key(b, sort_field), desc));
+
+  if let Some(cursor) = params.get("cursor") {
+    let after = decode_cursor(cursor, sort)?;
+    filtered.retain(|item| compare_keys(&sort_key(item, sort_field), &after, desc) == Ordering::Greater);
+  }
+
+  let mut next_cursor = None;
+  if let Some(limit) = limit {
+    if filtered.len() > limit {
+      filtered.truncate(limit);
+      let last = filtered.last().unwrap();
+      next_cursor = Some(encode_cursor(sort, &sort_key(last, sort_field)));
+    }
+  }
+
+  Ok(Page { items: filtered, total, next_cursor })
+}
+
+enum Filter {
+  Equals(String, String),
+  Min(String, f64),
+  Max(String, f64),
+  Tags(TagQuery),
+}
+
+impl Filter {
+  fn parse(key: &str, value: &str) -> Result<Self, String> {
+    if key == "tags" {
+      return Ok(Filter::Tags(TagQuery::parse(value)?))
+    }
+
+    let range = key.strip_suffix("_min").map(|f| (f, true))
+      .or_else(|| key.strip_suffix("_max").map(|f| (f, false)));
+
+    match range {
+      Some((field, is_min)) => {
+        let bound = match value.parse::<f64>() {
+          Ok(res) => res,
+          Err(_) => return Err(format!("{} must be a number", key)),
+        };
+        match is_min {
+          true => Ok(Filter::Min(field.to_string(), bound)),
+          false => Ok(Filter::Max(field.to_string(), bound)),
+        }
+      }
+      None => Ok(Filter::Equals(key.to_string(), value.to_lowercase())),
+    }
+  }
+
+  fn matches(&self, item: &Value) -> bool {
+    match self {
+      Filter::Equals(field, value) => match field_value(item, field) {
+        Some(Value::Array(values)) => values.iter().any(|v| value_text(v) == *value),
+        Some(v) => value_text(&v) == *value,
+        None => false,
+      },
+      Filter::Min(field, bound) => field_value(item, field).and_then(|v| v.as_f64()).is_some_and(|v| v >= *bound),
+      Filter::Max(field, bound) => field_value(item, field).and_then(|v| v.as_f64()).is_some_and(|v| v <= *bound),
+      Filter::Tags(query) => query.matches(item),
+    }
+  }
+}
+
+/// Field of the item, `a.b` is the field `b` of the object in field `a`.
+/// A resolved price counts as its amount.
+fn field_value(item: &Value, field: &str) -> Option<Value> {
+  let value = field.split('.').try_fold(item, |value, part| value.get(part))?;
+  match (field, value.get("amount").and_then(|a| a.as_str())) {
+    ("price", Some(amount)) => amount.parse::<f64>().ok().map(|amount| json!(amount)),
+    _ => Some(value.clone()),
+  }
+}
+
+fn value_text(value: &Value) -> String {
+  match value {
+    Value::String(s) => s.to_lowercase(),
+    v => v.to_string(),
+  }
+}
+
+fn sort_key(item: &Value, field: &str) -> (Value, u64) {
+  let id = item.get("id").and_then(|id| id.as_u64()).unwrap_or(0);
+  (field_value(item, field).unwrap_or(Value::Null), id)
+}
+
+/// Missing values go last whichever the direction
+fn compare_keys(a: &(Value, u64), b: &(Value, u64), desc: bool) -> Ordering {
+  let missing = a.0.is_null().cmp(&b.0.is_null());
+  let ord = compare_values(&a.0, &b.0).then(a.1.cmp(&b.1));
+  missing.then(match desc {
+    true => ord.reverse(),
+    false => ord,
+  })
+}
+
+/// Numbers before strings before anything else, missing values go last
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+  fn rank(v: &Value) -> u8 {
+    match v {
+      Value::Number(_) => 0,
+      Value::String(_) => 1,
+      Value::Null => 3,
+      _ => 2,
+    }
+  }
+
+  match (a, b) {
+    (Value::Number(x), Value::Number(y)) => {
+      let (x, y) = (x.as_f64().unwrap_or(0.0), y.as_f64().unwrap_or(0.0));
+      x.partial_cmp(&y).unwrap_or(Ordering::Equal)
+    }
+    (Value::String(x), Value::String(y)) => x.to_lowercase().cmp(&y.to_lowercase()).then(x.cmp(y)),
+    _ => rank(a).cmp(&rank(b)),
+  }
+}
+
+/// Cursors are the hex encoded sort and key of the last item of a page
+fn encode_cursor(sort: &str, key: &(Value, u64)) -> String {
+  json!([sort, key.0, key.1])
+    .to_string()
+    .bytes()
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
+
+fn decode_cursor(cursor: &str, sort: &str) -> Result<(Value, u64), String> {
+  let invalid = || "cursor is invalid".to_string();
+
+  if !cursor.len().is_multiple_of(2) || !cursor.is_ascii() {
+    return Err(invalid())
+  }
+  let bytes = (0..cursor.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16))
+    .collect::<Result<Vec<u8>, _>>()
+    .map_err(|_| invalid())?;
+
+  let parts: Vec<Value> = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+  match parts.as_slice() {
+    [Value::String(cursor_sort), value, id] if cursor_sort == sort => {
+      let id = id.as_u64().ok_or_else(invalid)?;
+      Ok((value.clone(), id))
+    }
+    [Value::String(_), _, _] => Err("cursor does not match sort".to_string()),
+    _ => Err(invalid()),
+  }
+}
@@ -0,0 +1,157 @@
+use serde_json::{json, Map, Value};
+
+/// Text fields of an item that can be translated
+pub(crate) const TEXT_FIELDS: [&str; 2] = ["name", "description"];
+
+/// Canonical form of a language tag, e.g. "zh-hant-tw" is "zh-Hant-TW"
+pub(crate) fn normalize_locale(tag: &str) -> Option<String> {
+  let parts: Vec<&str> = tag.trim().split(['-', '_']).collect();
+  let valid = parts.iter().all(|p| (1..=8).contains(&p.len()) && p.chars().all(|c| c.is_ascii_alphanumeric()))
+    && parts[0].chars().all(|c| c.is_ascii_alphabetic())
+    && (2..=3).contains(&parts[0].len());
+  if !valid {
+    return None
+  }
+
+  let parts: Vec<String> = parts
+    .iter()
+    .enumerate()
+    .map(|(i, part)| match (i, part.len()) {
+      (0, _) => part.to_lowercase(),
+      (_, 4) => {
+        let mut script = part.to_lowercase();
+        script[..1].make_ascii_uppercase();
+        script
+      }
+      (_, 2) => part.to_uppercase(),
+      _ => part.to_lowercase(),
+    })
+    .collect();
+  Some(parts.join("-"))
+}
+
+/// Locales to try in order. `lang` is a comma separated list and wins over
+/// `Accept-Language`, whose entries are ordered by their `q` weight.
+/// Every locale is followed by its shorter forms, e.g. "zh-Hant-TW",
+/// "zh-Hant", "zh", and the chain ends with the default locale.
+pub(crate) fn locale_chain(lang: Option<&str>, accept_language: Option<&str>, default_locale: &str) -> Vec<String> {
+  let mut weighted: Vec<(f32, String)> = match (lang, accept_language) {
+    (Some(lang), _) => lang.split(',').map(|tag| (1.0, tag.to_string())).collect(),
+    (None, Some(header)) => header
+      .split(',')
+      .filter_map(|entry| {
+        let mut parts = entry.split(';');
+        let tag = parts.next()?.trim().to_string();
+        let q = parts
+          .find_map(|p| p.trim().strip_prefix("q="))
+          .map(|q| q.parse::<f32>().unwrap_or(0.0))
+          .unwrap_or(1.0);
+        Some((q, tag))
+      })
+      .collect(),
+    (None, None) => Vec::new(),
+  };
+  // Stable so tags with the same weight keep their order
+  weighted.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+  let mut chain: Vec<String> = Vec::new();
+  for (q, tag) in weighted {
+    let tag = match normalize_locale(&tag) {
+      Some(res) if q > 0.0 => res,
+      _ => continue,
+    };
+    let parts: Vec<&str> = tag.split('-').collect();
+    for len in (1..=parts.len()).rev() {
+      let locale = parts[..len].join("-");
+      if !chain.contains(&locale) {
+        chain.push(locale);
+      }
+    }
+  }
+  if let Some(default_locale) = normalize_locale(default_locale) {
+    if !chain.contains(&default_locale) {
+      chain.push(default_locale);
+    }
+  }
+  chain
+}
+
+fn translations(item: &Value) -> Option<&Map<String, Value>> {
+  item.get("translations").and_then(|t| t.as_object())
+}
+
+/// Base name and the names of every translation
+pub(crate) fn item_names(item: &Value) -> Vec<&str> {
+  let mut names: Vec<&str> = item.get("name").and_then(|n| n.as_str()).into_iter().collect();
+  if let Some(translations) = translations(item) {
+    names.extend(translations.values().filter_map(|t| t.get("name").and_then(|n| n.as_str())));
+  }
+  names
+}
+
+/// Every text field in every locale
+pub(crate) fn item_texts(item: &Value) -> Vec<&str> {
+  let mut texts: Vec<&str> = TEXT_FIELDS.iter().filter_map(|f| item.get(*f).and_then(|t| t.as_str())).collect();
+  if let Some(translations) = translations(item) {
+    for translation in translations.values() {
+      texts.extend(TEXT_FIELDS.iter().filter_map(|f| translation.get(*f).and_then(|t| t.as_str())));
+    }
+  }
+  texts
+}
+
+/// Replaces the text fields with the first translation found along the chain,
+/// fields without a translation keep the base text of the default locale.
+/// Returns the locale of the name.
+pub(crate) fn localize(item: &mut Value, chain: &[String], default_locale: &str) -> Option<String> {
+  let translations = translations(item)?.clone();
+  let default_locale = normalize_locale(default_locale).unwrap_or_default();
+
+  let mut name_locale = None;
+  for field in TEXT_FIELDS.iter() {
+    for locale in chain.iter() {
+      if *locale == default_locale {
+        break;
+      }
+      if let Some(text) = translations.get(locale).and_then(|t| t.get(*field)) {
+        item[*field] = text.clone();
+        if *field == "name" {
+          name_locale = Some(locale.clone());
+        }
+        break;
+      }
+    }
+  }
+  let locale = name_locale.unwrap_or(default_locale);
+  item["locale"] = json!(locale);
+  Some(locale)
+}
+
+/// Sets the text of a locale, an empty translation is removed
+pub(crate) fn set_translation(item: &mut Value, locale: &str, translation: Map<String, Value>) {
+  let fields = match item.as_object_mut() {
+    Some(res) => res,
+    None => return,
+  };
+  let translations = fields
+    .entry("translations")
+    .or_insert_with(|| json!({}))
+    .as_object_mut();
+  if let Some(translations) = translations {
+    match translation.is_empty() {
+      true => translations.remove(locale),
+      false => translations.insert(locale.to_string(), Value::Object(translation)),
+    };
+    if translations.is_empty() {
+      fields.remove("translations");
+    }
+  }
+}
+
+pub(crate) fn translation_of(item: &Value, locale: &str) -> Map<String, Value> {
+  translations(item)
+    .and_then(|t| t.get(locale))
+    .and_then(|t| t.as_object())
+    .cloned()
+    .unwrap_or_default()
+}
@@ -0,0 +1,176 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use crate::items::trash::read_trash;
+use crate::media::sniff::{sniff, thumbnail};
+use crate::media::BlobStore;
+use crate::store::{data_lock, read_json};
+
+/// Most media one item can have
+pub(crate) const MAX_MEDIA: usize = 20;
+
+/// Image or other file uploaded for an item, the blobs are in the `BlobStore`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct Attachment {
+  pub id: String,
+  /// Sniffed from the file, not taken from the upload
+  pub content_type: String,
+  pub size: u64,
+  /// Size of an image in pixels
+  pub width: Option<u32>,
+  pub height: Option<u32>,
+  pub key: String,
+  pub url: String,
+  pub thumbnail_key: Option<String>,
+  pub thumbnail_url: Option<String>,
+  pub uploaded_at: String,
+}
+
+/// URL the blob is served at
+pub(crate) fn media_url(key: &str) -> String {
+  format!("/media/{}", key)
+}
+
+pub(crate) fn attachments_of(item: &Value) -> Vec<Attachment> {
+  item
+    .get("media")
+    .and_then(|m| serde_json::from_value(m.clone()).ok())
+    .unwrap_or_default()
+}
+
+/// Sets the media of the item, the field is dropped when there is none
+pub(crate) fn set_attachments(item: &mut Value, attachments: &[Attachment]) {
+  if let Some(fields) = item.as_object_mut() {
+    match attachments.is_empty() {
+      true => fields.remove("media"),
+      false => fields.insert("media".to_string(), json!(attachments)),
+    };
+  }
+}
+
+/// Gives `item` the media of `current`, blobs of removed media are deleted
+/// right away so e.g. reverting an item can't bring them back
+pub(crate) fn keep_media(item: &mut Value, current: &Value) {
+  set_attachments(item, &attachments_of(current));
+}
+
+/// Keys of blobs put in the store for items that are not saved yet, they are
+/// put before the data lock is taken and `purge_media` must leave them alone
+static PENDING: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| {
+  Mutex::new(HashSet::new())
+});
+
+/// Blobs of an upload until the item holding them is saved, the blobs are
+/// deleted again when it is dropped without `keep`
+pub(crate) struct PendingUpload {
+  store: Arc<dyn BlobStore>,
+  attachment: Attachment,
+  kept: bool,
+}
+
+impl PendingUpload {
+  fn new(store: Arc<dyn BlobStore>, attachment: Attachment) -> Self {
+    let mut pending = PENDING.lock().unwrap();
+    pending.insert(attachment.key.clone());
+    pending.extend(attachment.thumbnail_key.clone());
+    Self { store, attachment, kept: false }
+  }
+
+  /// The item is saved with the attachment, call with the data lock held
+  pub(crate) fn keep(mut self) -> Attachment {
+    self.kept = true;
+    self.attachment.clone()
+  }
+}
+
+impl Drop for PendingUpload {
+  fn drop(&mut self) {
+    if !self.kept {
+      // A blob that can't be deleted now is left to `purge_media`
+      let _ = delete_blobs(self.store.as_ref(), &self.attachment);
+    }
+    let mut pending = PENDING.lock().unwrap();
+    pending.remove(&self.attachment.key);
+    if let Some(thumbnail_key) = &self.attachment.thumbnail_key {
+      pending.remove(thumbnail_key);
+    }
+  }
+}
+
+/// Sniffs the upload, makes a thumbnail of an image and puts the blobs in the
+/// store. `None` when the media type is not supported.
+/// Blocks while the image is decoded and scaled.
+pub(crate) fn store_upload(store: Arc<dyn BlobStore>, item_id: u64, bytes: &[u8]) -> std::io::Result<Option<PendingUpload>> {
+  let (content_type, extension) = match sniff(bytes) {
+    Some(res) => res,
+    None => return Ok(None),
+  };
+
+  let media_id = ulid::Ulid::new().to_string();
+  let key = format!("{}/{}.{}", item_id, media_id, extension);
+  let thumbnail = thumbnail(bytes, content_type);
+  let thumbnail_key = thumbnail.as_ref().map(|_t| format!("{}/{}-thumb.png", item_id, media_id));
+
+  let upload = PendingUpload::new(store.clone(), Attachment {
+    id: media_id,
+    content_type: content_type.to_string(),
+    size: bytes.len() as u64,
+    width: thumbnail.as_ref().map(|t| t.width),
+    height: thumbnail.as_ref().map(|t| t.height),
+    url: media_url(&key),
+    key: key.clone(),
+    thumbnail_url: thumbnail_key.as_deref().map(media_url),
+    thumbnail_key: thumbnail_key.clone(),
+    uploaded_at: Utc::now().to_rfc3339(),
+  });
+
+  store.put(&key, bytes)?;
+  if let (Some(thumbnail), Some(thumbnail_key)) = (&thumbnail, &thumbnail_key) {
+    store.put(thumbnail_key, &thumbnail.png)?;
+  }
+  Ok(Some(upload))
+}
+
+/// Deletes the blob of the attachment and its thumbnail
+pub(crate) fn delete_blobs(store: &dyn BlobStore, attachment: &Attachment) -> std::io::Result<()> {
+  store.delete(&attachment.key)?;
+  match &attachment.thumbnail_key {
+    Some(thumbnail_key) => store.delete(thumbnail_key),
+    None => Ok(()),
+  }
+}
+
+/// Blob keys of the media of the items
+fn blob_keys(items: &[Value]) -> HashSet<String> {
+  items
+    .iter()
+    .flat_map(attachments_of)
+    .flat_map(|a| [Some(a.key), a.thumbnail_key])
+    .flatten()
+    .collect()
+}
+
+/// Deletes blobs no item or item in the trash has anymore, e.g. those of
+/// purged items or of uploads that failed. Returns how many were deleted.
+pub async fn purge_media(data_path: &str, store: &dyn BlobStore) -> std::io::Result<usize> {
+  let _guard = data_lock(data_path).write_owned().await;
+
+  let items: Vec<Value> = read_json(data_path)?;
+  let mut kept = blob_keys(&items);
+  kept.extend(blob_keys(&read_trash(data_path)?));
+
+  let pending = PENDING.lock().unwrap().clone();
+
+  let mut purged = 0;
+  for key in store.list()? {
+    if !kept.contains(&key) && !pending.contains(&key) {
+      store.delete(&key)?;
+      purged += 1;
+    }
+  }
+  Ok(purged)
+}
@@ -0,0 +1,203 @@
+use std::io::ErrorKind;
+use poem::http::header;
+use poem::web::{Data, Multipart, Path};
+use poem::{handler, http::StatusCode, Request, Response, Result};
+use serde_json::Value;
+use tokio::io::AsyncReadExt;
+
+use super::etag::{if_match, item_etag, read_versions, version_of, with_etag};
+use super::{find_item, item_not_found, load_items, precondition_failed, save_item_change};
+use crate::media::sniff::content_type_of;
+use crate::store::data_lock;
+use crate::{error_response_json, response_json, Config, ErrorResponse};
+pub use attachments::purge_media;
+pub(crate) use attachments::{attachments_of, delete_blobs, keep_media, set_attachments, store_upload, MAX_MEDIA};
+
+mod attachments;
+
+/// Uploads an image or other media for the item as the `file` field of a
+/// multipart form. Images get a thumbnail.
+/// The upload is received and stored without the lock, only the update of
+/// the item holds it. Blobs of an upload that fails after that are removed by
+/// purge_media.
+#[handler]
+pub(crate) async fn post_item_media(
+  req: &Request,
+  key: Path<String>,
+  mut multipart: Multipart,
+  data_path: Data<&String>,
+  config: Data<&Config>
+) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware, loaded without holding the lock
+  let id = match find_item(req.extensions().get::<Vec<Value>>().unwrap(), &key) {
+    Some((id, _index)) => id,
+    None => return Err(item_not_found())
+  };
+
+  let invalid_upload = |msg: &str| error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+    error: "Invalid upload".to_string(),
+    msg: msg.to_string()
+  });
+
+  let mut bytes = None;
+  while let Some(field) = multipart.next_field().await.map_err(|_e| invalid_upload("Body is not a valid multipart form"))? {
+    if field.name() != Some("file") {
+      continue;
+    }
+    // One byte over the limit is enough to know the file is too large
+    let mut buf = Vec::new();
+    if field.into_async_read().take(config.max_media_size as u64 + 1).read_to_end(&mut buf).await.is_err() {
+      return Err(invalid_upload("Body is not a valid multipart form"))
+    }
+    bytes = Some(buf);
+    break;
+  }
+  let bytes = match bytes {
+    Some(res) if !res.is_empty() => res,
+    _ => return Err(invalid_upload("Send the media as the file field"))
+  };
+  if bytes.len() > config.max_media_size {
+    return Err(error_response_json(StatusCode::PAYLOAD_TOO_LARGE, ErrorResponse {
+      error: "File too large".to_string(),
+      msg: format!("Media can be at most {} bytes", config.max_media_size)
+    }))
+  }
+
+  // Decoding and scaling an image takes a while, it runs off the async workers
+  let store = config.blob_store(data_path.as_str());
+  let upload = match tokio::task::spawn_blocking(move || store_upload(store, id, &bytes)).await {
+    Ok(Ok(Some(res))) => res,
+    Ok(Ok(None)) => {
+      return Err(error_response_json(StatusCode::UNSUPPORTED_MEDIA_TYPE, ErrorResponse {
+        error: "Unsupported media type".to_string(),
+        msg: "Upload a PNG, JPEG, GIF or WebP image, an MP4 video or a PDF".to_string()
+      }))
+    }
+    _ => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error post_item_media 2".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let _guard = data_lock(data_path.as_str()).write_owned().await;
+  let mut items = load_items(data_path.as_str())?;
+  let (id, index) = match find_item(&items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let versions = match read_versions(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error post_item_media 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  if !if_match(req.headers(), &item_etag(version_of(&versions, id))) {
+    return Err(precondition_failed())
+  }
+
+  let mut attachments = attachments_of(&items[index]);
+  if attachments.len() >= MAX_MEDIA {
+    return Err(error_response_json(StatusCode::CONFLICT, ErrorResponse {
+      error: "Too many media".to_string(),
+      msg: format!("Items can have at most {} media", MAX_MEDIA)
+    }))
+  }
+  // Kept from here on, a failed save may have written the items already
+  let attachment = upload.keep();
+  attachments.push(attachment.clone());
+
+  let before = items[index].clone();
+  set_attachments(&mut items[index], &attachments);
+  let after = items[index].clone();
+  let version = save_item_change(req, &items, Some(before), &after, "update", data_path.as_str(), "post_item_media")?;
+  Ok(with_etag(response_json(StatusCode::CREATED, attachment), &item_etag(version)))
+}
+
+#[handler]
+pub(crate) async fn delete_item_media(
+  req: &Request,
+  Path((key, media_id)): Path<(String, String)>,
+  data_path: Data<&String>,
+  config: Data<&Config>
+) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let mut items = req.extensions().get::<Vec<Value>>().unwrap().clone();
+
+  let (id, index) = match find_item(&items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let versions = match read_versions(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error delete_item_media 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  if !if_match(req.headers(), &item_etag(version_of(&versions, id))) {
+    return Err(precondition_failed())
+  }
+
+  let mut attachments = attachments_of(&items[index]);
+  let removed = match attachments.iter().position(|a| a.id == media_id) {
+    Some(position) => attachments.remove(position),
+    None => {
+      return Err(error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+        error: "Not found".to_string(),
+        msg: "Media does not exist".to_string()
+      }))
+    }
+  };
+
+  let before = items[index].clone();
+  set_attachments(&mut items[index], &attachments);
+  let after = items[index].clone();
+  let version = save_item_change(req, &items, Some(before), &after, "update", data_path.as_str(), "delete_item_media")?;
+
+  if delete_blobs(config.blob_store(data_path.as_str()).as_ref(), &removed).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error delete_item_media 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  Ok(with_etag(response_json(StatusCode::OK, &after), &item_etag(version)))
+}
+
+/// Serves a media blob, keys are never reused so it can be cached for good
+#[handler]
+pub(crate) async fn get_media(key: Path<String>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  let store = config.blob_store(data_path.as_str());
+  let bytes = match store.get(&key) {
+    Ok(res) => res,
+    Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::InvalidInput) => {
+      return Err(error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+        error: "Not found".to_string(),
+        msg: "Media does not exist".to_string()
+      }))
+    }
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_media 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  Ok(Response::builder()
+    .status(StatusCode::OK)
+    .header(header::CONTENT_TYPE, content_type_of(&key))
+    .header(header::CACHE_CONTROL, "public, max-age=31536000, immutable")
+    .body(bytes))
+}
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use poem::http::{header, StatusCode};
+use poem::{Endpoint, IntoResponse, Middleware, Request, Response, Result};
+use serde_json::Value;
+
+use super::ids::resolve_id;
+use super::media::MAX_MEDIA;
+use crate::store::{read_json, sidecar_path, write_json};
+use crate::{error_response_json, ErrorResponse};
+
+/// Fields the merged item never takes from the duplicate: its identity, its
+/// place in the lifecycle and its components, which are merged on their own
+const OWN_FIELDS: [&str; 8] = ["id", "public_id", "slug", "parent_id", "options", "status", "release_date", "components"];
+
+/// Fills in what the item lacks from its duplicate. The item's own values win,
+/// lists such as tags, categories and media are joined and maps such as
+/// attributes and translations gain the keys they don't have.
+pub(crate) fn merge_fields(item: &mut Value, duplicate: &Value) {
+  let (fields, duplicate_fields) = match (item.as_object_mut(), duplicate.as_object()) {
+    (Some(fields), Some(duplicate_fields)) => (fields, duplicate_fields),
+    _ => return,
+  };
+
+  for (key, value) in duplicate_fields.iter() {
+    if OWN_FIELDS.contains(&key.as_str()) {
+      continue;
+    }
+    match (fields.get_mut(key), value) {
+      (None, _) => {
+        fields.insert(key.clone(), value.clone());
+      }
+      (Some(Value::Array(own)), Value::Array(other)) => {
+        for element in other.iter() {
+          if !own.contains(element) {
+            own.push(element.clone());
+          }
+        }
+      }
+      (Some(Value::Object(own)), Value::Object(other)) => {
+        for (other_key, other_value) in other.iter() {
+          own.entry(other_key.clone()).or_insert_with(|| other_value.clone());
+        }
+      }
+      _ => {}
+    }
+  }
+
+  if let Some(Value::Array(media)) = fields.get_mut("media") {
+    media.truncate(MAX_MEDIA);
+  }
+}
+
+/// Moves the entries of `from` to `into`, leaving those that clash with one
+/// `into` already has, e.g. a price in the same region and currency
+pub(crate) fn move_entries<T>(entries: &mut BTreeMap<u64, Vec<T>>, from: u64, into: u64, clash: impl Fn(&T, &T) -> bool) {
+  let moved = entries.remove(&from).unwrap_or_default();
+  let own = entries.entry(into).or_default();
+  let kept: Vec<T> = moved
+    .into_iter()
+    .filter(|entry| !own.iter().any(|o| clash(o, entry)))
+    .collect();
+  own.extend(kept);
+  if own.is_empty() {
+    entries.remove(&into);
+  }
+}
+
+/// Ids of merged items to the item they were merged into
+pub(crate) type Redirects = BTreeMap<u64, u64>;
+
+pub(crate) fn read_redirects(data_path: &str) -> std::io::Result<Redirects> {
+  read_json(&sidecar_path(data_path, "redirects"))
+}
+
+pub(crate) fn write_redirects(data_path: &str, redirects: &Redirects) -> std::io::Result<()> {
+  write_json(&sidecar_path(data_path, "redirects"), redirects)
+}
+
+/// Sends `from` and the ids merged into it before to `into`, so there is
+/// never more than one redirect to follow
+pub(crate) fn add_redirect(redirects: &mut Redirects, from: u64, into: u64) {
+  for target in redirects.values_mut() {
+    if *target == from {
+      *target = into;
+    }
+  }
+  redirects.insert(from, into);
+}
+
+/// Sends requests for an item that was merged away to the same path under the
+/// item it was merged into, e.g. `/items/2/stock` to `/items/1/stock`.
+/// Goes inside `AuthMiddleware`, which loads the items.
+pub(crate) struct RedirectMiddleware {
+  data_path: Arc<String>
+}
+
+impl RedirectMiddleware {
+  pub fn new(data_path: Arc<String>) -> Self {
+    Self { data_path }
+  }
+}
+
+impl<E: Endpoint> Middleware<E> for RedirectMiddleware {
+  type Output = RedirectMiddlewareImpl<E>;
+
+  fn transform(&self, ep: E) -> Self::Output {
+    RedirectMiddlewareImpl {
+      inner: ep,
+      data_path: self.data_path.clone()
+    }
+  }
+}
+
+pub(crate) struct RedirectMiddlewareImpl<E> {
+  inner: E,
+  data_path: Arc<String>,
+}
+
+impl<E: Endpoint> Endpoint for RedirectMiddlewareImpl<E> {
+  type Output = Response;
+
+  async fn call(&self, req: Request) -> Result<Self::Output> {
+    let key = req.raw_path_param("id").unwrap_or_default().to_string();
+    let items = req.extensions().get::<Vec<Value>>().map(|i| i.as_slice()).unwrap_or_default();
+    let id = match resolve_id(items, &key) {
+      Some(id) if !items.iter().any(|item| item.get("id").and_then(|i| i.as_u64()) == Some(id)) => id,
+      _ => return self.inner.call(req).await.map(IntoResponse::into_response)
+    };
+
+    let redirects = match read_redirects(&self.data_path) {
+      Ok(res) => res,
+      Err(_e) => {
+        return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+          error: format!("Error reading redirects of {:?}", self.data_path),
+          msg: "Please contact support".to_string()
+        }))
+      }
+    };
+    let into = match redirects.get(&id) {
+      Some(res) => res,
+      None => return self.inner.call(req).await.map(IntoResponse::into_response)
+    };
+
+    let rest = req.uri().path().strip_prefix(&format!("/items/{}", key)).unwrap_or_default();
+    let mut location = format!("/items/{}{}", into, rest);
+    if let Some(query) = req.uri().query() {
+      location = format!("{}?{}", location, query);
+    }
+    Ok(Response::builder()
+      .status(StatusCode::PERMANENT_REDIRECT)
+      .header(header::LOCATION, location)
+      .finish())
+  }
+}
@@ -1,15 +1,81 @@
+use chrono::{DateTime, Utc};
 use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
 use poem::http::Method;
-use poem::web::{Data, Path};
-use poem::{get, post, Endpoint, EndpointExt, IntoResponse, Middleware, Request, Response, Result};
+use poem::http::{header, HeaderValue};
+use poem::web::{Data, Path, Query};
+use poem::{delete, get, post, put, Body, Endpoint, EndpointExt, IntoResponse, Middleware, Request, Response, Result};
 use poem::{handler, http::StatusCode, web::Json, Route};
 use serde::{Serialize, Deserialize};
-use serde_json::{from_str, json, Value};
+use serde_json::{from_str, json, Map, Value};
+use barcodes::{barcodes_of, normalize_barcode, BarcodeIndex, MAX_BARCODES};
+use bulk::{BulkMode, BulkReq, MAX_OPERATIONS};
+pub(crate) use catalogue::Catalogue;
+use bundles::{bundles_above, check_components, combine_components, components_of, decorate_bundle, set_components, Component};
+use duplicates::{find_duplicates, DEFAULT_THRESHOLD};
+use etag::{bump_version, if_match, if_none_match, item_etag, list_etag, localized_etag, not_modified, read_versions, version_of, with_etag, write_versions};
+use listing::{list_items, MAX_LIMIT};
+use locale::{item_names, locale_chain, localize, normalize_locale, set_translation, translation_of};
+use ids::{new_slug, read_sequence, resolve_id, retire_id, slug_taken};
+pub use ids::PublicIds;
+use history::{move_history, push_revision, read_history, record_revision, state_as_of};
+use media::{delete_item_media, get_media, keep_media, post_item_media};
+pub use media::purge_media;
+use merge::{add_redirect, merge_fields, move_entries, read_redirects, RedirectMiddleware};
+use names::NameIndex;
+pub use names::NamePolicy;
+use prices::{delete_item_price, delete_item_sale, get_item_prices, post_item_price, post_item_sale};
+use prices::{read_prices, read_rates, read_sales, resolve_price};
+pub(crate) use prices::{format_amount, parse_currency, GLOBAL_REGION};
+pub use prices::run_price_schedule;
+use relations::{delete_item_relation, get_item_relations, post_item_relation, read_relations};
+use search::search_items;
+use state::ItemState;
+use status::{get_item_status, put_item_status};
+use status::{keep_lifecycle, move_transitions, read_transitions, ItemStatus};
+pub use status::run_release_schedule;
+use stock::{confirm_reservation, get_item_reservations, get_item_stock, get_low_stock, get_reservation, get_stock_ledger, post_reservation, post_stock_adjustment, put_stock_threshold, release_reservation};
+use stock::{read_inventory, Inventory};
+pub use stock::expire_reservations;
+use tags::{normalize_tag, set_tags, tag_counts, tags_of, MAX_TAGS};
+use trash::{read_trash, write_trash};
+use transfer::{export_body, read_import, Format, MAX_IMPORT_ROWS};
+pub use trash::purge_trash;
+use variants::{get_item_variants, post_item_variant, put_item_variant};
+use variants::{check_variant_conflicts, inherit_title, options_owner, parent_of, variants_of};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{read_to_string, write, OpenOptions};
 use std::io::Write;
 use std::sync::Arc;
 
-use crate::{error_response_json, response_json, Claims, ErrorResponse, SECRET_KEY};
+use crate::categories::{category_ids_of, check_item_attributes, read_categories};
+use crate::store::{data_lock, sidecar_path, write_json, FileBatch};
+use crate::{error_response_json, response_json, Claims, Config, ErrorResponse, SECRET_KEY};
+
+mod barcodes;
+mod bulk;
+mod bundles;
+mod catalogue;
+mod duplicates;
+mod entries;
+mod etag;
+mod history;
+mod ids;
+mod listing;
+mod locale;
+mod media;
+mod merge;
+mod names;
+mod prices;
+mod relations;
+mod search;
+mod state;
+mod status;
+mod stock;
+mod tags;
+mod text;
+mod transfer;
+mod trash;
+mod variants;
 
 pub fn route(data_path: String) -> Route {
   Route::new()
@@ -17,9 +83,154 @@ pub fn route(data_path: String) -> Route {
       .get(get_items)
       .with(AuthMiddleware::new(data_path.clone().into()))
     )
+    .at("/items/bulk", post(bulk_items)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/export", get(export_items)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/import", post(import_items)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/search", get(search_item)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/by-barcode/:code", get(get_item_by_barcode)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/duplicates", get(get_duplicates)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/merge", post(merge_item)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/trash", get(get_trash)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/restore", post(restore_item)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/history", get(get_history)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/revert", post(revert_item)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/categories", put(put_item_categories)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/translations/:locale", put(put_item_translation)
+      .delete(delete_item_translation)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/media", post(post_item_media)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::without_lock(data_path.clone().into()))
+    )
+    .at("/items/:id/media/:media_id", delete(delete_item_media)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/media/*key", get(get_media))
+    .at("/items/:id/relations", get(get_item_relations)
+      .post(post_item_relation)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/relations/:relation_id", delete(delete_item_relation)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/status", get(get_item_status)
+      .put(put_item_status)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/components", put(put_item_components)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/variants", get(get_item_variants)
+      .post(post_item_variant)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/variants/:variant_id", put(put_item_variant)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/tags", put(put_item_tags)
+      .post(post_item_tags)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/tags/:tag", delete(delete_item_tag)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/tags", get(get_tags)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/stock", get(get_item_stock)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/stock/adjustments", post(post_stock_adjustment)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/stock/ledger", get(get_stock_ledger)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/stock/threshold", put(put_stock_threshold)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/stock/low", get(get_low_stock)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/prices", get(get_item_prices)
+      .post(post_item_price)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/prices/:price_id", delete(delete_item_price)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/sales", post(post_item_sale)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/sales/:sale_id", delete(delete_item_sale)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/items/:id/reservations", post(post_reservation)
+      .get(get_item_reservations)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/reservations/:id", get(get_reservation)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/reservations/:id/confirm", post(confirm_reservation)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
+    .at("/reservations/:id/release", post(release_reservation)
+      .with(AuthMiddleware::new(data_path.clone().into()))
+    )
     .at("/items/:id", get(get_item)
       .put(put_item)
+      .patch(patch_item)
       .delete(delete_item)
+      .with(RedirectMiddleware::new(data_path.clone().into()))
       .with(AuthMiddleware::new(data_path.clone().into()))
     )
 }
@@ -28,278 +239,1833 @@ pub fn route(data_path: String) -> Route {
 async fn post_item(
   req: &Request, 
   item_req: Json<ItemReq>, 
-  data_path: Data<&String>
+  data_path: Data<&String>,
+  config: Data<&Config>
+) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap().clone();
+
+  let mut state = match ItemState::load(data_path.as_str(), items) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error post_item 9".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let (_status, item) = state.create(&item_req, &config, &actor(req)).map_err(|e| e.into_error())?;
+  if state.save(data_path.as_str()).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error post_item 5".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  Ok(with_etag(response_json(StatusCode::CREATED, &item), &item_etag(1)))
+}
+
+#[handler]
+async fn get_items(req: &Request, params: Query<BTreeMap<String, String>>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  page_response(req, items, &params, data_path.as_str(), &config, "get_items")
+}
+
+/// One page of `items` filtered, sorted and paged by `params`, with the list
+/// ETag, `X-Total-Count` and a `Link` to the next page. With `currency` (and
+/// `price_region`) every item has its resolved price.
+pub(crate) fn page_response(req: &Request, items: &[Value], params: &BTreeMap<String, String>, data_path: &str, config: &Config, handler: &str) -> Result<Response> {
+  let server_error = |n: u8| error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+    error: format!("Server error {} {}", handler, n),
+    msg: "Please contact support".to_string()
+  });
+
+  let inventory = match read_inventory(data_path) {
+    Ok(res) => res,
+    Err(_e) => return Err(server_error(2))
+  };
+
+  // Filters and sort see the items as they are shown
+  let mut decorated = items.to_vec();
+  decorated.iter_mut().for_each(|item| decorate_item(item, items, &inventory));
+
+  if let Some(currency) = params.get("currency") {
+    let currency = match parse_currency(currency) {
+      Ok(res) => res,
+      Err(e) => {
+        return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+          error: "Invalid query".to_string(),
+          msg: e
+        }))
+      }
+    };
+    let region = params.get("price_region").map(|r| r.trim().to_lowercase()).unwrap_or(GLOBAL_REGION.to_string());
+
+    let (prices, sales, rates) = match (read_prices(data_path), read_sales(data_path), read_rates(data_path, &config.exchange_rates_path)) {
+      (Ok(prices), Ok(sales), Ok(rates)) => (prices, sales, rates),
+      _ => return Err(server_error(3))
+    };
+    let now = Utc::now();
+    for item in decorated.iter_mut() {
+      let id = item.get("id").and_then(|i| i.as_u64()).unwrap_or(0);
+      let entries = prices.get(&id).map(|e| e.as_slice()).unwrap_or_default();
+      let item_sales = sales.get(&id).map(|s| s.as_slice()).unwrap_or_default();
+      item["price"] = json!(resolve_price(entries, item_sales, &region, &currency, &rates, now));
+    }
+  }
+
+  let page = match list_items(&decorated, params) {
+    Ok(res) => res,
+    Err(e) => {
+      return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+        error: "Invalid query".to_string(),
+        msg: e
+      }))
+    }
+  };
+
+  let versions = match read_versions(data_path) {
+    Ok(res) => res,
+    Err(_e) => return Err(server_error(1))
+  };
+
+  // Prices change over time without the items changing, priced pages have no ETag
+  let etag = list_etag(&page, &versions, params);
+  let priced = params.contains_key("currency");
+  if !priced && if_none_match(req.headers(), &etag) {
+    return Ok(not_modified(&etag))
+  }
+
+  let mut page_items = page.items;
+  localize_items(req, params.get("lang").map(|l| l.as_str()), &mut page_items);
+  let mut resp = response_json(StatusCode::OK, &page_items);
+  if !priced {
+    resp = with_etag(resp, &etag);
+  }
+  resp.headers_mut().insert(header::VARY, HeaderValue::from_static("Accept-Language"));
+  resp.headers_mut().insert("X-Total-Count", page.total.into());
+  if let Some(cursor) = page.next_cursor {
+    // Keep the caller's filters and sort, only the cursor moves
+    let mut query: Vec<&str> = req.uri().query().unwrap_or("")
+      .split('&')
+      .filter(|p| !p.is_empty() && !p.starts_with("cursor="))
+      .collect();
+    let cursor_param = format!("cursor={}", cursor);
+    query.push(&cursor_param);
+
+    let link = format!("<{}?{}>; rel=\"next\"", req.uri().path(), query.join("&"));
+    if let Ok(value) = HeaderValue::from_str(&link) {
+      resp.headers_mut().insert(header::LINK, value);
+    }
+  }
+
+  Ok(resp)
+}
+
+/// Creates, updates and deletes many items with a single write of the data
+#[handler]
+async fn bulk_items(req: &Request, bulk_req: Json<BulkReq>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap().clone();
+
+  if bulk_req.operations.is_empty() || bulk_req.operations.len() > MAX_OPERATIONS {
+    return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+      error: "Invalid operations".to_string(),
+      msg: format!("Send between 1 and {} operations", MAX_OPERATIONS)
+    }))
+  }
+
+  let mut state = match ItemState::load(data_path.as_str(), items) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error bulk_items 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let (status, results, changed) = bulk::run(&mut state, &bulk_req, &config, &actor(req));
+  if changed && state.save(data_path.as_str()).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error bulk_items 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  Ok(response_json(status, json!({
+    "results": results
+  })))
+}
+
+/// Whole catalogue as a file download
+#[handler]
+async fn export_items(req: &Request, params: Query<ExportQuery>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap().clone();
+  let format = params.format.unwrap_or(Format::Csv);
+
+  Ok(Response::builder()
+    .status(StatusCode::OK)
+    .content_type(format.content_type())
+    .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"items.{}\"", format.extension()))
+    .body(export_body(items, format)))
+}
+
+/// Creates or updates items from a CSV, NDJSON or JSON file, every row is reported on.
+/// With `dry_run` the rows are only validated.
+#[handler]
+async fn import_items(
+  req: &Request,
+  body: Body,
+  params: Query<ImportQuery>,
+  data_path: Data<&String>,
+  config: Data<&Config>
 ) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap().clone();
+
+  let format = params.format.or_else(|| {
+    req.headers()
+      .get(header::CONTENT_TYPE)
+      .and_then(|c| c.to_str().ok())
+      .and_then(Format::from_content_type)
+  }).unwrap_or(Format::Csv);
+
+  // e.g. `Title:name,Item ID:id`
+  let mut mapping = HashMap::new();
+  for pair in params.map.as_deref().unwrap_or("").split(',').filter(|p| !p.trim().is_empty()) {
+    match pair.rsplit_once(':') {
+      Some((column, field)) => {
+        mapping.insert(column.trim().to_string(), field.trim().to_lowercase());
+      }
+      None => {
+        return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+          error: "Invalid map".to_string(),
+          msg: "map must look like column:field,column:field".to_string()
+        }))
+      }
+    }
+  }
+
+  let rows = match read_import(body, format, mapping, MAX_IMPORT_ROWS).await {
+    Ok(res) => res,
+    Err(e) => {
+      return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+        error: "Invalid file".to_string(),
+        msg: e
+      }))
+    }
+  };
+
+  if rows.is_empty() {
+    return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+      error: "Invalid file".to_string(),
+      msg: format!("Send between 1 and {} rows", MAX_IMPORT_ROWS)
+    }))
+  }
+
+  let state = match ItemState::load(data_path.as_str(), items) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error import_items 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let actor = actor(req);
+  let mut attempt = state.clone();
+  let (mut created, mut updated) = (0, 0);
+  let mut errors = Vec::new();
+  let mut warnings = Vec::new();
+  for row in rows.iter() {
+    if !row.ignored.is_empty() {
+      warnings.push(json!({
+        "row": row.line,
+        "msg": format!("Import doesn't set {}", row.ignored.join(", ")),
+        "ignored": row.ignored,
+      }));
+    }
+
+    let result = match &row.record {
+      Ok(record) => attempt.import(record, &config, &actor).map_err(|e| e.to_json()),
+      Err(msg) => Err(json!({
+        "status": StatusCode::BAD_REQUEST.as_u16(),
+        "error": "Invalid row",
+        "msg": msg,
+      })),
+    };
+    match result {
+      Ok((StatusCode::CREATED, _)) => created += 1,
+      Ok(_) => updated += 1,
+      Err(mut e) => {
+        e["row"] = json!(row.line);
+        errors.push(e);
+      }
+    }
+  }
+
+  let mode = params.mode.unwrap_or_default();
+  let status = match (errors.is_empty(), mode) {
+    (true, _) => StatusCode::OK,
+    (false, BulkMode::Atomic) => StatusCode::UNPROCESSABLE_ENTITY,
+    (false, BulkMode::BestEffort) => StatusCode::MULTI_STATUS,
+  };
+  let applied = !params.dry_run.unwrap_or(false)
+    && created + updated > 0
+    && (errors.is_empty() || mode == BulkMode::BestEffort);
+
+  if applied && attempt.save(data_path.as_str()).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error import_items 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  Ok(response_json(status, json!({
+    "dry_run": params.dry_run.unwrap_or(false),
+    "applied": applied,
+    "rows": rows.len(),
+    "created": created,
+    "updated": updated,
+    "errors": errors,
+    "warnings": warnings
+  })))
+}
+
+/// Items matching `q` ordered by relevance
+#[handler]
+async fn search_item(req: &Request, params: Query<SearchReq>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  if params.q.trim().is_empty() {
+    return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+      error: "Invalid query".to_string(),
+      msg: "q must not be empty".to_string()
+    }))
+  }
+
+  let inventory = match read_inventory(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error search_item 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let versions = match read_versions(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error search_item 2".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let ranked = search_items(data_path.as_str(), items, &versions, &params.q);
+  let limit = params.limit.unwrap_or(20).clamp(1, MAX_LIMIT);
+  let mut results: Vec<Value> = ranked
+    .iter()
+    .take(limit)
+    .filter_map(|(id, _score)| {
+      items.iter().find(|item| item.get("id").and_then(|i| i.as_u64()) == Some(*id))
+    })
+    .map(|item| {
+      let mut item = item.clone();
+      decorate_item(&mut item, items, &inventory);
+      item
+    })
+    .collect();
+  localize_items(req, params.lang.as_deref(), &mut results);
+
+  let mut resp = response_json(StatusCode::OK, &results);
+  resp.headers_mut().insert("X-Total-Count", ranked.len().into());
+  Ok(resp)
+}
+
+#[handler]
+async fn get_item(req: &Request, key: Path<String>, params: Query<ItemQuery>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+  let id = resolve_id(items, &key);
+
+  if let Some(as_of) = &params.as_of {
+    return get_item_as_of(items, id, as_of, data_path.as_str())
+  }
+
+  if let Some(expand) = &params.expand {
+    return get_item_expanded(req, id, expand, params.lang.as_deref(), data_path.as_str())
+  }
+
+  if let Some(currency) = &params.currency {
+    let region = params.region.as_deref().unwrap_or(GLOBAL_REGION).trim().to_lowercase();
+    return get_item_priced(req, id, currency, &region, params.lang.as_deref(), data_path.as_str(), &config)
+  }
+
+  for item in items.iter() {
+    let tmp_id = match item.get("id").and_then(|id| id.as_u64()) {
+      Some(res) => res,
+      None =>  {
+        return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+          error: "Server error get_item 3".to_string(),
+          msg: "Please contact support".to_string()
+        }))
+      }
+    };
+
+    if Some(tmp_id) == id {
+      let versions = match read_versions(data_path.as_str()) {
+        Ok(res) => res,
+        Err(_e) => {
+          return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+            error: "Server error get_item 4".to_string(),
+            msg: "Please contact support".to_string()
+          }))
+        }
+      };
+
+      let inventory = match read_inventory(data_path.as_str()) {
+        Ok(res) => res,
+        Err(_e) => {
+          return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+            error: "Server error get_item 5".to_string(),
+            msg: "Please contact support".to_string()
+          }))
+        }
+      };
+
+      let mut item = item.clone();
+      decorate_item(&mut item, items, &inventory);
+      return Ok(localized_item_response(req, item, params.lang.as_deref(), version_of(&versions, tmp_id)))
+    }
+  }
+
+  Err(error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+    error: "Not found".to_string(),
+    msg: "Item does not exist".to_string()
+  }))
+}
+
+/// The item with the items it links to by kind. The related items change
+/// without the item changing, so there is no ETag.
+fn get_item_expanded(req: &Request, id: Option<u64>, expand: &str, lang: Option<&str>, data_path: &str) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  if expand != "related" {
+    return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+      error: "Invalid query".to_string(),
+      msg: "expand can only be related".to_string()
+    }))
+  }
+
+  let (id, index) = match id.and_then(|id| find_item(items, &id.to_string())) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let (relations, inventory) = match (read_relations(data_path), read_inventory(data_path)) {
+    (Ok(relations), Ok(inventory)) => (relations, inventory),
+    _ => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_item 8".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let mut item = items[index].clone();
+  decorate_item(&mut item, items, &inventory);
+  localize_items(req, lang, std::slice::from_mut(&mut item));
+
+  let mut related: BTreeMap<&str, Vec<Value>> = BTreeMap::new();
+  for relation in relations.outgoing(id) {
+    if let Some((_id, index)) = find_item(items, &relation.to.to_string()) {
+      let mut related_item = items[index].clone();
+      decorate_item(&mut related_item, items, &inventory);
+      related_item["relation_id"] = json!(relation.id);
+      related.entry(relation.kind.as_str()).or_default().push(related_item);
+    }
+  }
+  for related_items in related.values_mut() {
+    localize_items(req, lang, related_items);
+  }
+  item["related"] = json!(related);
+  Ok(response_json(StatusCode::OK, item))
+}
+
+/// The item with its price in the currency. Prices change over time and with
+/// the exchange rates without the item changing, so there is no ETag.
+fn get_item_priced(req: &Request, id: Option<u64>, currency: &str, region: &str, lang: Option<&str>, data_path: &str, config: &Config) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let currency = match parse_currency(currency) {
+    Ok(res) => res,
+    Err(e) => {
+      return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+        error: "Invalid query".to_string(),
+        msg: e
+      }))
+    }
+  };
+
+  let mut item = match id.and_then(|id| find_item(items, &id.to_string())) {
+    Some((_id, index)) => items[index].clone(),
+    None => return Err(item_not_found())
+  };
+  let id = id.unwrap_or(0);
+
+  let (prices, rates, inventory) = match (read_prices(data_path), read_rates(data_path, &config.exchange_rates_path), read_inventory(data_path)) {
+    (Ok(prices), Ok(rates), Ok(inventory)) => (prices, rates, inventory),
+    _ => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_item 6".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let sales = match read_sales(data_path) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_item 7".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  decorate_item(&mut item, items, &inventory);
+  localize_items(req, lang, std::slice::from_mut(&mut item));
+  let entries = prices.get(&id).map(|e| e.as_slice()).unwrap_or_default();
+  let item_sales = sales.get(&id).map(|s| s.as_slice()).unwrap_or_default();
+  item["price"] = json!(resolve_price(entries, item_sales, region, &currency, &rates, Utc::now()));
+  Ok(response_json(StatusCode::OK, item))
+}
+
+fn get_item_as_of(items: &[Value], id: Option<u64>, as_of: &str, data_path: &str) -> Result<Response> {
+  let as_of = match DateTime::parse_from_rfc3339(as_of) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+        error: "Invalid query".to_string(),
+        msg: "as_of must be an RFC 3339 time".to_string()
+      }))
+    }
+  };
+
+  let history = match read_history(data_path) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_item 5".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let state = id.and_then(|id| {
+    let current = items.iter().find(|item| item.get("id").and_then(|i| i.as_u64()) == Some(id));
+    let revisions = history.get(&id).map(|r| r.as_slice()).unwrap_or(&[]);
+    state_as_of(revisions, current, as_of)
+  });
+
+  match state {
+    Some(state) => Ok(response_json(StatusCode::OK, state)),
+    None => Err(error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+      error: "Not found".to_string(),
+      msg: "Item did not exist at that time".to_string()
+    }))
+  }
+}
+
+#[handler]
+async fn put_item(req: &Request, key: Path<String>, item_req: Json<ItemReq>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  let changes = ItemPatchReq {
+    name: Some(item_req.name.clone()),
+    category_ids: item_req.category_ids.clone(),
+    attributes: item_req.attributes.clone(),
+    barcodes: item_req.barcodes.clone()
+  };
+  update_item(req, &key, changes, data_path.as_str(), &config, "put_item")
+}
+
+#[handler]
+async fn patch_item(req: &Request, key: Path<String>, item_req: Json<ItemPatchReq>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  update_item(req, &key, item_req.0, data_path.as_str(), &config, "patch_item")
+}
+
+/// Shared by PUT and PATCH, fields missing from `changes` are left as they are
+fn update_item(req: &Request, key: &str, changes: ItemPatchReq, data_path: &str, config: &Config, handler: &str) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap().clone();
+
+  let mut state = match ItemState::load(data_path, items) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: format!("Server error {} 8", handler),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let id = match state.find(key) {
+    Ok((id, _index)) => id,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+        error: format!("Server error {} 7", handler),
+        msg: "Item doesn't exist".to_string()
+      }))
+    }
+  };
+  let version = state.version(id);
+  let etag = item_etag(version);
+  if !if_match(req.headers(), &etag) {
+    return Err(precondition_failed())
+  }
+
+  let (_status, mut item) = state.update(&id.to_string(), &changes, None, config, &actor(req)).map_err(|e| e.into_error())?;
+  inherit_title(&mut item, state.items());
+  if state.version(id) == version {
+    // NOTE: ChatGPT said this should be StatusCode::NO_CONTENT or OK
+    //        I chose OK so that there is a response body
+    return Ok(with_etag(response_json(StatusCode::OK, &item), &etag))
+  }
+
+  if state.save(data_path).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: format!("Server error {} 6", handler),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  Ok(with_etag(response_json(StatusCode::OK, item), &item_etag(state.version(id))))
+}
+
+#[handler]
+async fn delete_item(req: &Request, key: Path<String>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap().clone();
+
+  let mut state = match ItemState::load(data_path.as_str(), items) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error delete_item 6".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let id = match state.find(&key) {
+    Ok((id, _index)) => id,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+        error: "Server error delete_item 5".to_string(),
+        msg: "Item doesn't exist".to_string()
+      }))
+    }
+  };
+  if !if_match(req.headers(), &item_etag(state.version(id))) {
+    return Err(precondition_failed())
+  }
+
+  state.delete(&id.to_string(), None, &actor(req)).map_err(|e| e.into_error())?;
+  if state.save(data_path.as_str()).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error delete_item 4".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  Ok(response_json(StatusCode::OK, ItemDeleted {
+    message: "Item deleted successfully".to_string()
+  }))
+}
+
+#[handler]
+async fn get_trash(data_path: Data<&String>) -> Result<Response> {
+  let trash = match read_trash(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_trash 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  Ok(response_json(StatusCode::OK, &trash))
+}
+
+#[handler]
+async fn restore_item(req: &Request, key: Path<String>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let mut items = req.extensions().get::<Vec<Value>>().unwrap().clone();
+
+  let mut trash = match read_trash(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error restore_item 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let found = resolve_id(&trash, &key).and_then(|id| {
+    trash
+      .iter()
+      .position(|t| t.get("id").and_then(|i| i.as_u64()) == Some(id))
+      .map(|index| (id, index))
+  });
+  let (id, index) = match found {
+    Some(res) => res,
+    None => {
+      return Err(error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+        error: "Not found".to_string(),
+        msg: "Item is not in the trash".to_string()
+      }))
+    }
+  };
+
+  let mut item = trash.remove(index);
+  if let Some(fields) = item.as_object_mut() {
+    fields.remove("deleted_at");
+    fields.remove("deleted_by");
+  }
+
+  // Another item may have taken one of its names in the meantime
+  check_returning_names(&items, id, &item, &config.name_policy)?;
+  // A variant needs its parent back first, another variant may have taken its SKU
+  if let Some(parent_id) = parent_of(&item) {
+    if find_item(&items, &parent_id.to_string()).is_none() {
+      return Err(error_response_json(StatusCode::CONFLICT, ConflictResponse {
+        error: "Parent item is gone".to_string(),
+        msg: format!("Please restore item {} first", parent_id),
+        id: parent_id
+      }))
+    }
+    let sku = item.get("sku").and_then(|s| s.as_str()).unwrap_or_default();
+    let options = item.get("options").and_then(|o| o.as_object()).cloned().unwrap_or_default();
+    check_variant_conflicts(&items, parent_id, Some(id), sku, &options)?;
+  }
+  let name = item.get("name").and_then(|n| n.as_str()).unwrap_or("");
+  // Slugs of trashed items are kept free, unless the data was written before
+  let slug = item.get("slug").and_then(|s| s.as_str()).unwrap_or("");
+  if !slug.is_empty() && slug_taken(&items, slug) {
+    item["slug"] = json!(new_slug(&items, &trash, name));
+  }
+  reclassify_returning(&mut item, data_path.as_str(), "restore_item")?;
+  check_barcodes(&items, Some(id), &barcodes_of(&item))?;
+  check_returning_components(&items, id, &item)?;
+
+  items.push(item.clone());
+  let new_items_str = match serde_json::to_string_pretty(&items) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error restore_item 2".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  if write(data_path.as_str(), new_items_str.as_bytes()).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error restore_item 3".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  if write_trash(data_path.as_str(), &trash).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error restore_item 4".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  let mut versions = match read_versions(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error restore_item 5".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let version = bump_version(&mut versions, id);
+  if write_versions(data_path.as_str(), &versions).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error restore_item 6".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  if record_revision(data_path.as_str(), id, version, "restore", None, Some(item.clone()), actor(req)).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error restore_item 7".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  Ok(with_etag(response_json(StatusCode::OK, &item), &item_etag(version)))
+}
+
+#[handler]
+async fn get_history(req: &Request, key: Path<String>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let history = match read_history(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_history 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  // Deleted items still have their history
+  let trash = match read_trash(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_history 2".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+  let id = resolve_id(items, &key).or_else(|| resolve_id(&trash, &key));
+  let revisions = id.and_then(|id| history.get(&id));
+  match revisions {
+    Some(revisions) => Ok(response_json(StatusCode::OK, revisions)),
+    None => Err(error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+      error: "Not found".to_string(),
+      msg: "Item has no history".to_string()
+    }))
+  }
+}
+
+/// Puts the item back to how it was after the given revision
+#[handler]
+async fn revert_item(req: &Request, key: Path<String>, revert_req: Json<RevertReq>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let mut items = req.extensions().get::<Vec<Value>>().unwrap().clone();
+
+  let found = resolve_id(&items, &key).and_then(|id| {
+    items
+      .iter()
+      .position(|item| item.get("id").and_then(|i| i.as_u64()) == Some(id))
+      .map(|index| (id, index))
+  });
+  let (id, index) = match found {
+    Some(res) => res,
+    None => {
+      return Err(error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+        error: "Not found".to_string(),
+        msg: "Item does not exist".to_string()
+      }))
+    }
+  };
+
+  let mut versions = match read_versions(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error revert_item 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  if !if_match(req.headers(), &item_etag(version_of(&versions, id))) {
+    return Err(precondition_failed())
+  }
+
+  let history = match read_history(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error revert_item 2".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let target = history
+    .get(&id)
+    .and_then(|revisions| revisions.iter().find(|r| r.revision == revert_req.revision && r.merged_from.is_none()))
+    .and_then(|revision| revision.after.clone());
+  let mut target = match target {
+    Some(res) => res,
+    None => {
+      return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+        error: "Invalid revision".to_string(),
+        msg: "The item has no state for this revision".to_string()
+      }))
+    }
+  };
+  target["id"] = json!(id);
+  keep_lifecycle(&mut target, &items[index]);
+  keep_media(&mut target, &items[index]);
+
+  check_returning_names(&items, id, &target, &config.name_policy)?;
+  // Another variant may have taken the SKU or the options in the meantime
+  if let (Some(parent_id), Some(sku)) = (parent_of(&target), target.get("sku").and_then(|s| s.as_str())) {
+    let options = target.get("options").and_then(|o| o.as_object()).cloned().unwrap_or_default();
+    check_variant_conflicts(&items, parent_id, Some(id), sku, &options)?;
+  }
+  reclassify_returning(&mut target, data_path.as_str(), "revert_item")?;
+  check_barcodes(&items, Some(id), &barcodes_of(&target))?;
+  check_returning_components(&items, id, &target)?;
+
+  let before = std::mem::replace(&mut items[index], target.clone());
+  let new_items_str = match serde_json::to_string_pretty(&items) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error revert_item 3".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  if write(data_path.as_str(), new_items_str.as_bytes()).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error revert_item 4".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  let version = bump_version(&mut versions, id);
+  if write_versions(data_path.as_str(), &versions).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error revert_item 5".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  let components_changed = components_of(&before) != components_of(&target);
+  if record_revision(data_path.as_str(), id, version, "revert", Some(before), Some(target.clone()), actor(req)).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error revert_item 6".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+  // Bundles holding this one now have a different availability
+  if components_changed {
+    for bundle_id in bundles_above(&items, id) {
+      bump_item_version(data_path.as_str(), bundle_id, "revert_item")?;
+    }
+  }
+
+  Ok(with_etag(response_json(StatusCode::OK, &target), &item_etag(version)))
+}
+
+/// The item a scanned EAN-13, JAN or UPC-A code belongs to
+#[handler]
+async fn get_item_by_barcode(req: &Request, code: Path<String>, params: Query<ItemQuery>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let barcode = match normalize_barcode(&code) {
+    Ok(res) => res,
+    Err(msg) => return Err(invalid_barcode(msg))
+  };
+  let index = match BarcodeIndex::build(items).find(&barcode).and_then(|id| find_item(items, &id.to_string())) {
+    Some((_id, index)) => index,
+    None => {
+      return Err(error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+        error: "Not found".to_string(),
+        msg: "No item has this barcode".to_string()
+      }))
+    }
+  };
+
+  let (versions, inventory) = match (read_versions(data_path.as_str()), read_inventory(data_path.as_str())) {
+    (Ok(versions), Ok(inventory)) => (versions, inventory),
+    _ => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_item_by_barcode 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let mut item = items[index].clone();
+  let version = version_of(&versions, item.get("id").and_then(|i| i.as_u64()).unwrap_or(0));
+  decorate_item(&mut item, items, &inventory);
+  Ok(localized_item_response(req, item, params.lang.as_deref(), version))
+}
+
+/// The item in the locale the request asks for. Translated items are tagged
+/// per representation, the text differs between locales at the same version.
+fn localized_item_response(req: &Request, mut item: Value, lang: Option<&str>, version: u64) -> Response {
+  let locale = localize_items(req, lang, std::slice::from_mut(&mut item));
+  let etag = match locale {
+    Some(_) => localized_etag(version, &item),
+    None => item_etag(version),
+  };
+  if if_none_match(req.headers(), &etag) {
+    return not_modified(&etag)
+  }
+
+  let mut resp = with_etag(response_json(StatusCode::OK, item), &etag);
+  resp.headers_mut().insert(header::VARY, HeaderValue::from_static("Accept-Language"));
+  if let Some(value) = locale.and_then(|l| HeaderValue::from_str(&l).ok()) {
+    resp.headers_mut().insert(header::CONTENT_LANGUAGE, value);
+  }
+  resp
+}
+
+/// Pairs of items that are likely the same product
+#[handler]
+async fn get_duplicates(req: &Request, params: Query<DuplicatesQuery>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let threshold = params.threshold.unwrap_or(DEFAULT_THRESHOLD);
+  if !(0.0..=1.0).contains(&threshold) {
+    return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+      error: "Invalid query".to_string(),
+      msg: "threshold must be between 0 and 1".to_string()
+    }))
+  }
+
+  Ok(response_json(StatusCode::OK, find_duplicates(items, threshold)))
+}
+
+/// Merges a duplicate into the item. The item gains what it lacks from the
+/// duplicate and takes over its stock, prices, sales, links, variants and
+/// place in bundles. The duplicate is removed and its id redirects to the
+/// item, its history and transitions move to the item. Every file is written
+/// in one batch, a failure leaves all of them as they were.
+#[handler]
+async fn merge_item(req: &Request, key: Path<String>, merge_req: Json<MergeReq>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let mut items = req.extensions().get::<Vec<Value>>().unwrap().clone();
+  let server_error = |n: u8| error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+    error: format!("Server error merge_item {}", n),
+    msg: "Please contact support".to_string()
+  });
+  let cant_merge = |msg: &str| error_response_json(StatusCode::CONFLICT, ErrorResponse {
+    error: "Items can't be merged".to_string(),
+    msg: msg.to_string()
+  });
+
+  let (id, index) = match find_item(&items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+  let from = merge_req.item_id;
+  let from_index = match find_item(&items, &from.to_string()) {
+    Some((_id, index)) if from != id => index,
+    found => {
+      return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+        error: "Invalid merge".to_string(),
+        msg: match found {
+          Some(_) => "An item can't be merged into itself".to_string(),
+          None => format!("Item {} does not exist", from)
+        }
+      }))
+    }
+  };
+
+  let mut versions = match read_versions(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => return Err(server_error(1))
+  };
+  if !if_match(req.headers(), &item_etag(version_of(&versions, id))) {
+    return Err(precondition_failed())
+  }
+
+  if parent_of(&items[index]).is_some() || parent_of(&items[from_index]).is_some() {
+    return Err(cant_merge("Variants can't be merged, please merge their parents"))
+  }
+  if bundles_above(&items, from).contains(&id) || bundles_above(&items, id).contains(&from) {
+    return Err(cant_merge("One item is in a bundle of the other"))
+  }
+  let clashing_variants: Vec<u64> = variants_of(&items, from)
+    .filter(|variant| {
+      let options = variant.get("options").and_then(|o| o.as_object()).cloned().unwrap_or_default();
+      options_owner(&items, id, &options).is_some()
+    })
+    .filter_map(|variant| variant.get("id").and_then(|i| i.as_u64()))
+    .collect();
+  if !clashing_variants.is_empty() {
+    return Err(error_response_json(StatusCode::CONFLICT, json!({
+      "error": "Items can't be merged",
+      "msg": "Variants of both items have the same options",
+      "variant_ids": clashing_variants
+    })))
+  }
+
+  let categories = match read_categories(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => return Err(server_error(2))
+  };
+
+  let before = items[index].clone();
+  let mut after = before.clone();
+  merge_fields(&mut after, &items[from_index]);
+  let tags = tags_of(&after);
+  set_tags(&mut after, tags);
+  if after.get("category_ids").is_some() {
+    let mut category_ids = category_ids_of(&after);
+    category_ids.sort();
+    after["category_ids"] = json!(category_ids);
+  }
+  let from_components = components_of(&items[from_index]);
+  if after.get("components").is_none() && !from_components.is_empty() {
+    set_components(&mut after, &from_components);
+  }
+  if let Err(errors) = check_item_attributes(&categories, &after) {
+    return Err(invalid_attributes(errors))
+  }
+  if barcodes_of(&after).len() > MAX_BARCODES {
+    return Err(cant_merge(&format!("Together the items have more than {} barcodes", MAX_BARCODES)))
+  }
+  if tags_of(&after).len() > MAX_TAGS {
+    return Err(cant_merge(&format!("Together the items have more than {} tags", MAX_TAGS)))
+  }
+
+  // Variants of the duplicate and bundles holding it now point at the item
+  let mut changed = Vec::new();
+  for other in items.iter_mut() {
+    let other_id = match other.get("id").and_then(|i| i.as_u64()) {
+      Some(res) if res != id && res != from => res,
+      _ => continue
+    };
+    let other_before = other.clone();
+    if parent_of(other) == Some(from) {
+      other["parent_id"] = json!(id);
+    }
+    let components = components_of(other);
+    if components.iter().any(|c| c.item_id == from) {
+      let components: Vec<Component> = components
+        .into_iter()
+        .map(|c| Component { item_id: if c.item_id == from { id } else { c.item_id }, quantity: c.quantity })
+        .collect();
+      match combine_components(&components) {
+        Ok(components) => set_components(other, &components),
+        Err(msg) => return Err(cant_merge(&format!("Bundle {} can't take both items, {}", other_id, msg)))
+      }
+    }
+    if *other != other_before {
+      changed.push((other_id, other_before, other.clone()));
+    }
+  }
+  items[index] = after.clone();
+  let duplicate = items.remove(from_index);
+
+  let (mut history, mut sequence, mut transitions, mut inventory, mut prices, mut sales, mut relations, mut redirects) = match (
+    read_history(data_path.as_str()),
+    read_sequence(data_path.as_str()),
+    read_transitions(data_path.as_str()),
+    read_inventory(data_path.as_str()),
+    read_prices(data_path.as_str()),
+    read_sales(data_path.as_str()),
+    read_relations(data_path.as_str()),
+    read_redirects(data_path.as_str()),
+  ) {
+    (Ok(history), Ok(sequence), Ok(transitions), Ok(inventory), Ok(prices), Ok(sales), Ok(relations), Ok(redirects)) => {
+      (history, sequence, transitions, inventory, prices, sales, relations, redirects)
+    }
+    _ => return Err(server_error(3))
+  };
+
+  if let Err(msg) = inventory.merge_item(from, id, actor(req)) {
+    return Err(cant_merge(&format!("Item {} can't take the stock of item {}, {}", id, from, msg)))
+  }
+  move_entries(&mut prices, from, id, |a, b| a.region == b.region && a.currency == b.currency);
+  move_entries(&mut sales, from, id, |a, b| a.region == b.region);
+  relations.merge_item(from, id);
+  add_redirect(&mut redirects, from, id);
+  // Like a deleted item's, the duplicate's id is never given again
+  retire_id(&mut sequence, from);
+
+  // The duplicate's history ends with the merge and moves to the item
+  let from_version = bump_version(&mut versions, from);
+  push_revision(&mut history, from, from_version, "merge", Some(duplicate), None, actor(req));
+  move_history(&mut history, from, id);
+  move_transitions(&mut transitions, from, id);
+  let version = bump_version(&mut versions, id);
+  push_revision(&mut history, id, version, "merge", Some(before), Some(after.clone()), actor(req));
+  for (other_id, other_before, other_after) in changed {
+    let other_version = bump_version(&mut versions, other_id);
+    push_revision(&mut history, other_id, other_version, "merge", Some(other_before), Some(other_after), actor(req));
+  }
+  // Availability of the bundles above follows the stock that moved
+  for bundle_id in bundles_above(&items, id) {
+    bump_version(&mut versions, bundle_id);
+  }
+
+  let saved = (|| {
+    let mut batch = FileBatch::default();
+    batch.add(data_path.as_str(), &items)?;
+    batch.add(&sidecar_path(data_path.as_str(), "versions"), &versions)?;
+    batch.add(&sidecar_path(data_path.as_str(), "history"), &history)?;
+    batch.add(&sidecar_path(data_path.as_str(), "sequence"), &sequence)?;
+    batch.add(&sidecar_path(data_path.as_str(), "transitions"), &transitions)?;
+    batch.add(&sidecar_path(data_path.as_str(), "inventory"), &inventory)?;
+    batch.add(&sidecar_path(data_path.as_str(), "prices"), &prices)?;
+    batch.add(&sidecar_path(data_path.as_str(), "sales"), &sales)?;
+    batch.add(&sidecar_path(data_path.as_str(), "relations"), &relations)?;
+    batch.add(&sidecar_path(data_path.as_str(), "redirects"), &redirects)?;
+    batch.commit()
+  })();
+  if saved.is_err() {
+    return Err(server_error(4))
+  }
+
+  Ok(with_etag(response_json(StatusCode::OK, &after), &item_etag(version)))
+}
+
+/// Puts the item in the given categories, replacing the ones it was in
+#[handler]
+async fn put_item_categories(req: &Request, key: Path<String>, categories_req: Json<ItemCategoriesReq>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let mut items = req.extensions().get::<Vec<Value>>().unwrap().clone();
+
+  let (id, index) = match find_item(&items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let versions = match read_versions(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error put_item_categories 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let etag = item_etag(version_of(&versions, id));
+  if !if_match(req.headers(), &etag) {
+    return Err(precondition_failed())
+  }
+
+  let categories = match read_categories(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error put_item_categories 2".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let mut category_ids = categories_req.category_ids.clone();
+  category_ids.sort();
+  category_ids.dedup();
+  if let Some(missing) = category_ids.iter().find(|c| categories.get(**c).is_none()) {
+    return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+      error: "Invalid category".to_string(),
+      msg: format!("Category {} does not exist", missing)
+    }))
+  }
+
+  if category_ids_of(&items[index]) == category_ids {
+    return Ok(with_etag(response_json(StatusCode::OK, &items[index]), &etag))
+  }
+
+  let before = items[index].clone();
+  match (category_ids.is_empty(), items[index].as_object_mut()) {
+    (true, Some(fields)) => {
+      fields.remove("category_ids");
+    }
+    (false, Some(fields)) => {
+      fields.insert("category_ids".to_string(), json!(category_ids));
+    }
+    _ => {}
+  }
+  if let Err(errors) = check_item_attributes(&categories, &items[index]) {
+    return Err(invalid_attributes(errors))
+  }
+  let after = items[index].clone();
+
+  let version = save_item_change(req, &items, Some(before), &after, "update", data_path.as_str(), "put_item_categories")?;
+  Ok(with_etag(response_json(StatusCode::OK, &after), &item_etag(version)))
+}
+
+/// Makes the item a bundle of the given items, replacing its components
+#[handler]
+async fn put_item_components(req: &Request, key: Path<String>, components_req: Json<ItemComponentsReq>, data_path: Data<&String>) -> Result<Response> {
   // Error handling on this already in AuthMiddleware
   let mut items = req.extensions().get::<Vec<Value>>().unwrap().clone();
 
-  if !contains_item(&items, &item_req.name) {
-    let item = Item {
-      id: get_new_last_id(&items),
-      name: item_req.name.to_string()
-    };
+  let (id, index) = match find_item(&items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
 
-    items.push(json!(item));
-    let new_items_str = match serde_json::to_string_pretty(&items) {
-      Ok(res) => res,
-      Err(_e) => {
-        return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
-          error: "Server error post_item 4".to_string(),
-          msg: "Please contact support".to_string()
-        }))
-      }
-    };
+  let versions = match read_versions(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error put_item_components 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
 
-    match write(data_path.as_str(), new_items_str.as_bytes()) {
-      Ok(res) => res,
-      Err(_e) => {
-        return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
-          error: "Server error post_item 5".to_string(),
-          msg: "Please contact support".to_string()
-        }))
-      }
+  let etag = item_etag(version_of(&versions, id));
+  if !if_match(req.headers(), &etag) {
+    return Err(precondition_failed())
+  }
+
+  let components = match check_components(&items, id, &components_req.components) {
+    Ok(res) => res,
+    Err(msg) => {
+      return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+        error: "Invalid components".to_string(),
+        msg
+      }))
+    }
+  };
+
+  let inventory = match read_inventory(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error put_item_components 2".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let before = items[index].clone();
+  set_components(&mut items[index], &components);
+  let mut after = items[index].clone();
+  if after == before {
+    decorate_item(&mut after, &items, &inventory);
+    return Ok(with_etag(response_json(StatusCode::OK, &after), &etag))
+  }
+
+  let version = save_item_change(req, &items, Some(before), &after, "update", data_path.as_str(), "put_item_components")?;
+  // Bundles holding this one now have a different availability
+  for bundle_id in bundles_above(&items, id) {
+    bump_item_version(data_path.as_str(), bundle_id, "put_item_components")?;
+  }
+  decorate_item(&mut after, &items, &inventory);
+  Ok(with_etag(response_json(StatusCode::OK, &after), &item_etag(version)))
+}
+
+/// Checks an item coming back from the trash or the history against the
+/// categories as they are now. Categories deleted in the meantime are dropped
+/// from it, the attributes have to match the schema of the ones left.
+fn reclassify_returning(item: &mut Value, data_path: &str, handler: &str) -> Result<()> {
+  let categories = match read_categories(data_path) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: format!("Server error {} categories 1", handler),
+        msg: "Please contact support".to_string()
+      }))
     }
+  };
+
+  let category_ids: Vec<u64> = category_ids_of(item).into_iter().filter(|c| categories.get(*c).is_some()).collect();
+  if let Some(fields) = item.as_object_mut() {
+    match category_ids.is_empty() {
+      true => fields.remove("category_ids"),
+      false => fields.insert("category_ids".to_string(), json!(category_ids)),
+    };
+  }
+
+  match check_item_attributes(&categories, item) {
+    Ok(()) => Ok(()),
+    Err(errors) => Err(error_response_json(StatusCode::CONFLICT, json!({
+      "error": "Invalid attributes",
+      "msg": "The attributes don't match the schema of the item's categories anymore",
+      "errors": errors
+    })))
+  }
+}
+
+/// Checks the components of a bundle coming back from the trash or the
+/// history, items in it may be gone or may contain it by now
+fn check_returning_components(items: &[Value], id: u64, item: &Value) -> Result<()> {
+  let components = components_of(item);
+  if components.is_empty() {
+    return Ok(())
+  }
 
-    return Ok(response_json(StatusCode::CREATED, &item));
+  match check_components(items, id, &components) {
+    Ok(_components) => Ok(()),
+    Err(msg) => Err(error_response_json(StatusCode::CONFLICT, ErrorResponse {
+      error: "Invalid components".to_string(),
+      msg
+    }))
   }
-  
-  Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
-    error: "Server error post_item 6".to_string(),
-    msg: "Item already exists".to_string()
+}
+
+/// Normalizes the barcodes of the item with id `id` and checks that no other
+/// item has them
+fn check_barcodes(items: &[Value], id: Option<u64>, codes: &[String]) -> Result<Vec<String>> {
+  state::check_barcodes(items, id, codes).map_err(|e| e.into_error())
+}
+
+fn invalid_barcode(msg: String) -> poem::Error {
+  error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+    error: "Invalid barcode".to_string(),
+    msg
+  })
+}
+
+fn invalid_attributes(errors: Vec<String>) -> poem::Error {
+  error_response_json(StatusCode::BAD_REQUEST, json!({
+    "error": "Invalid attributes",
+    "msg": "The attributes don't match the schema of the item's categories",
+    "errors": errors
   }))
 }
 
+/// `sub` of the JWT, set by AuthMiddleware for requests that needed a token
+fn actor(req: &Request) -> Option<String> {
+  req.extensions().get::<Claims>().map(|claims| claims.sub.clone())
+}
+
+/// Checks the names in every locale of an item coming back from the trash or
+/// the history against those of the other items
+fn check_returning_names(items: &[Value], id: u64, item: &Value, policy: &NamePolicy) -> Result<()> {
+  let name_index = NameIndex::build(items, policy);
+  for name in item_names(item) {
+    match name_index.find(name) {
+      Some(existing_id) if existing_id != id => return Err(name_conflict(existing_id)),
+      _ => {}
+    }
+  }
+  Ok(())
+}
+
+fn name_conflict(existing_id: u64) -> poem::Error {
+  error_response_json(StatusCode::CONFLICT, ConflictResponse {
+    error: "Item already exists".to_string(),
+    msg: "Please use a different name".to_string(),
+    id: existing_id
+  })
+}
+
+fn precondition_failed() -> poem::Error {
+  error_response_json(StatusCode::PRECONDITION_FAILED, ErrorResponse {
+    error: "Precondition failed".to_string(),
+    msg: "Item was modified by someone else, please reload it".to_string()
+  })
+}
+
+/// Every tag in use with how many items carry it
 #[handler]
-async fn get_items(req: &Request) -> Result<Response> {
+async fn get_tags(req: &Request) -> Result<Response> {
   // Error handling on this already in AuthMiddleware
-  let mut items = req.extensions().get::<Vec<Value>>().unwrap().clone();
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  Ok(response_json(StatusCode::OK, tag_counts(items)))
+}
 
-  Ok(response_json(StatusCode::OK, &items))
+/// Replaces the tags of the item
+#[handler]
+async fn put_item_tags(req: &Request, key: Path<String>, tags_req: Json<ItemTagsReq>, data_path: Data<&String>) -> Result<Response> {
+  let tags = normalize_tags(&tags_req.tags)?;
+  change_tags(req, &key, data_path.as_str(), "put_item_tags", |_current| tags)
+}
+
+/// Adds tags to the ones the item already has
+#[handler]
+async fn post_item_tags(req: &Request, key: Path<String>, tags_req: Json<ItemTagsReq>, data_path: Data<&String>) -> Result<Response> {
+  let tags = normalize_tags(&tags_req.tags)?;
+  change_tags(req, &key, data_path.as_str(), "post_item_tags", |current| [current, tags].concat())
 }
 
 #[handler]
-async fn get_item(req: &Request, id: Path<u64>) -> Result<Response> {
+async fn delete_item_tag(req: &Request, Path((key, tag)): Path<(String, String)>, data_path: Data<&String>) -> Result<Response> {
+  let tag = normalize_tags(&[tag])?.remove(0);
+  change_tags(req, &key, data_path.as_str(), "delete_item_tag", |current| {
+    current.into_iter().filter(|t| *t != tag).collect()
+  })
+}
+
+fn normalize_tags(tags: &[String]) -> Result<Vec<String>> {
+  tags
+    .iter()
+    .map(|tag| normalize_tag(tag))
+    .collect::<std::result::Result<Vec<String>, String>>()
+    .map_err(|e| error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+      error: "Invalid tag".to_string(),
+      msg: e
+    }))
+}
+
+/// Sets the tags of the item to what `change` makes of its current tags
+fn change_tags(req: &Request, key: &str, data_path: &str, handler: &str, change: impl FnOnce(Vec<String>) -> Vec<String>) -> Result<Response> {
   // Error handling on this already in AuthMiddleware
   let mut items = req.extensions().get::<Vec<Value>>().unwrap().clone();
 
-  for item in items.iter() {
-    let tmp_id = match item.get("id").and_then(|id| id.as_u64()) {
-      Some(res) => res,
-      None =>  {
-        return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
-          error: "Server error get_item 3".to_string(),
-          msg: "Please contact support".to_string()
-        }))
-      }
-    };
+  let (id, index) = match find_item(&items, key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
 
-    if tmp_id == *id {
-      return Ok(response_json(StatusCode::OK, item))
+  let versions = match read_versions(data_path) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: format!("Server error {} 1", handler),
+        msg: "Please contact support".to_string()
+      }))
     }
+  };
+
+  let etag = item_etag(version_of(&versions, id));
+  if !if_match(req.headers(), &etag) {
+    return Err(precondition_failed())
   }
 
-  Err(error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
-    error: "Not found".to_string(),
-    msg: "Item does not exist".to_string()
-  }))
+  let before = items[index].clone();
+  set_tags(&mut items[index], change(tags_of(&before)));
+  if tags_of(&items[index]).len() > MAX_TAGS {
+    return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+      error: "Invalid tag".to_string(),
+      msg: format!("Items can have at most {} tags", MAX_TAGS)
+    }))
+  }
+
+  if items[index] == before {
+    return Ok(with_etag(response_json(StatusCode::OK, &before), &etag))
+  }
+
+  let after = items[index].clone();
+  let version = save_item_change(req, &items, Some(before), &after, "update", data_path, handler)?;
+  Ok(with_etag(response_json(StatusCode::OK, &after), &item_etag(version)))
+}
+
+/// Shows the text of the items in the locale the client asked for through
+/// `lang` or `Accept-Language`, returns the locale of the first item's name
+fn localize_items(req: &Request, lang: Option<&str>, items: &mut [Value]) -> Option<String> {
+  let accept_language = req.headers().get(header::ACCEPT_LANGUAGE).and_then(|h| h.to_str().ok());
+  let default_locale = req.data::<Config>().map(|c| c.default_locale.clone()).unwrap_or_default();
+  let chain = locale_chain(lang, accept_language, &default_locale);
+
+  let mut locales: Vec<Option<String>> = items.iter_mut().map(|item| localize(item, &chain, &default_locale)).collect();
+  match locales.is_empty() {
+    true => None,
+    false => locales.swap_remove(0),
+  }
 }
 
+/// Sets the name and description of the item in a locale, for the default
+/// locale these are the item's own fields
 #[handler]
-async fn put_item(req: &Request, id: Path<u64>, item_req: Json<ItemReq>, data_path: Data<&String>) -> Result<Response> {
+async fn put_item_translation(
+  req: &Request,
+  Path((key, locale)): Path<(String, String)>,
+  translation_req: Json<TranslationReq>,
+  data_path: Data<&String>,
+  config: Data<&Config>
+) -> Result<Response> {
   // Error handling on this already in AuthMiddleware
   let mut items = req.extensions().get::<Vec<Value>>().unwrap().clone();
-  
-  let mut updated_item_op = None;
-  for index in 0..items.iter().len() {
-    let mut item = match items.get_mut(index) {
-      Some(res) => res,
-      None => {
-        return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
-          error: "Server error put_item 1".to_string(),
-          msg: "Please contact support".to_string()
-        }))
-      }
-    };
-
-    let tmp_id = match item.get("id").and_then(|id| id.as_u64()) {
-      Some(res) => res,
-      None =>  {
-        return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
-          error: "Server error put_item 2".to_string(),
-          msg: "Please contact support".to_string()
-        }))
-      }
-    };
 
-    if tmp_id == *id {
-      let mut update_name = match item.get_mut("name") {
-        Some(res) => res,
-        None => {
-          return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
-            error: "Server error put_item 3".to_string(),
-            msg: "Please contact support".to_string()
-          }))
-        }
-      };
+  let (id, index) = match find_item(&items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+  let locale = match normalize_locale(&locale) {
+    Some(res) => res,
+    None => return Err(invalid_locale())
+  };
 
-      let name: &str = match update_name.as_str() {
-        Some(res) => res,
-        None => {
-          return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
-            error: "Server error put_item 4".to_string(),
-            msg: "Please contact support".to_string()
-          }))
-        }
-      };
+  let versions = match read_versions(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error put_item_translation 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
 
-      if name == item_req.name {
-        // NOTE: ChatGPT said this should be StatusCode::NO_CONTENT or OK
-        //        I chose OK so that there is a response body
-        return Ok(response_json(StatusCode::OK, item))
-      } else {
-        *update_name = json!(item_req.name);
-        updated_item_op = Some(item.clone());
-      }
+  let etag = item_etag(version_of(&versions, id));
+  if !if_match(req.headers(), &etag) {
+    return Err(precondition_failed())
+  }
 
-      
-      break;
+  // Names are unique across every locale
+  if let Some(name) = &translation_req.name {
+    match NameIndex::build(&items, &config.name_policy).find(name) {
+      Some(existing_id) if existing_id != id => return Err(name_conflict(existing_id)),
+      _ => {}
     }
   }
 
-  if updated_item_op.is_some() {
-    let new_items_str = match serde_json::to_string_pretty(&items) {
-      Ok(res) => res,
-      Err(_e) => {
-        return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
-          error: "Server error put_item 5".to_string(),
-          msg: "Please contact support".to_string()
-        }))
+  let before = items[index].clone();
+  let is_default = normalize_locale(&config.default_locale).as_deref() == Some(locale.as_str());
+  let texts = [("name", &translation_req.name), ("description", &translation_req.description)];
+  if is_default {
+    for (field, text) in texts {
+      if let Some(text) = text {
+        items[index][field] = json!(text);
       }
-    };
-
-    match write(data_path.as_str(), new_items_str.as_bytes()) {
-      Ok(res) => res,
-      Err(_e) => {
-        return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
-          error: "Server error put_item 6".to_string(),
-          msg: "Please contact support".to_string()
-        }))
+    }
+  } else {
+    let mut translation = translation_of(&items[index], &locale);
+    for (field, text) in texts {
+      if let Some(text) = text {
+        translation.insert(field.to_string(), json!(text));
       }
     }
+    set_translation(&mut items[index], &locale, translation);
+  }
 
-    let newly_updated = updated_item_op.unwrap();
-    return Ok(response_json(StatusCode::OK, newly_updated))
+  if items[index] == before {
+    return Ok(with_etag(response_json(StatusCode::OK, &before), &etag))
   }
-  
-  Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
-    error: "Server error put_item 7".to_string(),
-    msg: "Item doesn't exist".to_string()
-  }))
+
+  let after = items[index].clone();
+  let version = save_item_change(req, &items, Some(before), &after, "update", data_path.as_str(), "put_item_translation")?;
+  Ok(with_etag(response_json(StatusCode::OK, &after), &item_etag(version)))
 }
 
 #[handler]
-async fn delete_item(req: &Request, id: Path<u64>, data_path: Data<&String>) -> Result<Response> {
+async fn delete_item_translation(req: &Request, Path((key, locale)): Path<(String, String)>, data_path: Data<&String>) -> Result<Response> {
   // Error handling on this already in AuthMiddleware
   let mut items = req.extensions().get::<Vec<Value>>().unwrap().clone();
-  
-  let mut has_deleted = false;
-  for index in 0..items.iter().len() {
-    let item = match items.get(index) {
-      Some(res) => res,
-      None => {
-        return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
-          error: "Server error delete_item 1".to_string(),
-          msg: "Please contact support".to_string()
-        }))
-      }
-    };
 
-    let tmp_id = match item.get("id").and_then(|id| id.as_u64()) {
-      Some(res) => res,
-      None =>  {
-        return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
-          error: "Server error delete_item 2".to_string(),
-          msg: "Please contact support".to_string()
-        }))
-      }
-    };
+  let (id, index) = match find_item(&items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+  let locale = match normalize_locale(&locale) {
+    Some(res) => res,
+    None => return Err(invalid_locale())
+  };
 
-    if tmp_id == *id {
-      items.remove(index);
-      has_deleted = true;
-      break;
+  let versions = match read_versions(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error delete_item_translation 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
     }
-  }
-
-  if has_deleted {
-    let new_items_str = match serde_json::to_string_pretty(&items) {
-      Ok(res) => res,
-      Err(_e) => {
-        return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
-          error: "Server error delete_item 3".to_string(),
-          msg: "Please contact support".to_string()
-        }))
-      }
-    };
+  };
 
-    match write(data_path.as_str(), new_items_str.as_bytes()) {
-      Ok(res) => res,
-      Err(_e) => {
-        return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
-          error: "Server error delete_item 4".to_string(),
-          msg: "Please contact support".to_string()
-        }))
-      }
-    }
+  if !if_match(req.headers(), &item_etag(version_of(&versions, id))) {
+    return Err(precondition_failed())
+  }
 
-    return Ok(response_json(StatusCode::OK, ItemDeleted {
-      message: "Item deleted successfully".to_string()
+  if translation_of(&items[index], &locale).is_empty() {
+    return Err(error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+      error: "Not found".to_string(),
+      msg: "Item has no translation for this locale".to_string()
     }))
   }
-  
-  Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
-    error: "Server error delete_item 5".to_string(),
-    msg: "Item doesn't exist".to_string()
-  }))
+
+  let before = items[index].clone();
+  set_translation(&mut items[index], &locale, Default::default());
+  let after = items[index].clone();
+  let version = save_item_change(req, &items, Some(before), &after, "update", data_path.as_str(), "delete_item_translation")?;
+  Ok(with_etag(response_json(StatusCode::OK, &after), &item_etag(version)))
 }
 
+fn invalid_locale() -> poem::Error {
+  error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+    error: "Invalid locale".to_string(),
+    msg: "Use a language tag like en, ja or zh-Hant".to_string()
+  })
+}
 
+/// Position of the item addressed by `key` together with its id
+fn find_item(items: &[Value], key: &str) -> Option<(u64, usize)> {
+  resolve_id(items, key).and_then(|id| {
+    items
+      .iter()
+      .position(|item| item.get("id").and_then(|i| i.as_u64()) == Some(id))
+      .map(|index| (id, index))
+  })
+}
 
+fn item_not_found() -> poem::Error {
+  error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+    error: "Not found".to_string(),
+    msg: "Item does not exist".to_string()
+  })
+}
 
-fn contains_item(items: &Vec<Value>, name: &str) -> bool {
-  for item in items.iter() {
-    let n = match item.get("name").and_then(|n| n.as_str()) {
-      Some(res) => res,
-      None => return false,
-    };
+/// Bumps the version of an item whose response changed without the item
+/// itself changing, e.g. its stock, so cached copies are not served
+fn bump_item_version(data_path: &str, id: u64, handler: &str) -> Result<u64> {
+  let server_error = |n: u8| error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+    error: format!("Server error {} version {}", handler, n),
+    msg: "Please contact support".to_string()
+  });
 
-    if n == name {
-      return true
-    }
-    // println!("item {:?}", name);
+  let mut versions = match read_versions(data_path) {
+    Ok(res) => res,
+    Err(_e) => return Err(server_error(1))
+  };
+  let version = bump_version(&mut versions, id);
+  if write_versions(data_path, &versions).is_err() {
+    return Err(server_error(2))
   }
-  return false;
+  Ok(version)
 }
 
-fn get_new_last_id(data: &Vec<Value>) -> u64 {
-  let mut highest_id = 1;
-  for data in data.iter() {
-    let n = match data.get("id").and_then(|n| n.as_u64()) {
-      Some(res) => res,
-      None => 1,
-    };
+/// Adds what responses show besides the stored item: the stock, the title a
+/// variant shares with its parent and the availability of a bundle
+fn decorate_item(item: &mut Value, items: &[Value], inventory: &Inventory) {
+  inventory.decorate(item);
+  inherit_title(item, items);
+  decorate_bundle(item, items, inventory);
+}
 
-    if n >= highest_id {
-      highest_id = n + 1;
-    }
+/// Stores an item that was created or changed in place: writes the items,
+/// bumps the version and records the revision.
+/// Returns the new version.
+fn save_item_change(
+  req: &Request,
+  items: &[Value],
+  before: Option<Value>,
+  after: &Value,
+  action: &str,
+  data_path: &str,
+  handler: &str
+) -> Result<u64> {
+  let server_error = |n: u8| error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+    error: format!("Server error {} save {}", handler, n),
+    msg: "Please contact support".to_string()
+  });
+  let id = after.get("id").and_then(|i| i.as_u64()).unwrap_or(0);
+
+  if write_json(data_path, &items).is_err() {
+    return Err(server_error(1))
   }
-  highest_id
-}
 
+  let mut versions = match read_versions(data_path) {
+    Ok(res) => res,
+    Err(_e) => return Err(server_error(2))
+  };
+  let version = bump_version(&mut versions, id);
+  if write_versions(data_path, &versions).is_err() {
+    return Err(server_error(3))
+  }
+
+  if record_revision(data_path, id, version, action, before, Some(after.clone()), actor(req)).is_err() {
+    return Err(server_error(4))
+  }
+  Ok(version)
+}
 
 #[derive(Serialize, Deserialize, Debug)]
-struct ItemReq {
+pub(crate) struct ItemReq {
   name: String,
+  category_ids: Option<Vec<u64>>,
+  /// Checked against the attribute schema of the item's categories
+  attributes: Option<Map<String, Value>>,
+  /// Status of a new item, later changes go through `PUT /items/:id/status`
+  status: Option<ItemStatus>,
+  /// EAN-13, JAN or UPC-A codes, no two items share one
+  barcodes: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SearchReq {
+  q: String,
+  limit: Option<usize>,
+  lang: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ExportQuery {
+  format: Option<Format>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ImportQuery {
+  format: Option<Format>,
+  /// Columns to item fields, e.g. `Title:name,Item ID:id`
+  map: Option<String>,
+  dry_run: Option<bool>,
+  mode: Option<BulkMode>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ItemQuery {
+  /// RFC 3339 time to view the item as it was back then
+  as_of: Option<String>,
+  /// Adds the price in this currency
+  currency: Option<String>,
+  /// Region of the price, `global` by default
+  region: Option<String>,
+  /// Locales to show the text in, takes precedence over `Accept-Language`
+  lang: Option<String>,
+  /// `related` adds the items the item links to
+  expand: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct DuplicatesQuery {
+  /// Lowest name similarity from 0 to 1
+  threshold: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct MergeReq {
+  /// Duplicate merged into the item, it no longer exists afterwards
+  item_id: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ItemComponentsReq {
+  /// Empty turns the bundle back into a plain item
+  components: Vec<Component>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TranslationReq {
+  name: Option<String>,
+  description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RevertReq {
+  revision: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ItemTagsReq {
+  tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ItemCategoriesReq {
+  category_ids: Vec<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct ItemPatchReq {
+  name: Option<String>,
+  category_ids: Option<Vec<u64>>,
+  /// Replaces all attributes of the item
+  attributes: Option<Map<String, Value>>,
+  /// Replaces all barcodes of the item
+  barcodes: Option<Vec<String>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -313,15 +2079,30 @@ struct ItemDeleted {
   message: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+struct ConflictResponse {
+  error: String,
+  msg: String,
+  /// Item that already uses the name or SKU
+  id: u64,
+}
 
-
-struct AuthMiddleware {
-  data_path: Arc<String>
+/// Loads the items into the request extensions and checks the JWT of
+/// requests that change data, also used by the routes that depend on items
+pub(crate) struct AuthMiddleware {
+  data_path: Arc<String>,
+  hold_lock: bool
 }
 
 impl AuthMiddleware {
   pub fn new(data_path: Arc<String>) -> Self {
-    Self { data_path }
+    Self { data_path, hold_lock: true }
+  }
+
+  /// Releases the lock once the items are loaded, for handlers that receive
+  /// large bodies. They take the lock themselves for the write.
+  pub fn without_lock(data_path: Arc<String>) -> Self {
+    Self { data_path, hold_lock: false }
   }
 }
 
@@ -331,111 +2112,115 @@ impl<E: Endpoint> Middleware<E> for AuthMiddleware {
   fn transform(&self, ep: E) -> Self::Output {
     AuthMiddlewareImpl {
       inner: ep,
-      data_path: self.data_path.clone()
+      data_path: self.data_path.clone(),
+      hold_lock: self.hold_lock
     }
   }
 }
 
-struct AuthMiddlewareImpl<E> {
+pub(crate) struct AuthMiddlewareImpl<E> {
   inner: E,
   data_path: Arc<String>,
+  hold_lock: bool,
 }
 
 impl<E: Endpoint> Endpoint for AuthMiddlewareImpl<E> {
   type Output = Response;
 
   async fn call(&self, mut req: Request) -> Result<Self::Output> {
-    if let Err(_) = ensure_file_exists(&self.data_path) {
-      // NOTE: Should be addressed internally, can't expose error outside
-      return Err(error_response_json(
-        StatusCode::INTERNAL_SERVER_ERROR,
-        ErrorResponse {
-          error: format!("Error creating {}", self.data_path),
-          msg: "Please contact support".to_string(),
-        },
-      ));
-    }
-
-    // File if exists
-    let data_str = match read_to_string(self.data_path.as_str()) {
-      Ok(res) => res,
-      Err(_) => {
-        return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
-          error: format!("Error reading {:?}", self.data_path),
-          msg: "Please contact support".to_string()
-        }))
-      }
-    };
+    // Checked before taking the lock, requests without a valid JWT
+    // never wait for or hold up the writers
+    let is_read = req.method() == Method::GET;
+    if !is_read {
+      let claims = req
+        .headers()
+        .get("Authorization")
+        .and_then(|auth_header| auth_header.to_str().ok())
+        .and_then(|auth_str| auth_str.strip_prefix("Bearer "))
+        .and_then(|token| {
+          let key = DecodingKey::from_secret(SECRET_KEY.as_ref());
+          let mut validation = Validation::new(Algorithm::HS256);
+          validation.validate_exp = true;
+          decode::<Claims>(token, &key, &validation).ok()
+        });
+      match claims {
+        Some(token_data) => req.extensions_mut().insert(token_data.claims),
+        None => return Err(StatusCode::UNAUTHORIZED.into())
+      };
+    }
 
-    // File if can be parsed to items
-    let items: Vec<Value> = match from_str(&data_str) {
-      Ok(res) => res,
-      Err(_) => {
-        return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
-          error: format!("Error parsing {:?}", self.data_path),
-          msg: "Please contact support".to_string()
-        }))
-      }
+    // Writes hold the lock through the handler so that read-modify-write,
+    // including the If-Match check, is not interleaved with other requests
+    let lock = data_lock(&self.data_path);
+    let (read_guard, _write_guard) = if is_read || !self.hold_lock {
+      (Some(lock.read_owned().await), None)
+    } else {
+      (None, Some(lock.write_owned().await))
     };
 
+    let items = load_items(&self.data_path)?;
     req.extensions_mut().insert(items);
+    if !self.hold_lock {
+      drop(read_guard);
+    }
 
-    if req.method() == Method::GET {
-      let res = self.inner.call(req).await;
-
-      match res {
-        Ok(resp) => {
-          let resp = resp.into_response();
-          return Ok(resp)
-        }
-        Err(err) => {
+    match self.inner.call(req).await {
+      Ok(resp) => Ok(resp.into_response()),
+      Err(err) => {
+        if is_read {
           println!("error: {err}");
-          return Err(err)
         }
+        Err(err)
       }
     }
+  }
+}
 
-    if let Some(auth_header) = req.headers().get("Authorization") {
-      if let Ok(auth_str) = auth_header.to_str() {
-        if auth_str.starts_with("Bearer ") {
-          let token = &auth_str[7..];
 
-          let key = DecodingKey::from_secret(SECRET_KEY.as_ref());
-          let mut validation = Validation::new(Algorithm::HS256);
-          validation.validate_exp = true;
+/// Reads the items of the data file, which is created empty on the first request
+pub(crate) fn load_items(data_path: &str) -> Result<Vec<Value>> {
+  if ensure_file_exists(data_path).is_err() {
+    // NOTE: Should be addressed internally, can't expose error outside
+    return Err(error_response_json(
+      StatusCode::INTERNAL_SERVER_ERROR,
+      ErrorResponse {
+        error: format!("Error creating {}", data_path),
+        msg: "Please contact support".to_string(),
+      },
+    ));
+  }
 
-          if let Ok(_claims) = decode::<Claims>(token, &key, &validation) {
-            let res = self.inner.call(req).await;
-
-            match res {
-              Ok(resp) => {
-                let resp = resp.into_response();
-                return Ok(resp)
-              }
-              Err(err) => {
-                // println!("3 {:?}", err);
-                return Err(err)
-              }
-            }
-          }
-        }
-      }
+  // File if exists
+  let data_str = match read_to_string(data_path) {
+    Ok(res) => res,
+    Err(_) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: format!("Error reading {:?}", data_path),
+        msg: "Please contact support".to_string()
+      }))
     }
+  };
 
-    
-
-    Err(StatusCode::UNAUTHORIZED.into())
+  // File if can be parsed to items
+  match from_str(&data_str) {
+    Ok(res) => Ok(res),
+    Err(_) => {
+      Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: format!("Error parsing {:?}", data_path),
+        msg: "Please contact support".to_string()
+      }))
+    }
   }
 }
 
-
 fn ensure_file_exists(path: &str) -> std::io::Result<()> {
   if !std::path::Path::new(path).exists() {
     let mut file = OpenOptions::new()
       .write(true)
       .create(true)
+      .truncate(true)
       .open(path)?;
-    file.write(b"[]")?;
+    file.write_all(b"[]")?;
   }
   Ok(())
 }
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use serde_json::Value;
+use unicode_normalization::UnicodeNormalization;
+
+use super::locale::item_names;
+
+/// How item names are compared when checking that they are unique
+#[derive(Clone, Debug)]
+pub struct NamePolicy {
+  /// Unicode NFKC, e.g. full width "ＺＥＬＤＡ" is the same as "ZELDA"
+  pub normalize: bool,
+  /// Ignore leading and trailing whitespace
+  pub trim: bool,
+  /// Ignore case
+  pub case_fold: bool,
+}
+
+impl Default for NamePolicy {
+  fn default() -> Self {
+    Self { normalize: true, trim: true, case_fold: true }
+  }
+}
+
+impl NamePolicy {
+  /// Exact `==` comparison, the behaviour before policies existed
+  pub fn exact() -> Self {
+    Self { normalize: false, trim: false, case_fold: false }
+  }
+
+  pub(crate) fn key(&self, name: &str) -> String {
+    let mut key = match self.normalize {
+      true => name.nfkc().collect::<String>(),
+      false => name.to_string(),
+    };
+    if self.trim {
+      key = key.trim().to_string();
+    }
+    if self.case_fold {
+      key = key.to_lowercase();
+    }
+    key
+  }
+}
+
+/// Normalized name in any locale to item id
+pub(crate) struct NameIndex<'a> {
+  policy: &'a NamePolicy,
+  ids: HashMap<String, u64>,
+}
+
+impl<'a> NameIndex<'a> {
+  pub(crate) fn build(items: &[Value], policy: &'a NamePolicy) -> Self {
+    let mut ids = HashMap::new();
+    for item in items.iter() {
+      // Records without an id can't clash with anything
+      let id = match item.get("id").and_then(|id| id.as_u64()) {
+        Some(res) => res,
+        None => continue,
+      };
+      for name in item_names(item) {
+        ids.entry(policy.key(name)).or_insert(id);
+      }
+    }
+    Self { policy, ids }
+  }
+
+  /// Id of the item already using the name
+  pub(crate) fn find(&self, name: &str) -> Option<u64> {
+    self.ids.get(&self.policy.key(name)).copied()
+  }
+}
@@ -0,0 +1,360 @@
+use chrono::{DateTime, Utc};
+use poem::web::{Data, Path};
+use poem::{handler, http::StatusCode, web::Json, Request, Response, Result};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use super::{actor, bump_item_version, find_item, item_not_found};
+use crate::{error_response_json, response_json, ErrorResponse};
+pub use sales::run_price_schedule;
+pub(crate) use pricing::{current_entries, entry_json, format_amount, parse_amount, parse_currency, read_prices, read_rates, resolve_price, write_prices, ExchangeRates, PriceEntry, Prices, GLOBAL_REGION};
+pub(crate) use sales::{read_sales, sale_json, write_sales, Sale, SaleStatus, Sales};
+
+mod pricing;
+mod sales;
+
+#[handler]
+pub(crate) async fn get_item_prices(req: &Request, key: Path<String>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let (id, _index) = match find_item(items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let prices = match read_prices(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_item_prices 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let sales = match read_sales(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_item_prices 2".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let now = Utc::now();
+  let entries = prices.get(&id).map(|e| e.as_slice()).unwrap_or_default();
+  let current: Vec<Value> = current_entries(entries, now).into_iter().map(entry_json).collect();
+
+  let mut entries: Vec<&PriceEntry> = entries.iter().collect();
+  entries.sort_by_key(|e| DateTime::parse_from_rfc3339(&e.effective_from).unwrap_or_default());
+  let (upcoming, history): (Vec<&PriceEntry>, Vec<&PriceEntry>) = entries
+    .into_iter()
+    .partition(|e| DateTime::parse_from_rfc3339(&e.effective_from).is_ok_and(|from| from > now));
+
+  let item_sales = sales.get(&id).map(|s| s.as_slice()).unwrap_or_default();
+  let sales_with = |status: SaleStatus| -> Vec<Value> {
+    item_sales.iter().filter(|s| s.status == status).map(sale_json).collect()
+  };
+
+  Ok(response_json(StatusCode::OK, json!({
+    "current": current,
+    // Newest first
+    "history": history.into_iter().rev().map(entry_json).collect::<Vec<Value>>(),
+    "upcoming": upcoming.into_iter().map(entry_json).collect::<Vec<Value>>(),
+    "sales": {
+      "active": sales_with(SaleStatus::Active),
+      "scheduled": sales_with(SaleStatus::Scheduled),
+      "ended": sales_with(SaleStatus::Ended)
+    }
+  })))
+}
+
+/// Schedules a discount on the item's prices in a region
+#[handler]
+pub(crate) async fn post_item_sale(req: &Request, key: Path<String>, sale_req: Json<SaleReq>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let (id, _index) = match find_item(items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let invalid_sale = |msg: String| error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+    error: "Invalid sale".to_string(),
+    msg
+  });
+  let region = sale_req.region.trim().to_lowercase();
+  if region.is_empty() {
+    return Err(invalid_sale("region must not be empty".to_string()))
+  }
+  let currency = match &sale_req.currency {
+    Some(currency) => Some(parse_currency(currency).map_err(invalid_sale)?),
+    None => None,
+  };
+  let amount_minor = match (&sale_req.amount, sale_req.percent_off, &currency) {
+    (Some(amount), None, Some(currency)) => Some(parse_amount(amount, currency).map_err(invalid_sale)?),
+    (None, Some(percent_off), _) if (1..100).contains(&percent_off) => None,
+    (None, Some(_), _) => return Err(invalid_sale("percent_off must be between 1 and 99".to_string())),
+    (Some(_), None, None) => return Err(invalid_sale("A sale price needs a currency".to_string())),
+    _ => return Err(invalid_sale("Give either amount or percent_off".to_string())),
+  };
+
+  let time = |t: &str| DateTime::parse_from_rfc3339(t).map_err(|_e| invalid_sale("starts_at and ends_at must be RFC 3339 times".to_string()));
+  let starts_at = match &sale_req.starts_at {
+    Some(starts_at) => time(starts_at)?.with_timezone(&Utc),
+    None => Utc::now(),
+  };
+  let ends_at = time(&sale_req.ends_at)?.with_timezone(&Utc);
+  if ends_at <= starts_at || ends_at <= Utc::now() {
+    return Err(invalid_sale("ends_at must be after starts_at and in the future".to_string()))
+  }
+
+  let mut sales = match read_sales(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error post_item_sale 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let mut sale = Sale {
+    id: sales.next_id(),
+    region,
+    currency,
+    amount_minor,
+    percent_off: sale_req.percent_off,
+    starts_at: starts_at.to_rfc3339(),
+    ends_at: ends_at.to_rfc3339(),
+    status: SaleStatus::Scheduled,
+    started_at: None,
+    ended_at: None,
+    actor: actor(req),
+    at: Utc::now().to_rfc3339(),
+  };
+  sale.status = sale.status_at(Utc::now());
+  if sale.status == SaleStatus::Active {
+    sale.started_at = Some(Utc::now().to_rfc3339());
+  }
+
+  sales.entry(id).or_default().push(sale.clone());
+  if write_sales(data_path.as_str(), &sales).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error post_item_sale 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+  bump_item_version(data_path.as_str(), id, "post_item_sale")?;
+
+  Ok(response_json(StatusCode::CREATED, sale_json(&sale)))
+}
+
+/// Cancels a scheduled sale or ends a running one now
+#[handler]
+pub(crate) async fn delete_item_sale(req: &Request, Path((key, sale_id)): Path<(String, u64)>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let (id, _index) = match find_item(items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let mut sales = match read_sales(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error delete_item_sale 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let item_sales = sales.entry(id).or_default();
+  let index = match item_sales.iter().position(|s| s.id == sale_id) {
+    Some(res) => res,
+    None => {
+      return Err(error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+        error: "Not found".to_string(),
+        msg: "Sale does not exist".to_string()
+      }))
+    }
+  };
+
+  let now = Utc::now();
+  match item_sales[index].status {
+    SaleStatus::Scheduled => {
+      item_sales.remove(index);
+    }
+    SaleStatus::Active => {
+      let sale = &mut item_sales[index];
+      sale.ends_at = now.to_rfc3339();
+      sale.ended_at = Some(now.to_rfc3339());
+      sale.status = SaleStatus::Ended;
+    }
+    SaleStatus::Ended => {
+      return Err(error_response_json(StatusCode::CONFLICT, ErrorResponse {
+        error: "Sale ended".to_string(),
+        msg: "The sale is already over".to_string()
+      }))
+    }
+  }
+
+  if write_sales(data_path.as_str(), &sales).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error delete_item_sale 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+  bump_item_version(data_path.as_str(), id, "delete_item_sale")?;
+
+  Ok(response_json(StatusCode::OK, json!({
+    "message": "Sale cancelled successfully"
+  })))
+}
+
+/// Sets the price of the item in a region and currency, from now or from a later time
+#[handler]
+pub(crate) async fn post_item_price(req: &Request, key: Path<String>, price_req: Json<PriceReq>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let (id, _index) = match find_item(items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let invalid_price = |msg: String| error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+    error: "Invalid price".to_string(),
+    msg
+  });
+  let region = price_req.region.trim().to_lowercase();
+  if region.is_empty() {
+    return Err(invalid_price("region must not be empty".to_string()))
+  }
+  let currency = parse_currency(&price_req.currency).map_err(invalid_price)?;
+  let amount_minor = parse_amount(&price_req.amount, &currency).map_err(invalid_price)?;
+  let effective_from = match &price_req.effective_from {
+    Some(time) => match DateTime::parse_from_rfc3339(time) {
+      Ok(res) => res.to_rfc3339(),
+      Err(_e) => return Err(invalid_price("effective_from must be an RFC 3339 time".to_string()))
+    },
+    None => Utc::now().to_rfc3339(),
+  };
+
+  let mut prices = match read_prices(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error post_item_price 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let entry = PriceEntry {
+    id: prices.next_id(),
+    region,
+    currency,
+    amount_minor,
+    effective_from,
+    actor: actor(req),
+    at: Utc::now().to_rfc3339(),
+  };
+  prices.entry(id).or_default().push(entry.clone());
+  if write_prices(data_path.as_str(), &prices).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error post_item_price 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+  bump_item_version(data_path.as_str(), id, "post_item_price")?;
+
+  Ok(response_json(StatusCode::CREATED, entry_json(&entry)))
+}
+
+/// Cancels a price that hasn't taken effect yet, prices already in effect stay as history
+#[handler]
+pub(crate) async fn delete_item_price(req: &Request, Path((key, price_id)): Path<(String, u64)>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let (id, _index) = match find_item(items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let mut prices = match read_prices(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error delete_item_price 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let entries = prices.entry(id).or_default();
+  let index = match entries.iter().position(|e| e.id == price_id) {
+    Some(res) => res,
+    None => {
+      return Err(error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+        error: "Not found".to_string(),
+        msg: "Price does not exist".to_string()
+      }))
+    }
+  };
+
+  let in_effect = DateTime::parse_from_rfc3339(&entries[index].effective_from).is_ok_and(|from| from <= Utc::now());
+  if in_effect {
+    return Err(error_response_json(StatusCode::CONFLICT, ErrorResponse {
+      error: "Price in effect".to_string(),
+      msg: "Only scheduled prices can be cancelled, set a new price instead".to_string()
+    }))
+  }
+
+  entries.remove(index);
+  if write_prices(data_path.as_str(), &prices).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error delete_item_price 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+  bump_item_version(data_path.as_str(), id, "delete_item_price")?;
+
+  Ok(response_json(StatusCode::OK, json!({
+    "message": "Price cancelled successfully"
+  })))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PriceReq {
+  #[serde(default = "global_region")]
+  region: String,
+  currency: String,
+  /// Decimal string like "19.99" or a number
+  amount: Value,
+  /// RFC 3339 time the price applies from, now by default
+  effective_from: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SaleReq {
+  #[serde(default = "global_region")]
+  region: String,
+  /// Needed for a fixed sale price, percentages apply to every currency without it
+  currency: Option<String>,
+  amount: Option<Value>,
+  percent_off: Option<u8>,
+  /// RFC 3339 time, now by default
+  starts_at: Option<String>,
+  ends_at: String,
+}
+
+fn global_region() -> String {
+  GLOBAL_REGION.to_string()
+}
@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use crate::items::entries::ItemEntries;
+use super::sales::{apply_sale, Sale};
+use crate::store::{read_json, sidecar_path, write_json};
+
+/// Region of prices that apply wherever a region has no price of its own
+pub(crate) const GLOBAL_REGION: &str = "global";
+
+/// One price of an item, a later `effective_from` in the same region and
+/// currency replaces it from then on
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct PriceEntry {
+  pub id: u64,
+  pub region: String,
+  pub currency: String,
+  /// In the minor unit of the currency, e.g. cents
+  pub amount_minor: i64,
+  pub effective_from: String,
+  pub actor: Option<String>,
+  pub at: String,
+}
+
+/// Price entries per item id, including past and scheduled ones
+pub(crate) type Prices = ItemEntries<PriceEntry>;
+
+pub(crate) fn read_prices(data_path: &str) -> std::io::Result<Prices> {
+  read_json(&sidecar_path(data_path, "prices"))
+}
+
+pub(crate) fn write_prices(data_path: &str, prices: &Prices) -> std::io::Result<()> {
+  write_json(&sidecar_path(data_path, "prices"), prices)
+}
+
+/// Units of `base` per currency, e.g. `{"base": "USD", "rates": {"JPY": 151.2}}`
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(crate) struct ExchangeRates {
+  pub base: String,
+  pub rates: BTreeMap<String, f64>,
+}
+
+impl ExchangeRates {
+  fn rate(&self, currency: &str) -> Option<f64> {
+    match currency == self.base {
+      true => Some(1.0),
+      false => self.rates.get(currency).copied().filter(|r| *r > 0.0),
+    }
+  }
+
+  /// Converts between currencies through the base, rounded half away from
+  /// zero to the minor unit of the target currency
+  pub(crate) fn convert(&self, amount_minor: i64, from: &str, to: &str) -> Option<i64> {
+    let amount = amount_minor as f64 / 10f64.powi(minor_digits(from) as i32);
+    let converted = amount / self.rate(from)? * self.rate(to)?;
+    Some((converted * 10f64.powi(minor_digits(to) as i32)).round() as i64)
+  }
+}
+
+/// Rates file given in `Config::exchange_rates_path`, next to the data file by default
+pub(crate) fn read_rates(data_path: &str, rates_path: &Option<String>) -> std::io::Result<ExchangeRates> {
+  match rates_path {
+    Some(path) => read_json(path),
+    None => read_json(&sidecar_path(data_path, "rates")),
+  }
+}
+
+/// Digits after the decimal point of the currency (ISO 4217)
+pub(crate) fn minor_digits(currency: &str) -> u32 {
+  match currency {
+    "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX" | "UYI" | "VND"
+      | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+    "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+    _ => 2,
+  }
+}
+
+pub(crate) fn parse_currency(currency: &str) -> Result<String, String> {
+  let currency = currency.trim().to_uppercase();
+  match currency.len() == 3 && currency.chars().all(|c| c.is_ascii_alphabetic()) {
+    true => Ok(currency),
+    false => Err("currency must be a 3 letter ISO 4217 code".to_string()),
+  }
+}
+
+/// Reads an amount like "19.99" or 1980 into minor units of the currency
+pub(crate) fn parse_amount(amount: &Value, currency: &str) -> Result<i64, String> {
+  let text = match amount {
+    Value::String(s) => s.trim().to_string(),
+    Value::Number(n) => n.to_string(),
+    _ => return Err("amount must be a number or a decimal string".to_string()),
+  };
+
+  let digits = minor_digits(currency) as usize;
+  let (whole, fraction) = text.split_once('.').unwrap_or((&text, ""));
+  let valid = !whole.is_empty()
+    && whole.chars().all(|c| c.is_ascii_digit())
+    && fraction.chars().all(|c| c.is_ascii_digit());
+  if !valid {
+    return Err("amount must be a positive decimal number".to_string())
+  }
+  if fraction.len() > digits {
+    return Err(format!("{} amounts have at most {} decimals", currency, digits))
+  }
+
+  format!("{}{:0<width$}", whole, fraction, width = digits)
+    .parse::<i64>()
+    .map_err(|_e| "amount is too large".to_string())
+}
+
+/// Minor units back to a decimal string, e.g. 1999 USD is "19.99"
+pub(crate) fn format_amount(amount_minor: i64, currency: &str) -> String {
+  let digits = minor_digits(currency);
+  if digits == 0 {
+    return amount_minor.to_string()
+  }
+  let factor = 10i64.pow(digits);
+  format!("{}.{:0width$}", amount_minor / factor, amount_minor % factor, width = digits as usize)
+}
+
+/// Entries in effect at `at`, the latest one per region and currency
+pub(crate) fn current_entries(entries: &[PriceEntry], at: DateTime<Utc>) -> Vec<&PriceEntry> {
+  let mut current: BTreeMap<(&str, &str), &PriceEntry> = BTreeMap::new();
+  for entry in entries.iter() {
+    let effective_from = match DateTime::parse_from_rfc3339(&entry.effective_from) {
+      Ok(res) => res,
+      Err(_e) => continue,
+    };
+    if effective_from > at {
+      continue;
+    }
+    let key = (entry.region.as_str(), entry.currency.as_str());
+    let replaces = match current.get(&key) {
+      Some(existing) => effective_from >= effective(existing),
+      None => true,
+    };
+    if replaces {
+      current.insert(key, entry);
+    }
+  }
+  current.into_values().collect()
+}
+
+fn effective(entry: &PriceEntry) -> DateTime<FixedOffset> {
+  DateTime::parse_from_rfc3339(&entry.effective_from).unwrap_or_default()
+}
+
+/// Price of the item in the currency for the region. A price set in that
+/// currency wins, otherwise one in another currency is converted. Regions
+/// without any price fall back to the global prices. Active sales are
+/// applied before converting.
+pub(crate) fn resolve_price(
+  entries: &[PriceEntry],
+  sales: &[Sale],
+  region: &str,
+  currency: &str,
+  rates: &ExchangeRates,
+  at: DateTime<Utc>
+) -> Option<Value> {
+  let current = current_entries(entries, at);
+
+  let mut regions = vec![region];
+  if region != GLOBAL_REGION {
+    regions.push(GLOBAL_REGION);
+  }
+
+  for region in regions {
+    let in_region: Vec<&&PriceEntry> = current.iter().filter(|e| e.region == region).collect();
+    if let Some(entry) = in_region.iter().find(|e| e.currency == currency) {
+      let (amount_minor, sale) = apply_sale(sales, &entry.region, &entry.currency, entry.amount_minor);
+      return Some(price_json((amount_minor, entry.amount_minor), currency, entry, sale, false))
+    }
+    for entry in in_region.iter() {
+      let (amount_minor, sale) = apply_sale(sales, &entry.region, &entry.currency, entry.amount_minor);
+      let converted = (
+        rates.convert(amount_minor, &entry.currency, currency),
+        rates.convert(entry.amount_minor, &entry.currency, currency),
+      );
+      if let (Some(amount_minor), Some(regular_minor)) = converted {
+        return Some(price_json((amount_minor, regular_minor), currency, entry, sale, true))
+      }
+    }
+  }
+  None
+}
+
+fn price_json((amount_minor, regular_minor): (i64, i64), currency: &str, entry: &PriceEntry, sale: Option<&Sale>, derived: bool) -> Value {
+  let mut res = json!({
+    "amount": format_amount(amount_minor, currency),
+    "amount_minor": amount_minor,
+    "currency": currency,
+    "region": entry.region,
+    "effective_from": entry.effective_from,
+    "derived": derived,
+  });
+  if derived {
+    res["source"] = json!({
+      "amount": format_amount(entry.amount_minor, &entry.currency),
+      "currency": entry.currency,
+    });
+  }
+  if let Some(sale) = sale {
+    res["regular_amount"] = json!(format_amount(regular_minor, currency));
+    res["sale"] = json!({
+      "id": sale.id,
+      "ends_at": sale.ends_at,
+    });
+  }
+  res
+}
+
+/// Price entry as shown to clients, with the amount as a decimal string
+pub(crate) fn entry_json(entry: &PriceEntry) -> Value {
+  let mut res = json!(entry);
+  res["amount"] = json!(format_amount(entry.amount_minor, &entry.currency));
+  res
+}
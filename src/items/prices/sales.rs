@@ -0,0 +1,133 @@
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use crate::items::entries::ItemEntries;
+use crate::items::etag::{bump_version, read_versions, write_versions};
+use super::pricing::format_amount;
+use crate::store::{data_lock, read_json, sidecar_path, write_json};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SaleStatus {
+  Scheduled,
+  Active,
+  Ended,
+}
+
+/// Discount on the prices of an item in a region between `starts_at` and `ends_at`.
+/// Either a fixed sale price in one currency or a percentage off, which applies
+/// to every currency unless `currency` is given.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct Sale {
+  pub id: u64,
+  pub region: String,
+  pub currency: Option<String>,
+  /// Sale price in the minor unit of `currency`
+  pub amount_minor: Option<i64>,
+  pub percent_off: Option<u8>,
+  pub starts_at: String,
+  pub ends_at: String,
+  /// Set by the scheduler, prices are only discounted while the sale is active
+  pub status: SaleStatus,
+  /// When the scheduler activated the sale
+  pub started_at: Option<String>,
+  /// When the scheduler reverted the prices
+  pub ended_at: Option<String>,
+  pub actor: Option<String>,
+  pub at: String,
+}
+
+impl Sale {
+  /// Status the sale should have at `now`
+  pub(crate) fn status_at(&self, now: DateTime<Utc>) -> SaleStatus {
+    let time = |t: &str| DateTime::parse_from_rfc3339(t).ok();
+    match (time(&self.starts_at), time(&self.ends_at)) {
+      (_, Some(ends_at)) if ends_at <= now => SaleStatus::Ended,
+      (Some(starts_at), _) if starts_at <= now => SaleStatus::Active,
+      _ => SaleStatus::Scheduled,
+    }
+  }
+
+  fn applies_to(&self, region: &str, currency: &str) -> bool {
+    self.region == region && self.currency.as_deref().is_none_or(|c| c == currency)
+  }
+
+  /// Sale price for a regular price, never more than the regular price
+  fn discounted(&self, regular_minor: i64, currency: &str) -> i64 {
+    let discounted = match (self.amount_minor, self.percent_off) {
+      (Some(amount_minor), _) if self.currency.as_deref() == Some(currency) => amount_minor,
+      (_, Some(percent_off)) => (regular_minor as f64 * (100 - percent_off as i64) as f64 / 100.0).round() as i64,
+      _ => regular_minor,
+    };
+    discounted.min(regular_minor)
+  }
+}
+
+/// Sales per item id
+pub(crate) type Sales = ItemEntries<Sale>;
+
+pub(crate) fn read_sales(data_path: &str) -> std::io::Result<Sales> {
+  read_json(&sidecar_path(data_path, "sales"))
+}
+
+pub(crate) fn write_sales(data_path: &str, sales: &Sales) -> std::io::Result<()> {
+  write_json(&sidecar_path(data_path, "sales"), sales)
+}
+
+/// Applies the best sale the scheduler activated to a regular price, returns the price
+/// and the sale when there is one
+pub(crate) fn apply_sale<'a>(sales: &'a [Sale], region: &str, currency: &str, regular_minor: i64) -> (i64, Option<&'a Sale>) {
+  sales
+    .iter()
+    .filter(|s| s.status == SaleStatus::Active && s.applies_to(region, currency))
+    .map(|s| (s.discounted(regular_minor, currency), Some(s)))
+    .min_by_key(|(amount, _sale)| *amount)
+    .unwrap_or((regular_minor, None))
+}
+
+pub(crate) fn sale_json(sale: &Sale) -> Value {
+  let mut res = json!(sale);
+  if let (Some(amount_minor), Some(currency)) = (sale.amount_minor, &sale.currency) {
+    res["amount"] = json!(format_amount(amount_minor, currency));
+  }
+  res
+}
+
+/// Activates sales whose start has come and reverts those whose end has,
+/// returns how many changed
+pub async fn run_price_schedule(data_path: &str) -> std::io::Result<usize> {
+  let _guard = data_lock(data_path).write_owned().await;
+
+  let mut sales = read_sales(data_path)?;
+  let now = Utc::now();
+  let mut item_ids = Vec::new();
+  for (id, item_sales) in sales.iter_mut() {
+    for sale in item_sales.iter_mut() {
+      let status = sale.status_at(now);
+      if status == sale.status {
+        continue;
+      }
+      if status != SaleStatus::Scheduled && sale.started_at.is_none() {
+        sale.started_at = Some(now.to_rfc3339());
+      }
+      if status == SaleStatus::Ended {
+        sale.ended_at = Some(now.to_rfc3339());
+      }
+      sale.status = status;
+      item_ids.push(*id);
+    }
+  }
+  if item_ids.is_empty() {
+    return Ok(0)
+  }
+  write_sales(data_path, &sales)?;
+
+  // Their prices changed
+  let mut versions = read_versions(data_path)?;
+  for id in item_ids.iter() {
+    bump_version(&mut versions, *id);
+  }
+  write_versions(data_path, &versions)?;
+  Ok(item_ids.len())
+}
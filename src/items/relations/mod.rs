@@ -0,0 +1,175 @@
+use poem::web::{Data, Path};
+use poem::{handler, http::StatusCode, web::Json, Request, Response, Result};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use super::etag::{if_match, item_etag, read_versions, version_of, with_etag};
+use super::history::record_revision;
+use super::{actor, bump_item_version, find_item, item_not_found, precondition_failed};
+use crate::{error_response_json, response_json, ErrorResponse};
+pub(crate) use relation::{read_relations, write_relations, RelationError, RelationKind};
+
+mod relation;
+
+/// Links from the item to others and from others to it
+#[handler]
+pub(crate) async fn get_item_relations(req: &Request, key: Path<String>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let id = match find_item(items, &key) {
+    Some((id, _index)) => id,
+    None => return Err(item_not_found())
+  };
+
+  let relations = match read_relations(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_item_relations 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  // Links of items in the trash stay until they are purged, hidden meanwhile
+  let exists = |id: u64| find_item(items, &id.to_string()).is_some();
+  Ok(response_json(StatusCode::OK, json!({
+    "outgoing": relations.outgoing(id).filter(|r| exists(r.to)).collect::<Vec<_>>(),
+    "incoming": relations.incoming(id).filter(|r| exists(r.from)).collect::<Vec<_>>()
+  })))
+}
+
+#[handler]
+pub(crate) async fn post_item_relation(req: &Request, key: Path<String>, relation_req: Json<RelationReq>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let (id, index) = match find_item(items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+  if !if_match_item(req, data_path.as_str(), id, "post_item_relation")? {
+    return Err(precondition_failed())
+  }
+  if find_item(items, &relation_req.item_id.to_string()).is_none() {
+    return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+      error: "Invalid relation".to_string(),
+      msg: format!("Item {} does not exist", relation_req.item_id)
+    }))
+  }
+
+  let mut relations = match read_relations(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error post_item_relation 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let relation = match relations.insert(id, relation_req.item_id, relation_req.kind, actor(req)) {
+    Ok(res) => res,
+    Err(RelationError::Invalid(msg)) => {
+      return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+        error: "Invalid relation".to_string(),
+        msg
+      }))
+    }
+    Err(RelationError::Duplicate(existing_id)) => {
+      return Err(error_response_json(StatusCode::CONFLICT, json!({
+        "error": "Relation already exists",
+        "msg": "The items are already linked this way",
+        "id": existing_id
+      })))
+    }
+  };
+
+  if write_relations(data_path.as_str(), &relations).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error post_item_relation 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  let version = record_link_change(req, data_path.as_str(), id, &items[index], "relate", "post_item_relation")?;
+  Ok(with_etag(response_json(StatusCode::CREATED, relation), &item_etag(version)))
+}
+
+#[handler]
+pub(crate) async fn delete_item_relation(req: &Request, Path((key, relation_id)): Path<(String, u64)>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let (id, index) = match find_item(items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+  if !if_match_item(req, data_path.as_str(), id, "delete_item_relation")? {
+    return Err(precondition_failed())
+  }
+
+  let mut relations = match read_relations(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error delete_item_relation 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let relation = match relations.remove(id, relation_id) {
+    Some(res) => res,
+    None => {
+      return Err(error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+        error: "Not found".to_string(),
+        msg: "Relation does not exist".to_string()
+      }))
+    }
+  };
+
+  if write_relations(data_path.as_str(), &relations).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error delete_item_relation 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  let version = record_link_change(req, data_path.as_str(), id, &items[index], "unrelate", "delete_item_relation")?;
+  Ok(with_etag(response_json(StatusCode::OK, relation), &item_etag(version)))
+}
+
+/// Whether the `If-Match` header matches the current version of the item
+fn if_match_item(req: &Request, data_path: &str, id: u64, handler: &str) -> Result<bool> {
+  match read_versions(data_path) {
+    Ok(versions) => Ok(if_match(req.headers(), &item_etag(version_of(&versions, id)))),
+    Err(_e) => {
+      Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: format!("Server error {} etag", handler),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  }
+}
+
+/// Bumps the version of an item whose links to other items changed and
+/// records the revision, the item itself stays the same
+fn record_link_change(req: &Request, data_path: &str, id: u64, item: &Value, action: &str, handler: &str) -> Result<u64> {
+  let version = bump_item_version(data_path, id, handler)?;
+  if record_revision(data_path, id, version, action, Some(item.clone()), Some(item.clone()), actor(req)).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: format!("Server error {} history", handler),
+      msg: "Please contact support".to_string()
+    }))
+  }
+  Ok(version)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RelationReq {
+  /// Item the link points to
+  item_id: u64,
+  kind: RelationKind,
+}
@@ -0,0 +1,116 @@
+use serde::{Serialize, Deserialize};
+use chrono::Utc;
+
+use crate::store::{read_json, sidecar_path, write_json};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RelationKind {
+  /// The same title on another platform
+  AlsoAvailableOn,
+  /// Works with or extends the other item, e.g. a controller for a console
+  AccessoryFor,
+  SimilarTo,
+  /// Newer or better version of the other item
+  UpgradeOf,
+}
+
+impl RelationKind {
+  pub(crate) fn as_str(self) -> &'static str {
+    match self {
+      RelationKind::AlsoAvailableOn => "also_available_on",
+      RelationKind::AccessoryFor => "accessory_for",
+      RelationKind::SimilarTo => "similar_to",
+      RelationKind::UpgradeOf => "upgrade_of",
+    }
+  }
+}
+
+/// Link from one item to another, read as "`from` is `kind` `to`"
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct Relation {
+  pub id: u64,
+  pub from: u64,
+  pub to: u64,
+  pub kind: RelationKind,
+  pub actor: Option<String>,
+  pub at: String,
+}
+
+pub(crate) enum RelationError {
+  Invalid(String),
+  Duplicate(u64),
+}
+
+/// Every link between items, ids of removed links are never given again
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub(crate) struct Relations {
+  last_id: u64,
+  relations: Vec<Relation>,
+}
+
+impl Relations {
+  pub(crate) fn outgoing(&self, id: u64) -> impl Iterator<Item = &Relation> {
+    self.relations.iter().filter(move |r| r.from == id)
+  }
+
+  pub(crate) fn incoming(&self, id: u64) -> impl Iterator<Item = &Relation> {
+    self.relations.iter().filter(move |r| r.to == id)
+  }
+
+  pub(crate) fn insert(&mut self, from: u64, to: u64, kind: RelationKind, actor: Option<String>) -> Result<Relation, RelationError> {
+    if from == to {
+      return Err(RelationError::Invalid("An item can't be related to itself".to_string()))
+    }
+    if let Some(existing) = self.relations.iter().find(|r| r.from == from && r.to == to && r.kind == kind) {
+      return Err(RelationError::Duplicate(existing.id))
+    }
+
+    self.last_id += 1;
+    let relation = Relation { id: self.last_id, from, to, kind, actor, at: Utc::now().to_rfc3339() };
+    self.relations.push(relation.clone());
+    Ok(relation)
+  }
+
+  /// Removes a link of the item, `None` when it has no such link
+  pub(crate) fn remove(&mut self, from: u64, relation_id: u64) -> Option<Relation> {
+    let index = self.relations.iter().position(|r| r.id == relation_id && r.from == from)?;
+    Some(self.relations.remove(index))
+  }
+
+  /// Points the links of an item merged into another at that item, links that
+  /// become self-links or duplicates are dropped
+  pub(crate) fn merge_item(&mut self, from: u64, into: u64) {
+    let mut merged: Vec<Relation> = Vec::new();
+    for mut relation in std::mem::take(&mut self.relations) {
+      if relation.from == from {
+        relation.from = into;
+      }
+      if relation.to == from {
+        relation.to = into;
+      }
+      let duplicate = merged
+        .iter()
+        .any(|r| r.from == relation.from && r.to == relation.to && r.kind == relation.kind);
+      if relation.from != relation.to && !duplicate {
+        merged.push(relation);
+      }
+    }
+    self.relations = merged;
+  }
+
+  /// Removes every link from or to the item, returns whether there were any
+  pub(crate) fn remove_item(&mut self, id: u64) -> bool {
+    let before = self.relations.len();
+    self.relations.retain(|r| r.from != id && r.to != id);
+    self.relations.len() != before
+  }
+}
+
+pub(crate) fn read_relations(data_path: &str) -> std::io::Result<Relations> {
+  read_json(&sidecar_path(data_path, "relations"))
+}
+
+pub(crate) fn write_relations(data_path: &str, relations: &Relations) -> std::io::Result<()> {
+  write_json(&sidecar_path(data_path, "relations"), relations)
+}
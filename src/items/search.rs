@@ -0,0 +1,157 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+use once_cell::sync::Lazy;
+use serde_json::Value;
+
+use super::etag::{version_of, Versions};
+use super::locale::item_texts;
+use super::text::{fold, tokenize};
+
+/// Matches on a whole word count double compared to prefix matches
+const PREFIX_WEIGHT: f64 = 0.5;
+/// BM25 term frequency saturation and length normalization,
+/// shorter titles rank above longer ones with the same matches
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+/// Extra score when the folded query appears as is in the item text
+const PHRASE_BONUS: f64 = 1.0;
+
+/// Inverted index over the searchable text of the items of one data file
+#[derive(Default)]
+struct SearchIndex {
+  postings: BTreeMap<String, HashMap<u64, u32>>,
+  docs: HashMap<u64, Doc>,
+}
+
+/// An indexed item, `version` is the one its text was taken from
+struct Doc {
+  version: u64,
+  tokens: Vec<String>,
+  text: String,
+}
+
+impl SearchIndex {
+  /// Indexes the items whose version moved on since they were indexed and
+  /// drops the ones that are gone, the rest of the index is kept as it is
+  fn refresh(&mut self, items: &[Value], versions: &Versions) {
+    let mut ids = HashSet::new();
+    for item in items.iter() {
+      let id = match item.get("id").and_then(|id| id.as_u64()) {
+        Some(res) => res,
+        None => continue,
+      };
+      ids.insert(id);
+      let version = version_of(versions, id);
+      if self.docs.get(&id).is_some_and(|doc| doc.version == version) {
+        continue;
+      }
+      self.remove(id);
+      self.insert(id, version, item);
+    }
+
+    let gone: Vec<u64> = self.docs.keys().filter(|id| !ids.contains(id)).copied().collect();
+    for id in gone {
+      self.remove(id);
+    }
+  }
+
+  fn insert(&mut self, id: u64, version: u64, item: &Value) {
+    let text = fold(&searchable_text(item));
+    let tokens = tokenize(&text, false);
+    for token in tokens.iter() {
+      *self.postings.entry(token.clone()).or_default().entry(id).or_insert(0) += 1;
+    }
+    self.docs.insert(id, Doc { version, tokens, text });
+  }
+
+  fn remove(&mut self, id: u64) {
+    let doc = match self.docs.remove(&id) {
+      Some(res) => res,
+      None => return,
+    };
+    for token in doc.tokens.iter() {
+      if let Some(posting) = self.postings.get_mut(token) {
+        posting.remove(&id);
+        if posting.is_empty() {
+          self.postings.remove(token);
+        }
+      }
+    }
+  }
+
+  /// Every query word has to match a word of the item, either whole or as a prefix.
+  /// Results are ordered by score, best first.
+  fn search(&self, query: &str) -> Vec<(u64, f64)> {
+    let folded = fold(query);
+    let query_tokens = tokenize(&folded, true);
+    if query_tokens.is_empty() {
+      return Vec::new()
+    }
+
+    let doc_count = self.docs.len() as f64;
+    let token_count: usize = self.docs.values().map(|doc| doc.tokens.len()).sum();
+    let avg_len = token_count as f64 / doc_count.max(1.0);
+    let mut scores: Option<HashMap<u64, f64>> = None;
+    for query_token in query_tokens.iter() {
+      let mut token_scores: HashMap<u64, f64> = HashMap::new();
+      let matches = self.postings
+        .range(query_token.clone()..)
+        .take_while(|(token, _)| token.starts_with(query_token.as_str()));
+
+      for (token, posting) in matches {
+        let weight = if token == query_token { 1.0 } else { PREFIX_WEIGHT };
+        let idf = (1.0 + doc_count / posting.len() as f64).ln();
+        for (id, tf) in posting.iter() {
+          let tf = *tf as f64;
+          let len = self.docs[id].tokens.len() as f64;
+          let score = weight * idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * len / avg_len));
+          let best = token_scores.entry(*id).or_insert(0.0);
+          *best = best.max(score);
+        }
+      }
+
+      scores = Some(match scores {
+        None => token_scores,
+        Some(scores) => scores
+          .into_iter()
+          .filter_map(|(id, score)| token_scores.get(&id).map(|s| (id, score + s)))
+          .collect(),
+      });
+    }
+
+    let mut results: Vec<(u64, f64)> = scores
+      .unwrap_or_default()
+      .into_iter()
+      .map(|(id, score)| {
+        let text = &self.docs[&id].text;
+        match text.contains(folded.trim()) {
+          true => (id, score + PHRASE_BONUS),
+          false => (id, score),
+        }
+      })
+      .collect();
+    results.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    results
+  }
+}
+
+/// Text of the item that search looks at, names and descriptions in every locale
+fn searchable_text(item: &Value) -> String {
+  item_texts(item).join("\n")
+}
+
+/// Indexes are kept in memory per data file. Every write of an item bumps its
+/// version, whichever code path or process wrote it, so only the items whose
+/// version differs from the indexed one are indexed again.
+static INDEXES: Lazy<Mutex<HashMap<String, SearchIndex>>> = Lazy::new(|| {
+  Mutex::new(HashMap::new())
+});
+
+/// Ids of the matching items with their score, best first.
+/// `items` and `versions` are the content of the data files as they are now.
+pub(crate) fn search_items(data_path: &str, items: &[Value], versions: &Versions, query: &str) -> Vec<(u64, f64)> {
+  let mut indexes = INDEXES.lock().unwrap();
+  let index = indexes.entry(data_path.to_string()).or_default();
+  index.refresh(items, versions);
+  index.search(query)
+}
@@ -0,0 +1,583 @@
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use poem::http::StatusCode;
+use serde_json::{json, Map, Value};
+
+use super::barcodes::{parse_barcodes, set_barcodes, BarcodeIndex};
+use super::bulk::ItemKey;
+use super::etag::{bump_version, read_versions, version_of, Versions};
+use super::history::{push_revision, read_history, History};
+use super::ids::{claim_id, new_public_id, new_slug, next_id, read_sequence, resolve_id, retire_id, Sequence};
+use super::locale::{normalize_locale, set_translation, TEXT_FIELDS};
+use super::names::NameIndex;
+use super::status::{read_transitions, status_of, transition, ItemStatus, Transitions};
+use super::tags::{normalize_tag, set_tags, tags_of, MAX_TAGS};
+use super::trash::{delete_blocker, read_trash};
+use super::transfer::ImportRecord;
+use super::variants::{name_to_store, normalize_sku, options_owner, parent_of, parse_options, sku_owner};
+use super::{ConflictResponse, Item, ItemPatchReq, ItemReq};
+use crate::categories::{check_item_attributes, read_categories, Categories};
+use crate::store::{sidecar_path, FileBatch};
+use crate::{error_response_json, Config, ErrorResponse};
+
+/// Why a create, update or delete failed, with the status the single item
+/// endpoints answer with
+#[derive(Debug, Clone)]
+pub(crate) struct OpError {
+  pub status: StatusCode,
+  error: String,
+  msg: String,
+  id: Option<u64>,
+  details: Option<Value>,
+}
+
+impl OpError {
+  pub(crate) fn new(status: StatusCode, error: &str, msg: &str) -> Self {
+    Self { status, error: error.to_string(), msg: msg.to_string(), id: None, details: None }
+  }
+
+  /// Keeps everything the single item endpoint would answer with, e.g. `bundle_ids`
+  pub(crate) fn from_json(status: StatusCode, body: Value) -> Self {
+    let error = body.get("error").and_then(|e| e.as_str()).unwrap_or_default();
+    let msg = body.get("msg").and_then(|m| m.as_str()).unwrap_or_default();
+    Self { details: Some(body.clone()), ..Self::new(status, error, msg) }
+  }
+
+  /// Result of the operation in a bulk request or an import
+  pub(crate) fn to_json(&self) -> Value {
+    let mut res = json!({
+      "status": self.status.as_u16(),
+      "error": self.error,
+      "msg": self.msg,
+    });
+    if let Some(id) = self.id {
+      res["id"] = json!(id);
+    }
+    if let Some(Value::Object(details)) = &self.details {
+      for (key, value) in details.iter() {
+        res[key] = value.clone();
+      }
+    }
+    res
+  }
+
+  /// Response of the single item endpoints
+  pub(crate) fn into_error(self) -> poem::Error {
+    match (self.details, self.id) {
+      (Some(details), _) => error_response_json(self.status, details),
+      (None, Some(id)) => error_response_json(self.status, ConflictResponse { error: self.error, msg: self.msg, id }),
+      (None, None) => error_response_json(self.status, ErrorResponse { error: self.error, msg: self.msg }),
+    }
+  }
+}
+
+/// The items and everything that changes with every create, update and
+/// delete of one, loaded once and saved together
+#[derive(Clone)]
+pub(crate) struct ItemState {
+  items: Vec<Value>,
+  versions: Versions,
+  sequence: Sequence,
+  trash: Vec<Value>,
+  history: History,
+  transitions: Transitions,
+  /// Only read, items are checked against the attribute schemas
+  categories: Arc<Categories>,
+}
+
+impl ItemState {
+  pub(crate) fn load(data_path: &str, items: Vec<Value>) -> std::io::Result<Self> {
+    Ok(Self {
+      items,
+      versions: read_versions(data_path)?,
+      sequence: read_sequence(data_path)?,
+      trash: read_trash(data_path)?,
+      history: read_history(data_path)?,
+      transitions: read_transitions(data_path)?,
+      categories: Arc::new(read_categories(data_path)?),
+    })
+  }
+
+  /// Writes all files or, when one of them fails, none
+  pub(crate) fn save(&self, data_path: &str) -> std::io::Result<()> {
+    let mut batch = FileBatch::default();
+    batch.add(data_path, &self.items)?;
+    batch.add(&sidecar_path(data_path, "versions"), &self.versions)?;
+    batch.add(&sidecar_path(data_path, "sequence"), &self.sequence)?;
+    batch.add(&sidecar_path(data_path, "trash"), &self.trash)?;
+    batch.add(&sidecar_path(data_path, "history"), &self.history)?;
+    batch.add(&sidecar_path(data_path, "transitions"), &self.transitions)?;
+    batch.commit()
+  }
+
+  pub(crate) fn items(&self) -> &[Value] {
+    &self.items
+  }
+
+  pub(crate) fn version(&self, id: u64) -> u64 {
+    version_of(&self.versions, id)
+  }
+
+  /// Applies a row of an import: the item with the id is updated, or created with
+  /// that id when it was never handed out, so exported files import as they were
+  pub(crate) fn import(&mut self, record: &ImportRecord, config: &Config, actor: &Option<String>) -> Result<(StatusCode, Value), OpError> {
+    let existing = match &record.id {
+      None => None,
+      Some(ItemKey::Id(id)) => self.find(&id.to_string()).ok(),
+      Some(key) => Some(self.find(&key.as_key())?),
+    };
+    let new_id = match (&record.id, existing) {
+      (Some(ItemKey::Id(id)), None) => Some(*id),
+      _ => None,
+    };
+    // Rows with an id bring back exported items whatever their status,
+    // rows without one are new items
+    if let (None, None, Some(status)) = (existing, new_id, record.status) {
+      check_start(status)?;
+    }
+
+    if let Some(variant) = &record.variant {
+      return self.import_variant(existing, new_id, record, variant, config, actor)
+    }
+    let mut changes = patch_of(record);
+    match existing {
+      Some((id, index)) => {
+        let mut item = self.updated(id, index, &changes, config)?;
+        self.import_fields(Some(id), &mut item, record, config, actor)?;
+        Ok((StatusCode::OK, self.replace(id, index, item, actor)))
+      }
+      None => {
+        let name = changes.name.take().unwrap_or_default();
+        let mut item = self.created(&name, None, &changes, config)?;
+        self.import_fields(None, &mut item, record, config, actor)?;
+        Ok((StatusCode::CREATED, self.insert(item, new_id, config, actor)?))
+      }
+    }
+  }
+
+  fn import_variant(
+    &mut self,
+    existing: Option<(u64, usize)>,
+    new_id: Option<u64>,
+    record: &ImportRecord,
+    (parent_key, sku, options): &(ItemKey, String, Map<String, Value>),
+    config: &Config,
+    actor: &Option<String>
+  ) -> Result<(StatusCode, Value), OpError> {
+    let invalid = |msg: &str| OpError::new(StatusCode::BAD_REQUEST, "Invalid variant", msg);
+    let (parent_id, parent_index) = self.find(&parent_key.as_key())
+      .map_err(|_| OpError::new(StatusCode::NOT_FOUND, "Not found", "Parent item does not exist"))?;
+    if parent_of(&self.items[parent_index]).is_some() {
+      return Err(invalid("Variants can't have variants of their own"))
+    }
+    if let Some((id, index)) = existing {
+      if parent_of(&self.items[index]) != Some(parent_id) {
+        return Err(invalid(&format!("Item {} is not a variant of item {}", id, parent_id)))
+      }
+    }
+    if let Some(name) = &record.name {
+      name_to_store(&self.items, Some(parent_id), name).map_err(|e| invalid(&e))?;
+    }
+    let sku = normalize_sku(sku).map_err(|e| invalid(&e))?;
+    let options = parse_options(options).map_err(|e| invalid(&e))?;
+
+    let id = existing.map(|(id, _index)| id);
+    match sku_owner(&self.items, &sku) {
+      Some(existing_id) if Some(existing_id) != id => {
+        return Err(OpError {
+          id: Some(existing_id),
+          ..OpError::new(StatusCode::CONFLICT, "SKU already exists", "Please use a different SKU")
+        })
+      }
+      _ => {}
+    }
+    match options_owner(&self.items, parent_id, &options) {
+      Some(existing_id) if Some(existing_id) != id => {
+        return Err(OpError {
+          id: Some(existing_id),
+          ..OpError::new(StatusCode::CONFLICT, "Variant already exists", "The item already has a variant with these options")
+        })
+      }
+      _ => {}
+    }
+
+    let mut item = match existing {
+      Some((_id, index)) => self.items[index].clone(),
+      None => json!({ "parent_id": parent_id }),
+    };
+    item["sku"] = json!(sku);
+    item["options"] = json!(options);
+    let changes = ItemPatchReq { name: None, ..patch_of(record) };
+    self.apply_changes(id, &mut item, &changes, config)?;
+    self.import_fields(id, &mut item, record, config, actor)?;
+    match existing {
+      Some((id, index)) => Ok((StatusCode::OK, self.replace(id, index, item, actor))),
+      None => Ok((StatusCode::CREATED, self.insert(item, new_id, config, actor)?)),
+    }
+  }
+
+  /// Creates the item
+  pub(crate) fn create(&mut self, item_req: &ItemReq, config: &Config, actor: &Option<String>) -> Result<(StatusCode, Value), OpError> {
+    let changes = ItemPatchReq {
+      name: None,
+      category_ids: item_req.category_ids.clone(),
+      attributes: item_req.attributes.clone(),
+      barcodes: item_req.barcodes.clone(),
+    };
+    let item = self.created(&item_req.name, item_req.status, &changes, config)?;
+    Ok((StatusCode::CREATED, self.insert(item, None, config, actor)?))
+  }
+
+  /// Fields missing from `changes` are left as they are, an item that ends up
+  /// the same keeps its version
+  pub(crate) fn update(&mut self, key: &str, changes: &ItemPatchReq, version: Option<u64>, config: &Config, actor: &Option<String>) -> Result<(StatusCode, Value), OpError> {
+    let (id, index) = self.find(key)?;
+    self.check_version(id, version)?;
+
+    let item = self.updated(id, index, changes, config)?;
+    Ok((StatusCode::OK, self.replace(id, index, item, actor)))
+  }
+
+  /// A new item checked against the others, without its id yet
+  fn created(&self, name: &str, status: Option<ItemStatus>, changes: &ItemPatchReq, config: &Config) -> Result<Value, OpError> {
+    if let Some(existing_id) = NameIndex::build(&self.items, &config.name_policy).find(name) {
+      return Err(name_conflict(existing_id))
+    }
+    if let Some(status) = status {
+      check_start(status)?;
+    }
+
+    let mut item = json!(Item { id: 0, name: name.to_string() });
+    self.apply_changes(None, &mut item, changes, config)?;
+    if let Some(status) = status {
+      item["status"] = json!(status);
+    }
+    Ok(item)
+  }
+
+  /// The item with `changes` applied, checked against the others
+  fn updated(&self, id: u64, index: usize, changes: &ItemPatchReq, config: &Config) -> Result<Value, OpError> {
+    let mut item = self.items[index].clone();
+    self.apply_changes(Some(id), &mut item, changes, config)?;
+    Ok(item)
+  }
+
+  fn apply_changes(&self, id: Option<u64>, item: &mut Value, changes: &ItemPatchReq, config: &Config) -> Result<(), OpError> {
+    if let Some(name) = &changes.name {
+      let name = name_to_store(&self.items, parent_of(item), name)
+        .map_err(|e| OpError::new(StatusCode::BAD_REQUEST, "Invalid variant", &e))?;
+      if let Some(name) = name {
+        match NameIndex::build(&self.items, &config.name_policy).find(name) {
+          Some(existing_id) if Some(existing_id) != id => return Err(name_conflict(existing_id)),
+          _ => {}
+        }
+        item["name"] = json!(name);
+      }
+    }
+    classify(&self.categories, item, changes.category_ids.as_deref(), changes.attributes.as_ref())?;
+    if let Some(codes) = &changes.barcodes {
+      let barcodes = check_barcodes(&self.items, id, codes)?;
+      set_barcodes(item, &barcodes);
+    }
+    Ok(())
+  }
+
+  /// Sets what an import row has besides the fields of `PATCH /items/:id`.
+  /// The status is set last, only once nothing can fail anymore.
+  fn import_fields(&mut self, id: Option<u64>, item: &mut Value, record: &ImportRecord, config: &Config, actor: &Option<String>) -> Result<(), OpError> {
+    let invalid = |error: &str, msg: &str| OpError::new(StatusCode::BAD_REQUEST, error, msg);
+
+    if let Some(description) = &record.description {
+      item["description"] = json!(description);
+    }
+    if let Some(public_id) = &record.public_id {
+      // Only digits would be read as the numeric id
+      if public_id.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid("Invalid public id", "public_id can't be only digits"))
+      }
+      self.check_unused(id, "public_id", public_id)?;
+      item["public_id"] = json!(public_id);
+    }
+    if let Some(slug) = &record.slug {
+      let valid = slug.chars().all(|c| (c.is_alphanumeric() && !c.is_uppercase()) || c == '-')
+        && !slug.chars().all(|c| c.is_ascii_digit());
+      if !valid {
+        return Err(invalid("Invalid slug", &format!("{} is not a valid slug, use lower case letters, digits and -", slug)))
+      }
+      self.check_unused(id, "slug", slug)?;
+      item["slug"] = json!(slug);
+    }
+    if let Some(release_date) = &record.release_date {
+      match DateTime::parse_from_rfc3339(release_date) {
+        Ok(res) => item["release_date"] = json!(res.with_timezone(&Utc).to_rfc3339()),
+        Err(_e) => return Err(invalid("Invalid release date", "release_date must be an RFC 3339 time")),
+      }
+    }
+    if let Some(tags) = &record.tags {
+      let tags = tags
+        .iter()
+        .map(|tag| normalize_tag(tag))
+        .collect::<Result<Vec<String>, String>>()
+        .map_err(|e| invalid("Invalid tag", &e))?;
+      set_tags(item, tags);
+      if tags_of(item).len() > MAX_TAGS {
+        return Err(invalid("Invalid tag", &format!("Items can have at most {} tags", MAX_TAGS)))
+      }
+    }
+    if let Some(translations) = &record.translations {
+      self.set_translations(id, item, translations, config)?;
+    }
+
+    if let Some(to) = record.status {
+      let from = status_of(item);
+      match id {
+        Some(id) if to != from => {
+          if !from.can_become(to) {
+            return Err(OpError::from_json(StatusCode::CONFLICT, json!({
+              "error": "Invalid transition",
+              "msg": format!("A {} item can't become {}", from.as_str(), to.as_str()),
+              "next": from.next()
+            })))
+          }
+          transition(&mut self.transitions, id, item, to, Some("Import".to_string()), actor.clone());
+        }
+        Some(_id) => {}
+        None => item["status"] = json!(to),
+      }
+    }
+    Ok(())
+  }
+
+  /// Replaces every translation, names stay unique across every locale
+  fn set_translations(&self, id: Option<u64>, item: &mut Value, translations: &Map<String, Value>, config: &Config) -> Result<(), OpError> {
+    let invalid = |msg: &str| OpError::new(StatusCode::BAD_REQUEST, "Invalid translation", msg);
+    let name_index = NameIndex::build(&self.items, &config.name_policy);
+
+    if let Some(fields) = item.as_object_mut() {
+      fields.remove("translations");
+    }
+    for (locale, texts) in translations.iter() {
+      let locale = normalize_locale(locale)
+        .ok_or_else(|| OpError::new(StatusCode::BAD_REQUEST, "Invalid locale", "Use a language tag like en, ja or zh-Hant"))?;
+      let texts = texts.as_object().ok_or_else(|| invalid("Translations must be objects"))?;
+      for (field, text) in texts.iter() {
+        if !TEXT_FIELDS.contains(&field.as_str()) {
+          return Err(invalid(&format!("{} can't be translated, use {}", field, TEXT_FIELDS.join(", "))))
+        }
+        if !text.is_string() {
+          return Err(invalid(&format!("{} must be text", field)))
+        }
+      }
+      if let Some(name) = texts.get("name").and_then(|n| n.as_str()) {
+        match name_index.find(name) {
+          Some(existing_id) if Some(existing_id) != id => return Err(name_conflict(existing_id)),
+          _ => {}
+        }
+      }
+      set_translation(item, &locale, texts.clone());
+    }
+    Ok(())
+  }
+
+  /// Fails when another item, also one in the trash, has the public id or slug
+  fn check_unused(&self, id: Option<u64>, field: &str, value: &str) -> Result<(), OpError> {
+    let owner = self.items
+      .iter()
+      .chain(self.trash.iter())
+      .find(|other| other.get(field).and_then(|v| v.as_str()) == Some(value))
+      .and_then(|other| other.get("id").and_then(|i| i.as_u64()));
+    let error = match field {
+      "slug" => "Slug already used",
+      _ => "Public id already used",
+    };
+    match owner {
+      Some(owner) if Some(owner) != id => Err(OpError {
+        id: Some(owner),
+        ..OpError::new(StatusCode::CONFLICT, error, "Please use a different one")
+      }),
+      _ => Ok(()),
+    }
+  }
+
+  /// Adds a new item with the given id when it was never handed out, the next one without.
+  /// Public ids and slugs are given unless the item brings its own.
+  fn insert(&mut self, mut item: Value, id: Option<u64>, config: &Config, actor: &Option<String>) -> Result<Value, OpError> {
+    let id = self.take_id(id)?;
+    item["id"] = json!(id);
+    if item.get("public_id").is_none() {
+      if let Some(public_id) = new_public_id(config.public_ids) {
+        item["public_id"] = json!(public_id);
+      }
+    }
+    // Variants have no name of their own and no slug
+    let name = item.get("name").and_then(|n| n.as_str()).map(|n| n.to_string());
+    if let (true, None, Some(name)) = (config.slugs, item.get("slug"), name) {
+      item["slug"] = json!(new_slug(&self.items, &self.trash, &name));
+    }
+
+    self.items.push(item.clone());
+    self.versions.insert(id, 1);
+    push_revision(&mut self.history, id, 1, "create", None, Some(item.clone()), actor.clone());
+    Ok(item)
+  }
+
+  /// Stores the changed item, nothing is recorded when it is the same
+  fn replace(&mut self, id: u64, index: usize, item: Value, actor: &Option<String>) -> Value {
+    if item == self.items[index] {
+      return item
+    }
+
+    let before = std::mem::replace(&mut self.items[index], item.clone());
+    let revision = bump_version(&mut self.versions, id);
+    push_revision(&mut self.history, id, revision, "update", Some(before), Some(item.clone()), actor.clone());
+    item
+  }
+
+  /// Soft delete, the item moves to the trash until it is purged
+  pub(crate) fn delete(&mut self, key: &str, version: Option<u64>, actor: &Option<String>) -> Result<(StatusCode, Value), OpError> {
+    let (id, index) = self.find(key)?;
+    self.check_version(id, version)?;
+
+    if let Some(blocker) = delete_blocker(&self.items, id) {
+      return Err(OpError::from_json(StatusCode::CONFLICT, blocker.to_json()))
+    }
+
+    let before = self.items.remove(index);
+    let mut deleted = before.clone();
+    deleted["deleted_at"] = json!(Utc::now().to_rfc3339());
+    deleted["deleted_by"] = json!(actor);
+    self.trash.push(deleted);
+    retire_id(&mut self.sequence, id);
+
+    // Bumped rather than dropped so that ETags from before the delete
+    // don't match the item once it is restored
+    let revision = bump_version(&mut self.versions, id);
+    push_revision(&mut self.history, id, revision, "delete", Some(before), None, actor.clone());
+    Ok((StatusCode::OK, json!({ "id": id })))
+  }
+
+  /// The given id when it was never handed out, the next one without
+  fn take_id(&mut self, id: Option<u64>) -> Result<u64, OpError> {
+    match id {
+      None => Ok(next_id(&self.items, &mut self.sequence)),
+      Some(id) if claim_id(&self.items, &mut self.sequence, id) => Ok(id),
+      Some(id) => Err(OpError {
+        id: Some(id),
+        ..OpError::new(StatusCode::CONFLICT, "Id already used", "Ids of deleted items are never given again")
+      }),
+    }
+  }
+
+  /// Id and position of the item addressed by `key`
+  pub(crate) fn find(&self, key: &str) -> Result<(u64, usize), OpError> {
+    resolve_id(&self.items, key)
+      .and_then(|id| {
+        self.items
+          .iter()
+          .position(|item| item.get("id").and_then(|i| i.as_u64()) == Some(id))
+          .map(|index| (id, index))
+      })
+      .ok_or_else(|| OpError::new(StatusCode::NOT_FOUND, "Not found", "Item does not exist"))
+  }
+
+  fn check_version(&self, id: u64, version: Option<u64>) -> Result<(), OpError> {
+    match version {
+      Some(version) if version != self.version(id) => Err(OpError::new(
+        StatusCode::PRECONDITION_FAILED,
+        "Precondition failed",
+        "Item was modified by someone else, please reload it",
+      )),
+      _ => Ok(()),
+    }
+  }
+}
+
+/// The fields of `PATCH /items/:id` the row sets
+fn patch_of(record: &ImportRecord) -> ItemPatchReq {
+  ItemPatchReq {
+    name: record.name.clone(),
+    category_ids: record.category_ids.clone(),
+    attributes: record.attributes.clone(),
+    barcodes: record.barcodes.clone(),
+  }
+}
+
+fn check_start(status: ItemStatus) -> Result<(), OpError> {
+  match status.can_start_as() {
+    true => Ok(()),
+    false => Err(OpError::new(
+      StatusCode::BAD_REQUEST,
+      "Invalid status",
+      &format!("New items start as draft or announced, not {}", status.as_str()),
+    )),
+  }
+}
+
+fn name_conflict(existing_id: u64) -> OpError {
+  OpError {
+    id: Some(existing_id),
+    ..OpError::new(StatusCode::CONFLICT, "Item already exists", "Please use a different name")
+  }
+}
+
+/// Puts the item in the categories and gives it the attributes, whichever is
+/// given, then checks the attributes against the schema of its categories
+fn classify(
+  categories: &Categories,
+  item: &mut Value,
+  category_ids: Option<&[u64]>,
+  attributes: Option<&Map<String, Value>>
+) -> Result<(), OpError> {
+  if category_ids.is_none() && attributes.is_none() {
+    return Ok(())
+  }
+
+  let fields = match item.as_object_mut() {
+    Some(res) => res,
+    None => return Ok(())
+  };
+  if let Some(category_ids) = category_ids {
+    let mut category_ids = category_ids.to_vec();
+    category_ids.sort();
+    category_ids.dedup();
+    if let Some(missing) = category_ids.iter().find(|c| categories.get(**c).is_none()) {
+      return Err(OpError::new(StatusCode::BAD_REQUEST, "Invalid category", &format!("Category {} does not exist", missing)))
+    }
+    match category_ids.is_empty() {
+      true => fields.remove("category_ids"),
+      false => fields.insert("category_ids".to_string(), json!(category_ids)),
+    };
+  }
+  if let Some(attributes) = attributes {
+    match attributes.is_empty() {
+      true => fields.remove("attributes"),
+      false => fields.insert("attributes".to_string(), json!(attributes)),
+    };
+  }
+
+  check_item_attributes(categories, item).map_err(|errors| OpError::from_json(StatusCode::BAD_REQUEST, json!({
+    "error": "Invalid attributes",
+    "msg": "The attributes don't match the schema of the item's categories",
+    "errors": errors
+  })))
+}
+
+/// Normalizes the barcodes of the item with id `id` and checks that no other
+/// item has them
+pub(crate) fn check_barcodes(items: &[Value], id: Option<u64>, codes: &[String]) -> Result<Vec<String>, OpError> {
+  let barcodes = parse_barcodes(codes).map_err(|msg| OpError::new(StatusCode::BAD_REQUEST, "Invalid barcode", &msg))?;
+
+  let barcode_index = BarcodeIndex::build(items);
+  for barcode in barcodes.iter() {
+    match barcode_index.find(barcode) {
+      Some(existing_id) if Some(existing_id) != id => {
+        return Err(OpError {
+          id: Some(existing_id),
+          ..OpError::new(StatusCode::CONFLICT, "Barcode already in use", &format!("{} belongs to another item", barcode))
+        })
+      }
+      _ => {}
+    }
+  }
+  Ok(barcodes)
+}
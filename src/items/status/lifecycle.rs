@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use crate::items::etag::{bump_version, read_versions, write_versions};
+use crate::items::history::{push_revision, read_history, write_history};
+use crate::store::{data_lock, read_json, sidecar_path, write_json};
+
+/// Where an item is in its life, items without a status are treated as released
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ItemStatus {
+  Draft,
+  Announced,
+  Preorder,
+  Released,
+  Discontinued,
+}
+
+impl ItemStatus {
+  pub(crate) fn as_str(self) -> &'static str {
+    match self {
+      ItemStatus::Draft => "draft",
+      ItemStatus::Announced => "announced",
+      ItemStatus::Preorder => "preorder",
+      ItemStatus::Released => "released",
+      ItemStatus::Discontinued => "discontinued",
+    }
+  }
+
+  /// Items only move forward, preorders can be skipped and unreleased
+  /// items can be cancelled by discontinuing them
+  pub(crate) fn can_become(self, to: ItemStatus) -> bool {
+    use ItemStatus::*;
+    matches!(
+      (self, to),
+      (Draft, Announced)
+        | (Announced, Preorder)
+        | (Announced, Released)
+        | (Announced, Discontinued)
+        | (Preorder, Released)
+        | (Preorder, Discontinued)
+        | (Released, Discontinued)
+    )
+  }
+
+  /// New items start as a draft or announced, later statuses are only reached
+  /// through transitions. Items created without a status are released.
+  pub(crate) fn can_start_as(self) -> bool {
+    self == ItemStatus::Draft || ItemStatus::Draft.can_become(self)
+  }
+
+  /// Statuses the item can move to next
+  pub(crate) fn next(self) -> Vec<ItemStatus> {
+    use ItemStatus::*;
+    [Draft, Announced, Preorder, Released, Discontinued]
+      .into_iter()
+      .filter(|to| self.can_become(*to))
+      .collect()
+  }
+}
+
+pub(crate) fn status_of(item: &Value) -> ItemStatus {
+  item
+    .get("status")
+    .and_then(|s| serde_json::from_value(s.clone()).ok())
+    .unwrap_or(ItemStatus::Released)
+}
+
+/// One change of status, `actor` is `None` for the release schedule
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct Transition {
+  pub from: ItemStatus,
+  pub to: ItemStatus,
+  pub note: Option<String>,
+  pub actor: Option<String>,
+  pub at: String,
+  /// Item that made the transition, when that item was merged into this one
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub merged_from: Option<u64>,
+}
+
+/// Transitions per item id, oldest first
+pub(crate) type Transitions = BTreeMap<u64, Vec<Transition>>;
+
+pub(crate) fn read_transitions(data_path: &str) -> std::io::Result<Transitions> {
+  read_json(&sidecar_path(data_path, "transitions"))
+}
+
+pub(crate) fn write_transitions(data_path: &str, transitions: &Transitions) -> std::io::Result<()> {
+  write_json(&sidecar_path(data_path, "transitions"), transitions)
+}
+
+/// Sets the status of the item and records the transition
+pub(crate) fn transition(
+  transitions: &mut Transitions,
+  id: u64,
+  item: &mut Value,
+  to: ItemStatus,
+  note: Option<String>,
+  actor: Option<String>,
+) {
+  let from = status_of(item);
+  item["status"] = json!(to);
+  transitions.entry(id).or_default().push(Transition {
+    from,
+    to,
+    note,
+    actor,
+    at: Utc::now().to_rfc3339(),
+    merged_from: None,
+  });
+}
+
+/// Moves the transitions of a merged item to the item it was merged into,
+/// marked with the id that made them
+pub(crate) fn move_transitions(transitions: &mut Transitions, from: u64, into: u64) {
+  let moved = match transitions.remove(&from) {
+    Some(res) => res,
+    None => return,
+  };
+  let own = transitions.entry(into).or_default();
+  own.extend(moved.into_iter().map(|t| Transition { merged_from: t.merged_from.or(Some(from)), ..t }));
+  own.sort_by(|a, b| a.at.cmp(&b.at));
+}
+
+/// Gives `item` the status and release date of `current`, the lifecycle only
+/// moves through transitions, e.g. reverting an item doesn't undo a release
+pub(crate) fn keep_lifecycle(item: &mut Value, current: &Value) {
+  for field in ["status", "release_date"] {
+    match current.get(field) {
+      Some(value) => item[field] = value.clone(),
+      None => {
+        if let Some(fields) = item.as_object_mut() {
+          fields.remove(field);
+        }
+      }
+    }
+  }
+}
+
+/// Announced and preordered items whose release date has come
+fn due_for_release(item: &Value, now: DateTime<Utc>) -> bool {
+  let release_date = item
+    .get("release_date")
+    .and_then(|d| d.as_str())
+    .and_then(|d| DateTime::parse_from_rfc3339(d).ok());
+  let status = status_of(item);
+  release_date.is_some_and(|release_date| release_date <= now)
+    && status.can_become(ItemStatus::Released)
+}
+
+/// Releases the items whose release date has come, returns how many were released
+pub async fn run_release_schedule(data_path: &str) -> std::io::Result<usize> {
+  let _guard = data_lock(data_path).write_owned().await;
+
+  let mut items: Vec<Value> = read_json(data_path)?;
+  let now = Utc::now();
+  let mut released = Vec::new();
+  let mut transitions = read_transitions(data_path)?;
+  for item in items.iter_mut() {
+    let id = match item.get("id").and_then(|i| i.as_u64()) {
+      Some(res) if due_for_release(item, now) => res,
+      _ => continue,
+    };
+    let before = item.clone();
+    transition(&mut transitions, id, item, ItemStatus::Released, Some("Release date".to_string()), None);
+    released.push((id, before, item.clone()));
+  }
+  if released.is_empty() {
+    return Ok(0)
+  }
+
+  write_json(data_path, &items)?;
+  write_transitions(data_path, &transitions)?;
+  let mut versions = read_versions(data_path)?;
+  let mut history = read_history(data_path)?;
+  for (id, before, after) in released.iter() {
+    let version = bump_version(&mut versions, *id);
+    push_revision(&mut history, *id, version, "release", Some(before.clone()), Some(after.clone()), None);
+  }
+  write_versions(data_path, &versions)?;
+  write_history(data_path, &history)?;
+  Ok(released.len())
+}
@@ -0,0 +1,133 @@
+use chrono::{DateTime, Utc};
+use poem::web::{Data, Path};
+use poem::{handler, http::StatusCode, web::Json, Request, Response, Result};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use super::etag::{if_match, item_etag, read_versions, version_of, with_etag};
+use super::{actor, find_item, item_not_found, precondition_failed, save_item_change};
+use crate::{error_response_json, response_json, ErrorResponse};
+pub use lifecycle::run_release_schedule;
+pub(crate) use lifecycle::{keep_lifecycle, move_transitions, read_transitions, status_of, transition, write_transitions, ItemStatus, Transitions};
+
+mod lifecycle;
+
+#[handler]
+pub(crate) async fn get_item_status(req: &Request, key: Path<String>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let (id, index) = match find_item(items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let transitions = match read_transitions(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_item_status 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let status = status_of(&items[index]);
+  Ok(response_json(StatusCode::OK, json!({
+    "item_id": id,
+    "status": status,
+    "release_date": items[index].get("release_date"),
+    "next": status.next(),
+    "transitions": transitions.get(&id).cloned().unwrap_or_default()
+  })))
+}
+
+/// Moves the item to another status, only the transitions of the lifecycle
+/// are allowed. A release date makes the release schedule release it.
+#[handler]
+pub(crate) async fn put_item_status(req: &Request, key: Path<String>, status_req: Json<ItemStatusReq>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let mut items = req.extensions().get::<Vec<Value>>().unwrap().clone();
+
+  let (id, index) = match find_item(&items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let versions = match read_versions(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error put_item_status 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let etag = item_etag(version_of(&versions, id));
+  if !if_match(req.headers(), &etag) {
+    return Err(precondition_failed())
+  }
+
+  let release_date = match &status_req.release_date {
+    Some(date) => match DateTime::parse_from_rfc3339(date) {
+      Ok(res) => Some(res.with_timezone(&Utc).to_rfc3339()),
+      Err(_e) => {
+        return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+          error: "Invalid release date".to_string(),
+          msg: "release_date must be an RFC 3339 time".to_string()
+        }))
+      }
+    },
+    None => None
+  };
+
+  let current = status_of(&items[index]);
+  if status_req.status != current && !current.can_become(status_req.status) {
+    return Err(error_response_json(StatusCode::CONFLICT, json!({
+      "error": "Invalid transition",
+      "msg": format!("A {} item can't become {}", current.as_str(), status_req.status.as_str()),
+      "next": current.next()
+    })))
+  }
+
+  let mut transitions = match read_transitions(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error put_item_status 2".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let before = items[index].clone();
+  if status_req.status != current {
+    transition(&mut transitions, id, &mut items[index], status_req.status, status_req.note.clone(), actor(req));
+  }
+  if let Some(release_date) = release_date {
+    items[index]["release_date"] = json!(release_date);
+  }
+
+  let after = items[index].clone();
+  if after == before {
+    return Ok(with_etag(response_json(StatusCode::OK, &after), &etag))
+  }
+
+  if write_transitions(data_path.as_str(), &transitions).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error put_item_status 3".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+  let version = save_item_change(req, &items, Some(before), &after, "status", data_path.as_str(), "put_item_status")?;
+  Ok(with_etag(response_json(StatusCode::OK, &after), &item_etag(version)))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ItemStatusReq {
+  status: ItemStatus,
+  /// RFC 3339 time the item is released by the release schedule
+  release_date: Option<String>,
+  note: Option<String>,
+}
@@ -0,0 +1,423 @@
+use std::collections::BTreeMap;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use crate::items::bundles::bundles_above;
+use crate::items::etag::{bump_version, read_versions, write_versions};
+use crate::store::{data_lock, read_json, sidecar_path, write_json};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Reason {
+  /// Goods arrived, adds to the stock
+  Receipt,
+  /// Goods left with an order, takes from the stock
+  Sale,
+  /// Goods can't be sold anymore, takes from the stock
+  Damage,
+  /// Stock count didn't match, the quantity may be negative
+  Correction,
+}
+
+/// One change of the stock of an item in a warehouse, the ledger is never rewritten
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct LedgerEntry {
+  pub entry: u64,
+  pub item_id: u64,
+  pub warehouse: String,
+  pub reason: Reason,
+  pub change: i64,
+  /// On hand in the warehouse after the change
+  pub on_hand: u64,
+  pub note: Option<String>,
+  pub actor: Option<String>,
+  pub at: String,
+}
+
+/// Longest a reservation can hold units, checkouts taking longer have to reserve again
+pub(crate) const MAX_RESERVATION_TTL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ReservationStatus {
+  /// Holds the units until it is confirmed, released or expires
+  Active,
+  /// The units were sold and taken from the stock
+  Confirmed,
+  Released,
+  Expired,
+}
+
+/// Units of an item held in a warehouse during checkout, they are not
+/// available to others but stay on hand until the reservation is confirmed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct Reservation {
+  pub id: u64,
+  pub item_id: u64,
+  pub warehouse: String,
+  pub quantity: u64,
+  pub status: ReservationStatus,
+  pub actor: Option<String>,
+  pub created_at: String,
+  pub expires_at: String,
+}
+
+impl Reservation {
+  /// Active and not past its expiry, the sweeper may not have caught up yet
+  pub(crate) fn holds(&self, now: DateTime<Utc>) -> bool {
+    self.status == ReservationStatus::Active
+      && DateTime::parse_from_rfc3339(&self.expires_at).is_ok_and(|expires_at| expires_at > now)
+  }
+}
+
+pub(crate) enum AdjustError {
+  Invalid(String),
+  Insufficient(u64),
+  /// Taking the units would leave less on hand than active reservations hold
+  Reserved { on_hand: u64, reserved: u64 },
+}
+
+pub(crate) enum ReservationError {
+  Invalid(String),
+  Insufficient(u64),
+  NotFound,
+  NotActive(ReservationStatus),
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(crate) struct Inventory {
+  /// On hand quantity per item and warehouse
+  stock: BTreeMap<u64, BTreeMap<String, u64>>,
+  /// Available quantity at or below which an item is low on stock
+  thresholds: BTreeMap<u64, u64>,
+  ledger: Vec<LedgerEntry>,
+  #[serde(default)]
+  reservations: Vec<Reservation>,
+  /// Highest ledger entry given, entries of purged items leave gaps
+  #[serde(default)]
+  last_entry: u64,
+  /// Highest reservation id given, ids are never given again
+  #[serde(default)]
+  last_reservation_id: u64,
+}
+
+impl Inventory {
+  /// Items that never had stock or a threshold show no stock at all
+  pub(crate) fn tracks(&self, id: u64) -> bool {
+    self.stock.contains_key(&id) || self.thresholds.contains_key(&id)
+  }
+
+  pub(crate) fn on_hand(&self, id: u64) -> u64 {
+    self.stock.get(&id).map(|warehouses| warehouses.values().sum()).unwrap_or(0)
+  }
+
+  /// On hand minus the units held by reservations
+  pub(crate) fn available(&self, id: u64) -> u64 {
+    self.on_hand(id).saturating_sub(self.reserved(id, None))
+  }
+
+  /// Units held by reservations, in one warehouse or in all of them
+  pub(crate) fn reserved(&self, id: u64, warehouse: Option<&str>) -> u64 {
+    let now = Utc::now();
+    self.reservations
+      .iter()
+      .filter(|r| r.item_id == id && warehouse.is_none_or(|w| r.warehouse == w) && r.holds(now))
+      .map(|r| r.quantity)
+      .sum()
+  }
+
+  fn on_hand_in(&self, id: u64, warehouse: &str) -> u64 {
+    self.stock.get(&id).and_then(|w| w.get(warehouse)).copied().unwrap_or(0)
+  }
+
+  /// Same as `available` for one warehouse
+  pub(crate) fn available_in_warehouse(&self, id: u64, warehouse: &str) -> u64 {
+    self.on_hand_in(id, warehouse).saturating_sub(self.reserved(id, Some(warehouse)))
+  }
+
+  pub(crate) fn threshold(&self, id: u64) -> Option<u64> {
+    self.thresholds.get(&id).copied()
+  }
+
+  pub(crate) fn set_threshold(&mut self, id: u64, threshold: Option<u64>) {
+    match threshold {
+      Some(threshold) => self.thresholds.insert(id, threshold),
+      None => self.thresholds.remove(&id),
+    };
+  }
+
+  pub(crate) fn is_low(&self, id: u64) -> bool {
+    self.threshold(id).is_some_and(|threshold| self.available(id) <= threshold)
+  }
+
+  pub(crate) fn ledger_of(&self, id: u64) -> impl Iterator<Item = &LedgerEntry> {
+    self.ledger.iter().filter(move |e| e.item_id == id)
+  }
+
+  /// Items that have a threshold and are at or below it
+  pub(crate) fn low_stock(&self) -> Vec<u64> {
+    self.thresholds.keys().copied().filter(|id| self.is_low(*id)).collect()
+  }
+
+  fn next_entry(&mut self) -> u64 {
+    self.last_entry += 1;
+    self.last_entry
+  }
+
+  fn next_reservation_id(&mut self) -> u64 {
+    self.last_reservation_id += 1;
+    self.last_reservation_id
+  }
+
+  /// Changes the stock and records it in the ledger. Receipts, sales and
+  /// damage take a positive quantity, the reason gives the direction.
+  pub(crate) fn adjust(
+    &mut self,
+    id: u64,
+    warehouse: &str,
+    reason: Reason,
+    quantity: i64,
+    note: Option<String>,
+    actor: Option<String>,
+  ) -> Result<LedgerEntry, AdjustError> {
+    let warehouse = warehouse.trim();
+    if warehouse.is_empty() {
+      return Err(AdjustError::Invalid("warehouse must not be empty".to_string()))
+    }
+
+    let change = match reason {
+      Reason::Receipt if quantity > 0 => quantity,
+      Reason::Sale | Reason::Damage if quantity > 0 => -quantity,
+      Reason::Correction if quantity != 0 => quantity,
+      Reason::Correction => return Err(AdjustError::Invalid("quantity must not be 0".to_string())),
+      _ => return Err(AdjustError::Invalid("quantity must be positive".to_string())),
+    };
+
+    let current = self.on_hand_in(id, warehouse);
+    let on_hand = match current.checked_add_signed(change) {
+      Some(res) => res,
+      None => return Err(AdjustError::Insufficient(current)),
+    };
+    let reserved = self.reserved(id, Some(warehouse));
+    if change < 0 && on_hand < reserved {
+      return Err(AdjustError::Reserved { on_hand: current, reserved })
+    }
+    self.stock.entry(id).or_default().insert(warehouse.to_string(), on_hand);
+
+    let entry = LedgerEntry {
+      entry: self.next_entry(),
+      item_id: id,
+      warehouse: warehouse.to_string(),
+      reason,
+      change,
+      on_hand,
+      note,
+      actor,
+      at: Utc::now().to_rfc3339(),
+    };
+    self.ledger.push(entry.clone());
+    Ok(entry)
+  }
+
+  /// Stock of the item across warehouses
+  pub(crate) fn stock_json(&self, id: u64) -> Value {
+    json!({
+      "item_id": id,
+      "warehouses": self.stock.get(&id).cloned().unwrap_or_default(),
+      "on_hand": self.on_hand(id),
+      "reserved": self.reserved(id, None),
+      "available": self.available(id),
+      "low_stock_threshold": self.threshold(id),
+      "low_stock": self.is_low(id),
+    })
+  }
+
+  /// Holds units in the warehouse until `ttl` has passed
+  pub(crate) fn reserve(
+    &mut self,
+    id: u64,
+    warehouse: &str,
+    quantity: u64,
+    ttl: Duration,
+    actor: Option<String>,
+  ) -> Result<Reservation, ReservationError> {
+    let warehouse = warehouse.trim();
+    if warehouse.is_empty() {
+      return Err(ReservationError::Invalid("warehouse must not be empty".to_string()))
+    }
+    if quantity == 0 {
+      return Err(ReservationError::Invalid("quantity must be positive".to_string()))
+    }
+
+    let available = self.available_in_warehouse(id, warehouse);
+    if quantity > available {
+      return Err(ReservationError::Insufficient(available))
+    }
+
+    let now = Utc::now();
+    let reservation = Reservation {
+      id: self.next_reservation_id(),
+      item_id: id,
+      warehouse: warehouse.to_string(),
+      quantity,
+      status: ReservationStatus::Active,
+      actor,
+      created_at: now.to_rfc3339(),
+      expires_at: (now + ttl).to_rfc3339(),
+    };
+    self.reservations.push(reservation.clone());
+    Ok(reservation)
+  }
+
+  pub(crate) fn reservation(&self, reservation_id: u64) -> Option<&Reservation> {
+    self.reservations.iter().find(|r| r.id == reservation_id)
+  }
+
+  pub(crate) fn reservations_of(&self, id: u64) -> impl Iterator<Item = &Reservation> {
+    self.reservations.iter().filter(move |r| r.item_id == id)
+  }
+
+  /// Ends an active reservation, confirming takes the units from the stock as a sale
+  pub(crate) fn end_reservation(
+    &mut self,
+    reservation_id: u64,
+    status: ReservationStatus,
+    actor: Option<String>,
+  ) -> Result<Reservation, ReservationError> {
+    let now = Utc::now();
+    let index = match self.reservations.iter().position(|r| r.id == reservation_id) {
+      Some(res) => res,
+      None => return Err(ReservationError::NotFound),
+    };
+
+    let reservation = self.reservations[index].clone();
+    if !reservation.holds(now) {
+      return match reservation.status {
+        ReservationStatus::Active => Err(ReservationError::NotActive(ReservationStatus::Expired)),
+        other => Err(ReservationError::NotActive(other)),
+      }
+    }
+
+    // Ended first so the sale isn't held back by the units it takes
+    self.reservations[index].status = status;
+    if status == ReservationStatus::Confirmed {
+      let note = Some(format!("Reservation {}", reservation.id));
+      let sale = self.adjust(reservation.item_id, &reservation.warehouse, Reason::Sale, reservation.quantity as i64, note, actor);
+      if let Err(AdjustError::Insufficient(on_hand) | AdjustError::Reserved { on_hand, .. }) = sale {
+        self.reservations[index].status = reservation.status;
+        return Err(ReservationError::Insufficient(on_hand))
+      }
+    }
+
+    Ok(self.reservations[index].clone())
+  }
+
+  /// Moves the stock, threshold and active reservations of an item merged into
+  /// another. The ledger is not rewritten, both sides get a correction.
+  /// Fails when the stock doesn't fit in the item it moves to, the inventory
+  /// is then not to be saved.
+  pub(crate) fn merge_item(&mut self, from: u64, into: u64, actor: Option<String>) -> Result<(), String> {
+    let stock = self.stock.get(&from).cloned().unwrap_or_default();
+    for (warehouse, quantity) in stock.iter() {
+      let fits = i64::try_from(*quantity).is_ok() && self.on_hand_in(into, warehouse).checked_add(*quantity).is_some();
+      if !fits {
+        return Err(format!("the stock in {} doesn't fit in item {}", warehouse, into))
+      }
+    }
+
+    // Moved first, the stock they hold goes with them below
+    let now = Utc::now();
+    for reservation in self.reservations.iter_mut().filter(|r| r.item_id == from && r.holds(now)) {
+      reservation.item_id = into;
+    }
+
+    for (warehouse, quantity) in stock {
+      if quantity == 0 {
+        continue;
+      }
+      let moved = self
+        .adjust(from, &warehouse, Reason::Correction, -(quantity as i64), Some(format!("Merged into item {}", into)), actor.clone())
+        .and_then(|_| self.adjust(into, &warehouse, Reason::Correction, quantity as i64, Some(format!("Merged from item {}", from)), actor.clone()));
+      if moved.is_err() {
+        return Err(format!("the stock in {} can't be moved to item {}", warehouse, into))
+      }
+    }
+    self.stock.remove(&from);
+
+    if let Some(threshold) = self.thresholds.remove(&from) {
+      self.thresholds.entry(into).or_insert(threshold);
+    }
+    Ok(())
+  }
+
+  /// Forgets everything about an item that was purged, its ledger included
+  pub(crate) fn remove_item(&mut self, id: u64) {
+    self.stock.remove(&id);
+    self.thresholds.remove(&id);
+    self.ledger.retain(|e| e.item_id != id);
+    self.reservations.retain(|r| r.item_id != id);
+  }
+
+  /// Marks reservations past their expiry as expired, returns the items they were for
+  pub(crate) fn expire_reservations(&mut self) -> Vec<u64> {
+    let now = Utc::now();
+    let mut item_ids = Vec::new();
+    for reservation in self.reservations.iter_mut() {
+      if reservation.status == ReservationStatus::Active && !reservation.holds(now) {
+        reservation.status = ReservationStatus::Expired;
+        item_ids.push(reservation.item_id);
+      }
+    }
+    item_ids
+  }
+
+  /// Adds `available` and `low_stock` to the item when its stock is tracked
+  pub(crate) fn decorate(&self, item: &mut Value) {
+    let id = match item.get("id").and_then(|i| i.as_u64()) {
+      Some(res) => res,
+      None => return,
+    };
+    if self.tracks(id) {
+      item["available"] = json!(self.available(id));
+      item["low_stock"] = json!(self.is_low(id));
+    }
+  }
+}
+
+pub(crate) fn read_inventory(data_path: &str) -> std::io::Result<Inventory> {
+  read_json(&sidecar_path(data_path, "inventory"))
+}
+
+pub(crate) fn write_inventory(data_path: &str, inventory: &Inventory) -> std::io::Result<()> {
+  write_json(&sidecar_path(data_path, "inventory"), inventory)
+}
+
+/// Expires reservations past their expiry so they stop holding stock,
+/// returns how many expired
+pub async fn expire_reservations(data_path: &str) -> std::io::Result<usize> {
+  let _guard = data_lock(data_path).write_owned().await;
+
+  let mut inventory = read_inventory(data_path)?;
+  let item_ids = inventory.expire_reservations();
+  if item_ids.is_empty() {
+    return Ok(0)
+  }
+  write_inventory(data_path, &inventory)?;
+
+  // Their available quantity changed, and with it the one of the bundles they are in
+  let items: Vec<Value> = read_json(data_path)?;
+  let mut bumped: Vec<u64> = Vec::new();
+  for id in item_ids.iter() {
+    bumped.push(*id);
+    bumped.extend(bundles_above(&items, *id));
+  }
+  bumped.sort();
+  bumped.dedup();
+  let mut versions = read_versions(data_path)?;
+  for id in bumped {
+    bump_version(&mut versions, id);
+  }
+  write_versions(data_path, &versions)?;
+  Ok(item_ids.len())
+}
@@ -0,0 +1,390 @@
+use poem::web::{Data, Path};
+use poem::{handler, http::StatusCode, web::Json, Request, Response, Result};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+use super::bundles::{bundles_above, reserve_bundle};
+use super::{actor, bump_item_version, decorate_item, find_item, item_not_found};
+use crate::{error_response_json, response_json, Config, ErrorResponse};
+pub use inventory::expire_reservations;
+pub(crate) use inventory::{read_inventory, write_inventory, AdjustError, Inventory, Reason, Reservation, ReservationError, ReservationStatus, MAX_RESERVATION_TTL};
+
+mod inventory;
+
+#[handler]
+pub(crate) async fn get_item_stock(req: &Request, key: Path<String>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let (id, _index) = match find_item(items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let inventory = match read_inventory(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_item_stock 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  Ok(response_json(StatusCode::OK, inventory.stock_json(id)))
+}
+
+/// Receives, sells, writes off or corrects stock of the item in one warehouse
+#[handler]
+pub(crate) async fn post_stock_adjustment(req: &Request, key: Path<String>, adjustment_req: Json<StockAdjustmentReq>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let (id, _index) = match find_item(items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let mut inventory = match read_inventory(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error post_stock_adjustment 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let adjustment = inventory.adjust(
+    id,
+    &adjustment_req.warehouse,
+    adjustment_req.reason,
+    adjustment_req.quantity,
+    adjustment_req.note.clone(),
+    actor(req)
+  );
+  let entry = match adjustment {
+    Ok(res) => res,
+    Err(AdjustError::Invalid(msg)) => {
+      return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+        error: "Invalid adjustment".to_string(),
+        msg
+      }))
+    }
+    Err(AdjustError::Insufficient(on_hand)) => {
+      return Err(error_response_json(StatusCode::CONFLICT, ErrorResponse {
+        error: "Insufficient stock".to_string(),
+        msg: format!("Only {} on hand in {}", on_hand, adjustment_req.warehouse.trim())
+      }))
+    }
+    Err(AdjustError::Reserved { on_hand, reserved }) => {
+      return Err(error_response_json(StatusCode::CONFLICT, ErrorResponse {
+        error: "Insufficient stock".to_string(),
+        msg: format!("{} of the {} on hand in {} are reserved", reserved, on_hand, adjustment_req.warehouse.trim())
+      }))
+    }
+  };
+
+  if write_inventory(data_path.as_str(), &inventory).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error post_stock_adjustment 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+  bump_stock_versions(req, data_path.as_str(), id, "post_stock_adjustment")?;
+
+  Ok(response_json(StatusCode::CREATED, json!({
+    "entry": entry,
+    "stock": inventory.stock_json(id)
+  })))
+}
+
+#[handler]
+pub(crate) async fn get_stock_ledger(req: &Request, key: Path<String>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let (id, _index) = match find_item(items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let inventory = match read_inventory(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_stock_ledger 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let ledger: Vec<_> = inventory.ledger_of(id).collect();
+  Ok(response_json(StatusCode::OK, ledger))
+}
+
+#[handler]
+pub(crate) async fn put_stock_threshold(req: &Request, key: Path<String>, threshold_req: Json<StockThresholdReq>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let (id, _index) = match find_item(items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let mut inventory = match read_inventory(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error put_stock_threshold 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  inventory.set_threshold(id, threshold_req.threshold);
+  if write_inventory(data_path.as_str(), &inventory).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error put_stock_threshold 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+  bump_item_version(data_path.as_str(), id, "put_stock_threshold")?;
+
+  Ok(response_json(StatusCode::OK, inventory.stock_json(id)))
+}
+
+/// Items at or below their low stock threshold, least available first
+#[handler]
+pub(crate) async fn get_low_stock(req: &Request, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let inventory = match read_inventory(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_low_stock 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let mut low: Vec<Value> = inventory
+    .low_stock()
+    .into_iter()
+    .filter_map(|id| find_item(items, &id.to_string()).map(|(_id, index)| {
+      let mut item = items[index].clone();
+      decorate_item(&mut item, items, &inventory);
+      item
+    }))
+    .collect();
+  low.sort_by_key(|item| item.get("available").and_then(|a| a.as_u64()).unwrap_or(0));
+
+  Ok(response_json(StatusCode::OK, low))
+}
+
+/// Holds units for a checkout, they stay on hand but are no longer available.
+/// For a bundle the units of its components are held, one reservation each.
+#[handler]
+pub(crate) async fn post_reservation(req: &Request, key: Path<String>, reservation_req: Json<ReservationReq>, data_path: Data<&String>, config: Data<&Config>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let (id, index) = match find_item(items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let ttl = match reservation_req.ttl_seconds {
+    Some(seconds) => std::time::Duration::from_secs(seconds),
+    None => config.reservation_ttl,
+  };
+  if ttl.is_zero() || ttl > MAX_RESERVATION_TTL {
+    return Err(error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+      error: "Invalid reservation".to_string(),
+      msg: format!("ttl_seconds must be between 1 and {}", MAX_RESERVATION_TTL.as_secs())
+    }))
+  }
+
+  let mut inventory = match read_inventory(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error post_reservation 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  // The write lock taken by AuthMiddleware keeps two checkouts from holding the same units
+  let ttl = chrono::Duration::from_std(ttl).unwrap_or(chrono::Duration::zero());
+  if items[index].get("components").is_some() {
+    let reservations = match reserve_bundle(items, &mut inventory, id, &reservation_req.warehouse, reservation_req.quantity, ttl, actor(req)) {
+      Ok(res) => res,
+      Err(e) => return Err(reservation_error(e))
+    };
+
+    if write_inventory(data_path.as_str(), &inventory).is_err() {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error post_reservation 3".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+    for reservation in reservations.iter() {
+      bump_stock_versions(req, data_path.as_str(), reservation.item_id, "post_reservation")?;
+    }
+    return Ok(response_json(StatusCode::CREATED, reservations))
+  }
+
+  let reservation = match inventory.reserve(id, &reservation_req.warehouse, reservation_req.quantity, ttl, actor(req)) {
+    Ok(res) => res,
+    Err(e) => return Err(reservation_error(e))
+  };
+
+  if write_inventory(data_path.as_str(), &inventory).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error post_reservation 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+  bump_stock_versions(req, data_path.as_str(), id, "post_reservation")?;
+
+  Ok(response_json(StatusCode::CREATED, reservation))
+}
+
+#[handler]
+pub(crate) async fn get_item_reservations(req: &Request, key: Path<String>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let (id, _index) = match find_item(items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+
+  let inventory = match read_inventory(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_item_reservations 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let reservations: Vec<_> = inventory.reservations_of(id).collect();
+  Ok(response_json(StatusCode::OK, reservations))
+}
+
+#[handler]
+pub(crate) async fn get_reservation(reservation_id: Path<u64>, data_path: Data<&String>) -> Result<Response> {
+  let inventory = match read_inventory(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_reservation 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  match inventory.reservation(*reservation_id) {
+    Some(reservation) => Ok(response_json(StatusCode::OK, reservation)),
+    None => Err(reservation_error(ReservationError::NotFound))
+  }
+}
+
+/// The checkout went through, the held units are sold
+#[handler]
+pub(crate) async fn confirm_reservation(req: &Request, reservation_id: Path<u64>, data_path: Data<&String>) -> Result<Response> {
+  end_reservation(req, *reservation_id, ReservationStatus::Confirmed, data_path.as_str(), "confirm_reservation")
+}
+
+/// The checkout was abandoned, the held units are available again
+#[handler]
+pub(crate) async fn release_reservation(req: &Request, reservation_id: Path<u64>, data_path: Data<&String>) -> Result<Response> {
+  end_reservation(req, *reservation_id, ReservationStatus::Released, data_path.as_str(), "release_reservation")
+}
+
+fn end_reservation(req: &Request, reservation_id: u64, status: ReservationStatus, data_path: &str, handler: &str) -> Result<Response> {
+  let mut inventory = match read_inventory(data_path) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: format!("Server error {} 1", handler),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let reservation = match inventory.end_reservation(reservation_id, status, actor(req)) {
+    Ok(res) => res,
+    Err(e) => return Err(reservation_error(e))
+  };
+
+  if write_inventory(data_path, &inventory).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: format!("Server error {} 2", handler),
+      msg: "Please contact support".to_string()
+    }))
+  }
+  bump_stock_versions(req, data_path, reservation.item_id, handler)?;
+
+  Ok(response_json(StatusCode::OK, reservation))
+}
+
+fn reservation_error(e: ReservationError) -> poem::Error {
+  let (status, error, msg) = match e {
+    ReservationError::Invalid(msg) => (StatusCode::BAD_REQUEST, "Invalid reservation", msg),
+    ReservationError::Insufficient(available) => (
+      StatusCode::CONFLICT,
+      "Insufficient stock",
+      format!("Only {} available in the warehouse", available)
+    ),
+    ReservationError::NotFound => (StatusCode::NOT_FOUND, "Not found", "Reservation does not exist".to_string()),
+    ReservationError::NotActive(status) => (
+      StatusCode::CONFLICT,
+      "Reservation is not active",
+      format!("Reservation is already {}", json!(status).as_str().unwrap_or(""))
+    ),
+  };
+
+  error_response_json(status, ErrorResponse {
+    error: error.to_string(),
+    msg
+  })
+}
+
+/// Bumps the version of an item whose stock changed and of the bundles it is
+/// in, their availability follows from its stock
+fn bump_stock_versions(req: &Request, data_path: &str, id: u64, handler: &str) -> Result<u64> {
+  let items = req.extensions().get::<Vec<Value>>().map(|i| i.as_slice()).unwrap_or_default();
+  for bundle_id in bundles_above(items, id) {
+    bump_item_version(data_path, bundle_id, handler)?;
+  }
+  bump_item_version(data_path, id, handler)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StockAdjustmentReq {
+  warehouse: String,
+  reason: Reason,
+  quantity: i64,
+  note: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ReservationReq {
+  warehouse: String,
+  quantity: u64,
+  /// Defaults to `Config::reservation_ttl`
+  ttl_seconds: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StockThresholdReq {
+  /// `None` stops low stock tracking for the item
+  threshold: Option<u64>,
+}
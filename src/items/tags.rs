@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+pub(crate) const MAX_TAGS: usize = 50;
+const MAX_TAG_LEN: usize = 50;
+
+/// Tags are kept lower case with dashes for spaces, e.g. "Limited Edition" is "limited-edition"
+pub(crate) fn normalize_tag(tag: &str) -> Result<String, String> {
+  let tag = tag.split_whitespace().collect::<Vec<&str>>().join("-").to_lowercase();
+
+  if tag.is_empty() || tag.chars().count() > MAX_TAG_LEN {
+    return Err(format!("Tags must be 1 to {} characters", MAX_TAG_LEN))
+  }
+  if !tag.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') || tag.starts_with('-') {
+    return Err(format!("{} is not a valid tag, use letters, digits, - and _", tag))
+  }
+  Ok(tag)
+}
+
+pub(crate) fn tags_of(item: &Value) -> Vec<String> {
+  item
+    .get("tags")
+    .and_then(|t| t.as_array())
+    .map(|tags| tags.iter().filter_map(|t| t.as_str().map(|t| t.to_string())).collect())
+    .unwrap_or_default()
+}
+
+/// Sets the tags of the item sorted, the field is dropped when there are none
+pub(crate) fn set_tags(item: &mut Value, mut tags: Vec<String>) {
+  tags.sort();
+  tags.dedup();
+  if let Some(fields) = item.as_object_mut() {
+    match tags.is_empty() {
+      true => fields.remove("tags"),
+      false => fields.insert("tags".to_string(), json!(tags)),
+    };
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct TagCount {
+  pub tag: String,
+  pub count: usize,
+}
+
+/// Every tag in use with the number of items carrying it, most used first
+pub(crate) fn tag_counts(items: &[Value]) -> Vec<TagCount> {
+  let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+  for item in items.iter() {
+    for tag in tags_of(item) {
+      *counts.entry(tag).or_default() += 1;
+    }
+  }
+
+  let mut counts: Vec<TagCount> = counts.into_iter().map(|(tag, count)| TagCount { tag, count }).collect();
+  counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+  counts
+}
+
+/// Tag filter of the item list, e.g. `tags=import|limited-edition,!preorder`.
+/// Groups separated by `,` must all match, a group matches when any of its
+/// `|` separated terms does and `!` negates a term.
+pub(crate) struct TagQuery {
+  groups: Vec<Vec<(bool, String)>>,
+}
+
+impl TagQuery {
+  pub(crate) fn parse(query: &str) -> Result<Self, String> {
+    let mut groups = Vec::new();
+    for group in query.split(',') {
+      let mut terms = Vec::new();
+      for term in group.split('|') {
+        let term = term.trim();
+        let (negated, tag) = match term.strip_prefix('!') {
+          Some(tag) => (true, tag),
+          None => (false, term),
+        };
+        terms.push((negated, normalize_tag(tag)?));
+      }
+      groups.push(terms);
+    }
+    Ok(Self { groups })
+  }
+
+  pub(crate) fn matches(&self, item: &Value) -> bool {
+    let tags = tags_of(item);
+    self.groups.iter().all(|group| {
+      group.iter().any(|(negated, tag)| tags.contains(tag) != *negated)
+    })
+  }
+}
@@ -0,0 +1,77 @@
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Case and diacritic folding used for matching item text,
+/// e.g. "Pokémon ＳＣＡＲＬＥＴ" becomes "pokemon scarlet".
+/// Kana voicing marks are kept, "ガ" and "カ" are different sounds.
+pub(crate) fn fold(text: &str) -> String {
+  let mut last_base = ' ';
+  let stripped: String = text
+    .nfkd()
+    .filter(|c| {
+      if is_combining_mark(*c) {
+        return is_cjk(last_base)
+      }
+      last_base = *c;
+      true
+    })
+    .collect();
+
+  stripped.nfc().collect::<String>().to_lowercase()
+}
+
+/// Splits folded text into words. Runs of CJK characters have no spaces,
+/// so they are indexed as single characters and overlapping bigrams.
+/// Queries only use the bigrams of a run unless it is a single character.
+pub(crate) fn tokenize(folded: &str, for_query: bool) -> Vec<String> {
+  let mut tokens = Vec::new();
+  let mut word = String::new();
+  let mut cjk_run: Vec<char> = Vec::new();
+
+  for c in folded.chars() {
+    if is_cjk(c) {
+      flush_word(&mut word, &mut tokens);
+      cjk_run.push(c);
+    } else if c.is_alphanumeric() {
+      flush_cjk(&mut cjk_run, &mut tokens, for_query);
+      word.push(c);
+    } else {
+      flush_word(&mut word, &mut tokens);
+      flush_cjk(&mut cjk_run, &mut tokens, for_query);
+    }
+  }
+  flush_word(&mut word, &mut tokens);
+  flush_cjk(&mut cjk_run, &mut tokens, for_query);
+
+  tokens
+}
+
+fn flush_word(word: &mut String, tokens: &mut Vec<String>) {
+  if !word.is_empty() {
+    tokens.push(std::mem::take(word));
+  }
+}
+
+fn flush_cjk(run: &mut Vec<char>, tokens: &mut Vec<String>, for_query: bool) {
+  if run.len() == 1 || (!for_query && !run.is_empty()) {
+    tokens.extend(run.iter().map(|c| c.to_string()));
+  }
+  for pair in run.windows(2) {
+    tokens.push(pair.iter().collect());
+  }
+  run.clear();
+}
+
+fn is_cjk(c: char) -> bool {
+  matches!(c as u32,
+    0x1100..=0x11FF     // Hangul Jamo
+    | 0x3040..=0x30FF   // Hiragana, Katakana
+    | 0x3130..=0x318F   // Hangul Compatibility Jamo
+    | 0x31F0..=0x31FF   // Katakana Phonetic Extensions
+    | 0x3400..=0x4DBF   // CJK Extension A
+    | 0x4E00..=0x9FFF   // CJK Unified Ideographs
+    | 0xAC00..=0xD7AF   // Hangul Syllables
+    | 0xF900..=0xFAFF   // CJK Compatibility Ideographs
+    | 0x20000..=0x2FFFF // CJK Extensions B onwards
+  )
+}
@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+use futures_util::stream;
+use poem::Body;
+use serde::de::{DeserializeOwned, Error as _, SeqAccess, Visitor};
+use serde::{Deserializer, Serialize, Deserialize};
+use serde_json::{json, Map, Value};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::runtime::Handle;
+
+use super::bulk::ItemKey;
+use super::status::ItemStatus;
+
+/// Rows in one import, larger catalogues are sent in parts
+pub(crate) const MAX_IMPORT_ROWS: usize = 10_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Format {
+  Csv,
+  Ndjson,
+  Json,
+}
+
+impl Format {
+  pub(crate) fn content_type(&self) -> &'static str {
+    match self {
+      Format::Csv => "text/csv",
+      Format::Ndjson => "application/x-ndjson",
+      Format::Json => "application/json",
+    }
+  }
+
+  pub(crate) fn extension(&self) -> &'static str {
+    match self {
+      Format::Csv => "csv",
+      Format::Ndjson => "ndjson",
+      Format::Json => "json",
+    }
+  }
+
+  pub(crate) fn from_content_type(content_type: &str) -> Option<Self> {
+    match content_type.split(';').next().unwrap_or("").trim() {
+      "text/csv" => Some(Format::Csv),
+      "application/x-ndjson" | "application/jsonl" => Some(Format::Ndjson),
+      "application/json" => Some(Format::Json),
+      _ => None,
+    }
+  }
+}
+
+/// Streams the items one record at a time, each record is serialized when
+/// the client is ready for it.
+/// Only the fields import sets are written so an export imports as it was,
+/// CSV columns are `IMPORTED_FIELDS` in that order with lists and objects as JSON.
+pub(crate) fn export_body(items: Vec<Value>, format: Format) -> Body {
+  let chunks: Box<dyn Iterator<Item = Vec<u8>> + Send> = match format {
+    Format::Csv => {
+      let header = csv_record(IMPORTED_FIELDS.iter().map(|c| c.to_string()));
+      let rows = items.into_iter().map(|item| {
+        csv_record(IMPORTED_FIELDS.iter().map(|c| cell_text(item.get(*c))))
+      });
+      Box::new(std::iter::once(header).chain(rows))
+    }
+    Format::Ndjson => Box::new(items
+      .into_iter()
+      .map(|item| format!("{}\n", exported(&item)).into_bytes())),
+    Format::Json => {
+      let last = items.len().saturating_sub(1);
+      let records = items
+        .into_iter()
+        .enumerate()
+        .map(move |(i, item)| format!("{}{}", exported(&item), if i == last { "" } else { "," }).into_bytes());
+      Box::new(std::iter::once(b"[".to_vec())
+        .chain(records)
+        .chain(std::iter::once(b"]".to_vec())))
+    }
+  };
+
+  Body::from_bytes_stream(stream::iter(chunks.map(Ok::<_, std::io::Error>)))
+}
+
+/// The fields of the item that import sets
+fn exported(item: &Value) -> Value {
+  let fields: Map<String, Value> = IMPORTED_FIELDS
+    .iter()
+    .filter_map(|field| item.get(*field).map(|value| (field.to_string(), value.clone())))
+    .collect();
+  Value::Object(fields)
+}
+
+fn csv_record(cells: impl Iterator<Item = String>) -> Vec<u8> {
+  let mut writer = csv::Writer::from_writer(Vec::new());
+  // Writing to memory can't fail
+  writer.write_record(cells).unwrap();
+  writer.into_inner().unwrap_or_default()
+}
+
+fn cell_text(value: Option<&Value>) -> String {
+  match value {
+    None | Some(Value::Null) => String::new(),
+    Some(Value::String(s)) => s.clone(),
+    Some(v) => v.to_string(),
+  }
+}
+
+/// A parsed row of an import, `line` is the line in the file starting at 1,
+/// for JSON the position of the record
+pub(crate) struct ImportRow {
+  pub line: usize,
+  pub record: Result<ImportRecord, String>,
+  /// Fields with a value that import doesn't set, e.g. `description`
+  pub ignored: Vec<String>,
+}
+
+/// What a row sets, items with the `id` are updated and the others created.
+/// Variants have no name of their own, only the one of their parent is accepted.
+/// Fields left empty stay as they are.
+pub(crate) struct ImportRecord {
+  pub id: Option<ItemKey>,
+  pub name: Option<String>,
+  /// Parent, SKU and options of a variant
+  pub variant: Option<(ItemKey, String, Map<String, Value>)>,
+  pub description: Option<String>,
+  pub public_id: Option<String>,
+  pub slug: Option<String>,
+  pub status: Option<ItemStatus>,
+  pub release_date: Option<String>,
+  pub tags: Option<Vec<String>>,
+  pub category_ids: Option<Vec<u64>>,
+  pub attributes: Option<Map<String, Value>>,
+  pub barcodes: Option<Vec<String>>,
+  /// Texts per locale, replaces all translations of the item
+  pub translations: Option<Map<String, Value>>,
+}
+
+/// Reads the rows of an uploaded file as they arrive, the upload is dropped
+/// as soon as it has more than `max_rows` rows
+pub(crate) async fn read_import(body: Body, format: Format, mapping: HashMap<String, String>, max_rows: usize) -> Result<Vec<ImportRow>, String> {
+  let reader = BlockingReader { reader: body.into_async_read(), handle: Handle::current() };
+  tokio::task::spawn_blocking(move || parse_import(reader, format, &mapping, max_rows))
+    .await
+    .unwrap_or_else(|_| Err("Unable to read the file".to_string()))
+}
+
+/// Reads an async body from a blocking thread, the parsers want `Read`
+struct BlockingReader<R> {
+  reader: R,
+  handle: Handle,
+}
+
+impl<R: AsyncRead + Unpin> Read for BlockingReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    self.handle.block_on(self.reader.read(buf))
+  }
+}
+
+/// Turns an uploaded file into records, `mapping` renames columns
+/// (or NDJSON and JSON keys) to item fields, e.g. `Title` to `name`.
+/// Stops with an error at the row after `max_rows`.
+pub(crate) fn parse_import(body: impl Read, format: Format, mapping: &HashMap<String, String>, max_rows: usize) -> Result<Vec<ImportRow>, String> {
+  let field_of = |column: &str| -> String {
+    match mapping.get(column) {
+      Some(field) => field.clone(),
+      None => column.trim().to_lowercase(),
+    }
+  };
+  let too_many = || format!("Send between 1 and {} rows", max_rows);
+
+  let mut rows = Vec::new();
+  match format {
+    Format::Csv => {
+      let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(body);
+      let headers: Vec<String> = match reader.headers() {
+        Ok(res) => res.iter().map(field_of).collect(),
+        Err(e) => return Err(format!("Unable to read the header: {}", e)),
+      };
+
+      for (index, record) in reader.records().enumerate() {
+        if rows.len() == max_rows {
+          return Err(too_many())
+        }
+        // Line 1 is the header
+        let line = index + 2;
+        let (record, ignored) = match record {
+          Ok(record) => {
+            let fields: HashMap<String, Value> = headers
+              .iter()
+              .zip(record.iter())
+              .map(|(field, cell)| (field.clone(), json!(cell)))
+              .collect();
+            (row_record(&fields), ignored_fields(&fields))
+          }
+          Err(e) => (Err(e.to_string()), Vec::new()),
+        };
+        rows.push(ImportRow { line, record, ignored });
+      }
+    }
+    Format::Ndjson => {
+      for (index, text) in BufReader::new(body).lines().enumerate() {
+        let text = text.map_err(|e| format!("Unable to read line {}: {}", index + 1, e))?;
+        if text.trim().is_empty() {
+          continue;
+        }
+        if rows.len() == max_rows {
+          return Err(too_many())
+        }
+        let (record, ignored) = match serde_json::from_str::<Map<String, Value>>(&text) {
+          Ok(record) => {
+            let fields = record.into_iter().map(|(key, value)| (field_of(&key), value)).collect();
+            (row_record(&fields), ignored_fields(&fields))
+          }
+          Err(e) => (Err(format!("Invalid JSON: {}", e)), Vec::new()),
+        };
+        rows.push(ImportRow { line: index + 1, record, ignored });
+      }
+    }
+    Format::Json => {
+      let mut records = Vec::new();
+      let mut deserializer = serde_json::Deserializer::from_reader(body);
+      let read = deserializer.deserialize_seq(Records { records: &mut records, max: max_rows });
+      if records.len() > max_rows {
+        return Err(too_many())
+      }
+      if let Err(e) = read.and_then(|_| deserializer.end()) {
+        return Err(format!("Invalid JSON: {}", e))
+      }
+      for (index, record) in records.into_iter().enumerate() {
+        let (record, ignored) = match record {
+          Value::Object(record) => {
+            let fields = record.into_iter().map(|(key, value)| (field_of(&key), value)).collect();
+            (row_record(&fields), ignored_fields(&fields))
+          }
+          _ => (Err("Records must be objects".to_string()), Vec::new()),
+        };
+        rows.push(ImportRow { line: index + 1, record, ignored });
+      }
+    }
+  }
+  Ok(rows)
+}
+
+/// Reads the records of a JSON array one at a time, stops after the one past `max`
+struct Records<'a> {
+  records: &'a mut Vec<Value>,
+  max: usize,
+}
+
+impl<'de> Visitor<'de> for Records<'_> {
+  type Value = ();
+
+  fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+    formatter.write_str("an array of records")
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+    while let Some(record) = seq.next_element::<Value>()? {
+      self.records.push(record);
+      if self.records.len() > self.max {
+        return Err(A::Error::custom("too many records"))
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Fields import sets and export writes, the others are reported back as ignored.
+/// Media and bundle components aren't among them, they have their own endpoints.
+pub(crate) const IMPORTED_FIELDS: [&str; 15] = [
+  "id",
+  "name",
+  "parent_id",
+  "sku",
+  "options",
+  "public_id",
+  "slug",
+  "status",
+  "release_date",
+  "description",
+  "tags",
+  "category_ids",
+  "attributes",
+  "barcodes",
+  "translations",
+];
+
+/// Fields of the row that have a value but aren't imported, in name order.
+/// Empty cells don't count, exports leave them for fields an item doesn't have.
+fn ignored_fields(fields: &HashMap<String, Value>) -> Vec<String> {
+  let mut ignored: Vec<String> = fields
+    .iter()
+    .filter(|(field, _)| !IMPORTED_FIELDS.contains(&field.as_str()))
+    .filter(|(_, value)| match value {
+      Value::Null => false,
+      Value::String(s) => !s.trim().is_empty(),
+      _ => true,
+    })
+    .map(|(field, _)| field.clone())
+    .collect();
+  ignored.sort();
+  ignored
+}
+
+fn row_record(fields: &HashMap<String, Value>) -> Result<ImportRecord, String> {
+  let name = text_field(fields, "name")?;
+  let id = key_field(fields, "id")?;
+  let parent_id = key_field(fields, "parent_id")?;
+  let sku = text_field(fields, "sku")?;
+  let options = json_field::<Map<String, Value>>(fields, "options", "a JSON object")?;
+
+  let variant = match (parent_id, sku, options) {
+    (Some(parent_id), Some(sku), Some(options)) => Some((parent_id, sku, options)),
+    (Some(_), _, _) => return Err("sku and options are required for variants".to_string()),
+    (None, None, None) => None,
+    (None, _, _) => return Err("sku and options are only set on variants, parent_id is missing".to_string()),
+  };
+  if variant.is_none() && name.is_none() {
+    return Err("name is required".to_string())
+  }
+
+  let status = match text_field(fields, "status")? {
+    Some(status) => match serde_json::from_value(json!(status.trim())) {
+      Ok(res) => Some(res),
+      Err(_) => return Err(format!("{} is not a status, use draft, announced, preorder, released or discontinued", status)),
+    },
+    None => None,
+  };
+
+  Ok(ImportRecord {
+    id,
+    name,
+    variant,
+    description: text_field(fields, "description")?,
+    public_id: text_field(fields, "public_id")?,
+    slug: text_field(fields, "slug")?,
+    status,
+    release_date: text_field(fields, "release_date")?,
+    tags: json_field(fields, "tags", "a JSON array of text")?,
+    category_ids: json_field(fields, "category_ids", "a JSON array of numbers")?,
+    attributes: json_field(fields, "attributes", "a JSON object")?,
+    barcodes: json_field(fields, "barcodes", "a JSON array of text")?,
+    translations: json_field(fields, "translations", "a JSON object")?,
+  })
+}
+
+/// Text of the field, empty cells are no value
+fn text_field(fields: &HashMap<String, Value>, field: &str) -> Result<Option<String>, String> {
+  match fields.get(field) {
+    None | Some(Value::Null) => Ok(None),
+    Some(Value::String(text)) if text.trim().is_empty() => Ok(None),
+    Some(Value::String(text)) => Ok(Some(text.clone())),
+    Some(_) => Err(format!("{} must be text", field)),
+  }
+}
+
+/// Lists and objects, CSV cells hold them as JSON
+fn json_field<T: DeserializeOwned>(fields: &HashMap<String, Value>, field: &str, expected: &str) -> Result<Option<T>, String> {
+  let value = match fields.get(field) {
+    None | Some(Value::Null) => return Ok(None),
+    Some(Value::String(text)) if text.trim().is_empty() => return Ok(None),
+    Some(Value::String(text)) => match serde_json::from_str(text) {
+      Ok(res) => res,
+      Err(_) => return Err(format!("{} must be {}", field, expected)),
+    },
+    Some(value) => value.clone(),
+  };
+  match serde_json::from_value(value) {
+    Ok(res) => Ok(Some(res)),
+    Err(_) => Err(format!("{} must be {}", field, expected)),
+  }
+}
+
+/// Numeric id, public id or slug of an item
+fn key_field(fields: &HashMap<String, Value>, field: &str) -> Result<Option<ItemKey>, String> {
+  match fields.get(field) {
+    None | Some(Value::Null) => Ok(None),
+    Some(Value::String(key)) if key.trim().is_empty() => Ok(None),
+    Some(Value::String(key)) => Ok(Some(match key.trim().parse::<u64>() {
+      Ok(id) => ItemKey::Id(id),
+      Err(_) => ItemKey::Key(key.trim().to_string()),
+    })),
+    Some(Value::Number(id)) => match id.as_u64() {
+      Some(id) => Ok(Some(ItemKey::Id(id))),
+      None => Err(format!("{} must be a positive number", field)),
+    },
+    Some(_) => Err(format!("{} must be a number or text", field)),
+  }
+}
@@ -0,0 +1,122 @@
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+use super::bundles::bundles_containing;
+use super::etag::{read_versions, write_versions};
+use super::history::{read_history, write_history};
+use super::merge::{read_redirects, write_redirects};
+use super::prices::{read_prices, read_sales, write_prices, write_sales};
+use super::relations::{read_relations, write_relations};
+use super::status::{read_transitions, write_transitions};
+use super::stock::{read_inventory, write_inventory};
+use super::variants::variants_of;
+use crate::store::{data_lock, read_json, sidecar_path, write_json};
+
+/// Deleted items with `deleted_at` and `deleted_by` added, kept until purged
+pub(crate) fn read_trash(data_path: &str) -> std::io::Result<Vec<Value>> {
+  read_json(&sidecar_path(data_path, "trash"))
+}
+
+pub(crate) fn write_trash(data_path: &str, trash: &[Value]) -> std::io::Result<()> {
+  write_json(&sidecar_path(data_path, "trash"), &trash)
+}
+
+/// Why an item can't move to the trash, deleting it would leave
+/// bundles or variants pointing at nothing
+pub(crate) enum DeleteBlocker {
+  InBundles(Vec<u64>),
+  HasVariants(usize),
+}
+
+impl DeleteBlocker {
+  pub(crate) fn to_json(&self) -> Value {
+    match self {
+      DeleteBlocker::InBundles(bundle_ids) => json!({
+        "error": "Item is in bundles",
+        "msg": "Please remove it from the bundles first",
+        "bundle_ids": bundle_ids
+      }),
+      DeleteBlocker::HasVariants(count) => json!({
+        "error": "Item has variants",
+        "msg": format!("Please delete its {} variant(s) first", count)
+      }),
+    }
+  }
+}
+
+/// Checked by every way of deleting an item
+pub(crate) fn delete_blocker(items: &[Value], id: u64) -> Option<DeleteBlocker> {
+  let bundle_ids = bundles_containing(items, id);
+  if !bundle_ids.is_empty() {
+    return Some(DeleteBlocker::InBundles(bundle_ids))
+  }
+  match variants_of(items, id).count() {
+    0 => None,
+    count => Some(DeleteBlocker::HasVariants(count)),
+  }
+}
+
+/// Permanently removes items that have been in the trash longer than `retention`,
+/// returns how many were removed
+pub async fn purge_trash(data_path: &str, retention: Duration) -> std::io::Result<usize> {
+  let _guard = data_lock(data_path).write_owned().await;
+
+  let trash = read_trash(data_path)?;
+  let retention = chrono::Duration::from_std(retention).unwrap_or(chrono::Duration::MAX);
+  let now = Utc::now();
+
+  let (kept, purged): (Vec<Value>, Vec<Value>) = trash.into_iter().partition(|item| {
+    let deleted_at = item
+      .get("deleted_at")
+      .and_then(|d| d.as_str())
+      .and_then(|d| DateTime::parse_from_rfc3339(d).ok());
+    match deleted_at {
+      Some(deleted_at) => now.signed_duration_since(deleted_at) < retention,
+      None => true,
+    }
+  });
+
+  if purged.is_empty() {
+    return Ok(0)
+  }
+  write_trash(data_path, &kept)?;
+
+  let ids: Vec<u64> = purged.iter().filter_map(|item| item.get("id").and_then(|i| i.as_u64())).collect();
+  forget_items(data_path, &ids)?;
+  Ok(purged.len())
+}
+
+/// Removes what the sidecar files keep per item for items that are gone for
+/// good. Media goes with the next `purge_media`.
+fn forget_items(data_path: &str, ids: &[u64]) -> std::io::Result<()> {
+  let mut versions = read_versions(data_path)?;
+  let mut history = read_history(data_path)?;
+  let mut transitions = read_transitions(data_path)?;
+  let mut prices = read_prices(data_path)?;
+  let mut sales = read_sales(data_path)?;
+  let mut inventory = read_inventory(data_path)?;
+  let mut relations = read_relations(data_path)?;
+  let mut redirects = read_redirects(data_path)?;
+
+  for id in ids.iter() {
+    versions.remove(id);
+    history.remove(id);
+    transitions.remove(id);
+    prices.remove(id);
+    sales.remove(id);
+    inventory.remove_item(*id);
+    relations.remove_item(*id);
+  }
+  // Ids merged into a purged item have nowhere to go anymore
+  redirects.retain(|_from, into| !ids.contains(into));
+
+  write_versions(data_path, &versions)?;
+  write_history(data_path, &history)?;
+  write_transitions(data_path, &transitions)?;
+  write_prices(data_path, &prices)?;
+  write_sales(data_path, &sales)?;
+  write_inventory(data_path, &inventory)?;
+  write_relations(data_path, &relations)?;
+  write_redirects(data_path, &redirects)
+}
@@ -0,0 +1,215 @@
+use poem::web::{Data, Path, Query};
+use poem::{handler, http::StatusCode, web::Json, Request, Response, Result};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Map, Value};
+
+use super::etag::{if_match, item_etag, read_versions, version_of, with_etag};
+use super::ids::{new_public_id, next_id, read_sequence, write_sequence};
+use super::stock::read_inventory;
+use super::{decorate_item, find_item, item_not_found, localize_items, precondition_failed, save_item_change, ConflictResponse, ItemQuery};
+use crate::{error_response_json, response_json, Config, ErrorResponse};
+pub(crate) use variant::{inherit_title, name_to_store, normalize_sku, options_owner, parent_of, parse_options, sku_owner, variants_of};
+
+mod variant;
+
+#[handler]
+pub(crate) async fn get_item_variants(req: &Request, key: Path<String>, params: Query<ItemQuery>, data_path: Data<&String>) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let items = req.extensions().get::<Vec<Value>>().unwrap();
+
+  let id = match find_item(items, &key) {
+    Some((id, _index)) => id,
+    None => return Err(item_not_found())
+  };
+
+  let inventory = match read_inventory(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error get_item_variants 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let mut variants: Vec<Value> = variants_of(items, id)
+    .map(|variant| {
+      let mut variant = variant.clone();
+      decorate_item(&mut variant, items, &inventory);
+      variant
+    })
+    .collect();
+  localize_items(req, params.lang.as_deref(), &mut variants);
+  Ok(response_json(StatusCode::OK, variants))
+}
+
+/// Adds a variant of the item, it is an item of its own with the SKU, stock
+/// and prices of that edition, region or language
+#[handler]
+pub(crate) async fn post_item_variant(
+  req: &Request,
+  key: Path<String>,
+  variant_req: Json<VariantReq>,
+  data_path: Data<&String>,
+  config: Data<&Config>
+) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let mut items = req.extensions().get::<Vec<Value>>().unwrap().clone();
+
+  let (parent_id, index) = match find_item(&items, &key) {
+    Some(res) => res,
+    None => return Err(item_not_found())
+  };
+  if parent_of(&items[index]).is_some() {
+    return Err(invalid_variant("Variants can't have variants of their own".to_string()))
+  }
+
+  let (sku, options) = match (normalize_sku(&variant_req.sku), parse_options(&variant_req.options)) {
+    (Ok(sku), Ok(options)) => (sku, options),
+    (Err(e), _) | (_, Err(e)) => return Err(invalid_variant(e))
+  };
+  check_variant_conflicts(&items, parent_id, None, &sku, &options)?;
+
+  let mut sequence = match read_sequence(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error post_item_variant 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let id = next_id(&items, &mut sequence);
+  let mut variant = json!({
+    "id": id,
+    "parent_id": parent_id,
+    "sku": sku,
+    "options": options
+  });
+  if let Some(public_id) = new_public_id(config.public_ids) {
+    variant["public_id"] = json!(public_id);
+  }
+  items.push(variant.clone());
+
+  if write_sequence(data_path.as_str(), &sequence).is_err() {
+    return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+      error: "Server error post_item_variant 2".to_string(),
+      msg: "Please contact support".to_string()
+    }))
+  }
+
+  let version = save_item_change(req, &items, None, &variant, "create", data_path.as_str(), "post_item_variant")?;
+  inherit_title(&mut variant, &items);
+  Ok(with_etag(response_json(StatusCode::CREATED, &variant), &item_etag(version)))
+}
+
+#[handler]
+pub(crate) async fn put_item_variant(
+  req: &Request,
+  Path((key, variant_key)): Path<(String, String)>,
+  variant_req: Json<VariantPatchReq>,
+  data_path: Data<&String>
+) -> Result<Response> {
+  // Error handling on this already in AuthMiddleware
+  let mut items = req.extensions().get::<Vec<Value>>().unwrap().clone();
+
+  let parent_id = match find_item(&items, &key) {
+    Some((id, _index)) => id,
+    None => return Err(item_not_found())
+  };
+  let (id, index) = match find_item(&items, &variant_key) {
+    Some((id, index)) if parent_of(&items[index]) == Some(parent_id) => (id, index),
+    _ => {
+      return Err(error_response_json(StatusCode::NOT_FOUND, ErrorResponse {
+        error: "Not found".to_string(),
+        msg: "Variant does not exist".to_string()
+      }))
+    }
+  };
+
+  let versions = match read_versions(data_path.as_str()) {
+    Ok(res) => res,
+    Err(_e) => {
+      return Err(error_response_json(StatusCode::INTERNAL_SERVER_ERROR, ErrorResponse {
+        error: "Server error put_item_variant 1".to_string(),
+        msg: "Please contact support".to_string()
+      }))
+    }
+  };
+
+  let etag = item_etag(version_of(&versions, id));
+  if !if_match(req.headers(), &etag) {
+    return Err(precondition_failed())
+  }
+
+  let sku = match &variant_req.sku {
+    Some(sku) => normalize_sku(sku),
+    None => Ok(items[index]["sku"].as_str().unwrap_or_default().to_string())
+  };
+  let options = match &variant_req.options {
+    Some(options) => parse_options(options),
+    None => Ok(items[index]["options"].as_object().cloned().unwrap_or_default())
+  };
+  let (sku, options) = match (sku, options) {
+    (Ok(sku), Ok(options)) => (sku, options),
+    (Err(e), _) | (_, Err(e)) => return Err(invalid_variant(e))
+  };
+  check_variant_conflicts(&items, parent_id, Some(id), &sku, &options)?;
+
+  let before = items[index].clone();
+  items[index]["sku"] = json!(sku);
+  items[index]["options"] = json!(options);
+
+  let mut after = items[index].clone();
+  if after == before {
+    inherit_title(&mut after, &items);
+    return Ok(with_etag(response_json(StatusCode::OK, &after), &etag))
+  }
+
+  let version = save_item_change(req, &items, Some(before), &after, "update", data_path.as_str(), "put_item_variant")?;
+  inherit_title(&mut after, &items);
+  Ok(with_etag(response_json(StatusCode::OK, &after), &item_etag(version)))
+}
+
+/// SKUs are unique across items and the variants of an item differ in their options
+pub(crate) fn check_variant_conflicts(items: &[Value], parent_id: u64, id: Option<u64>, sku: &str, options: &Map<String, Value>) -> Result<()> {
+  let conflict = |error: &str, msg: &str, existing_id: u64| error_response_json(StatusCode::CONFLICT, ConflictResponse {
+    error: error.to_string(),
+    msg: msg.to_string(),
+    id: existing_id
+  });
+
+  match sku_owner(items, sku) {
+    Some(existing_id) if Some(existing_id) != id => {
+      return Err(conflict("SKU already exists", "Please use a different SKU", existing_id))
+    }
+    _ => {}
+  }
+  match options_owner(items, parent_id, options) {
+    Some(existing_id) if Some(existing_id) != id => {
+      Err(conflict("Variant already exists", "The item already has a variant with these options", existing_id))
+    }
+    _ => Ok(())
+  }
+}
+
+fn invalid_variant(msg: String) -> poem::Error {
+  error_response_json(StatusCode::BAD_REQUEST, ErrorResponse {
+    error: "Invalid variant".to_string(),
+    msg
+  })
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct VariantReq {
+  sku: String,
+  /// Edition, region and/or language of the variant
+  options: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct VariantPatchReq {
+  sku: Option<String>,
+  options: Option<Map<String, Value>>,
+}
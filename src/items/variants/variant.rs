@@ -0,0 +1,102 @@
+use serde_json::{json, Map, Value};
+
+/// What variants of the same item differ in
+pub(crate) const VARIANT_OPTIONS: [&str; 3] = ["edition", "region", "language"];
+/// Fields a variant shows from its parent
+const TITLE_FIELDS: [&str; 3] = ["name", "description", "translations"];
+const MAX_SKU_LEN: usize = 64;
+const MAX_OPTION_LEN: usize = 50;
+
+pub(crate) fn parent_of(item: &Value) -> Option<u64> {
+  item.get("parent_id").and_then(|p| p.as_u64())
+}
+
+pub(crate) fn variants_of(items: &[Value], id: u64) -> impl Iterator<Item = &Value> {
+  items.iter().filter(move |item| parent_of(item) == Some(id))
+}
+
+/// Name to store when an item is sent `name`. Variants show the name of their
+/// parent, sending that name back is fine and stores nothing, any other name fails.
+pub(crate) fn name_to_store<'a>(items: &[Value], parent_id: Option<u64>, name: &'a str) -> Result<Option<&'a str>, String> {
+  let parent_id = match parent_id {
+    Some(res) => res,
+    None => return Ok(Some(name)),
+  };
+  let parent_name = items
+    .iter()
+    .find(|item| item.get("id").and_then(|i| i.as_u64()) == Some(parent_id))
+    .and_then(|parent| parent.get("name").and_then(|n| n.as_str()));
+  match parent_name == Some(name) {
+    true => Ok(None),
+    false => Err(format!("Variants have the name of their parent, please rename item {} instead", parent_id)),
+  }
+}
+
+/// SKUs are kept upper case, e.g. " hac-p-axn7a " is "HAC-P-AXN7A"
+pub(crate) fn normalize_sku(sku: &str) -> Result<String, String> {
+  let sku = sku.trim().to_uppercase();
+  if sku.is_empty() || sku.len() > MAX_SKU_LEN {
+    return Err(format!("sku must be 1 to {} characters", MAX_SKU_LEN))
+  }
+  if !sku.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') {
+    return Err(format!("{} is not a valid sku, use letters, digits, -, _ and .", sku))
+  }
+  Ok(sku)
+}
+
+/// Id of the item already using the SKU
+pub(crate) fn sku_owner(items: &[Value], sku: &str) -> Option<u64> {
+  items
+    .iter()
+    .find(|item| item.get("sku").and_then(|s| s.as_str()) == Some(sku))
+    .and_then(|item| item.get("id").and_then(|i| i.as_u64()))
+}
+
+/// Checks the options of a variant, at least one of `VARIANT_OPTIONS` is needed
+pub(crate) fn parse_options(options: &Map<String, Value>) -> Result<Map<String, Value>, String> {
+  let mut parsed = Map::new();
+  for (key, value) in options.iter() {
+    if !VARIANT_OPTIONS.contains(&key.as_str()) {
+      return Err(format!("{} is not a variant option, use {}", key, VARIANT_OPTIONS.join(", ")))
+    }
+    let value = match value.as_str().map(|v| v.trim()) {
+      Some(res) if !res.is_empty() && res.chars().count() <= MAX_OPTION_LEN => res,
+      _ => return Err(format!("{} must be 1 to {} characters", key, MAX_OPTION_LEN)),
+    };
+    parsed.insert(key.to_string(), json!(value));
+  }
+  if parsed.is_empty() {
+    return Err(format!("options need at least one of {}", VARIANT_OPTIONS.join(", ")))
+  }
+  Ok(parsed)
+}
+
+/// Id of the sibling that already has the same options, ignoring case
+pub(crate) fn options_owner(items: &[Value], parent_id: u64, options: &Map<String, Value>) -> Option<u64> {
+  let key = |options: &Map<String, Value>| -> Vec<(String, String)> {
+    options
+      .iter()
+      .map(|(k, v)| (k.to_string(), v.as_str().unwrap_or_default().to_lowercase()))
+      .collect()
+  };
+  variants_of(items, parent_id)
+    .find(|variant| variant.get("options").and_then(|o| o.as_object()).map(key) == Some(key(options)))
+    .and_then(|variant| variant.get("id").and_then(|i| i.as_u64()))
+}
+
+/// Gives a variant the title of its parent, variants don't store one of their own
+pub(crate) fn inherit_title(item: &mut Value, items: &[Value]) {
+  let parent_id = match parent_of(item) {
+    Some(res) => res,
+    None => return,
+  };
+  let parent = match items.iter().find(|i| i.get("id").and_then(|i| i.as_u64()) == Some(parent_id)) {
+    Some(res) => res,
+    None => return,
+  };
+  for field in TITLE_FIELDS.iter() {
+    if let (Some(value), None) = (parent.get(*field), item.get(*field)) {
+      item[*field] = value.clone();
+    }
+  }
+}
@@ -1,17 +1,98 @@
+use std::sync::Arc;
+use std::time::Duration;
 use poem::{http::StatusCode, EndpointExt, Error, Response, Route};
 use serde::{Serialize, Deserialize};
 
 pub mod users;
 pub mod items;
+pub mod categories;
+pub mod carts;
+pub mod tasks;
+pub mod media;
+mod store;
 
 pub fn all_routes(data_path: String) -> Route {
+  all_routes_with_config(data_path, Config::default())
+}
+
+pub fn all_routes_with_config(data_path: String, config: Config) -> Route {
   Route::new()
     .nest("/users", users::route())
+    .nest("/categories", categories::route(data_path.clone())
+      .data(data_path.clone())
+      .data(config.clone())
+    )
+    .nest("/cart", carts::route(data_path.clone())
+      .data(data_path.clone())
+      .data(config.clone())
+    )
     .nest("/", items::route(data_path.clone())
       .data(data_path.clone())
+      .data(config)
     )
 }
 
+/// Settings that change how the API behaves, `all_routes` uses the defaults
+#[derive(Clone, Debug)]
+pub struct Config {
+  /// How item names are compared when checking that they are unique
+  pub name_policy: items::NamePolicy,
+  /// Extra identifier given to new items, usable in place of the numeric id
+  pub public_ids: items::PublicIds,
+  /// Give new items a slug made from their name, usable in place of the numeric id
+  pub slugs: bool,
+  /// How long deleted items stay in the trash before they are purged
+  pub trash_retention: Duration,
+  /// How long a stock reservation holds units when the request gives no TTL
+  pub reservation_ttl: Duration,
+  /// Locale of the item text stored outside of `translations`
+  pub default_locale: String,
+  /// JSON file with the exchange rates for derived prices,
+  /// `None` uses `<data file>.rates.json`
+  pub exchange_rates_path: Option<String>,
+  /// Where uploaded item media is kept, `None` uses files in `<data file>.media/`
+  pub blob_store: Option<Arc<dyn media::BlobStore>>,
+  /// Largest media upload in bytes
+  pub max_media_size: usize,
+  /// How often the background tasks run
+  pub task_interval: Duration,
+  /// Currency new carts are priced in
+  pub default_currency: String,
+  /// How long a cart is kept after its last change
+  pub cart_ttl: Duration,
+  /// Most anonymous carts open at once, starting another one is refused
+  pub max_anonymous_carts: usize,
+}
+
+impl Config {
+  pub(crate) fn blob_store(&self, data_path: &str) -> Arc<dyn media::BlobStore> {
+    match &self.blob_store {
+      Some(store) => store.clone(),
+      None => Arc::new(media::LocalBlobStore::new(store::sidecar_dir(data_path, "media"))),
+    }
+  }
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Self {
+      name_policy: items::NamePolicy::default(),
+      public_ids: items::PublicIds::default(),
+      slugs: false,
+      trash_retention: Duration::from_secs(30 * 24 * 60 * 60),
+      reservation_ttl: Duration::from_secs(15 * 60),
+      default_locale: "en".to_string(),
+      exchange_rates_path: None,
+      blob_store: None,
+      max_media_size: 10 * 1024 * 1024,
+      task_interval: Duration::from_secs(60),
+      default_currency: "USD".to_string(),
+      cart_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+      max_anonymous_carts: 10_000,
+    }
+  }
+}
+
 fn response_json<T>(status_code: StatusCode, data: T) -> Response
 where
   T: Serialize
@@ -44,7 +125,7 @@ struct ErrorResponse {
   msg: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Claims {
   sub: String,
   exp: usize
@@ -1,9 +1,12 @@
-use play_asia::all_routes;
+use play_asia::{all_routes_with_config, tasks::spawn_background_tasks, Config};
 use poem::{listener::TcpListener, Server};
 
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
-  let routes = all_routes("data.json".to_string());
+  let data_path = "data.json".to_string();
+  let config = Config::default();
+  let routes = all_routes_with_config(data_path.clone(), config.clone());
+  spawn_background_tasks(data_path, config);
 
   Server::new(TcpListener::bind("0.0.0.0:3000"))
     .run(routes)
@@ -0,0 +1,80 @@
+use std::fmt::Debug;
+use std::fs::{create_dir_all, read, read_dir, remove_file, write};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+pub(crate) mod sniff;
+
+/// Where uploaded media is kept, keys look like `12/01J9Z3....png`
+pub trait BlobStore: Debug + Send + Sync {
+  fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()>;
+  fn get(&self, key: &str) -> std::io::Result<Vec<u8>>;
+  /// Deleting a missing blob is not an error
+  fn delete(&self, key: &str) -> std::io::Result<()>;
+  /// Every key in the store
+  fn list(&self) -> std::io::Result<Vec<String>>;
+}
+
+/// Keeps blobs as files below a directory
+#[derive(Debug, Clone)]
+pub struct LocalBlobStore {
+  root: PathBuf,
+}
+
+impl LocalBlobStore {
+  pub fn new(root: impl Into<PathBuf>) -> Self {
+    Self { root: root.into() }
+  }
+
+  /// Keys come from the URL, anything that could leave the root is refused
+  fn path(&self, key: &str) -> std::io::Result<PathBuf> {
+    let valid = !key.is_empty()
+      && key.split('/').all(|part| !part.is_empty() && part != "." && part != "..")
+      && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '/' || c == '-' || c == '_' || c == '.');
+    match valid {
+      true => Ok(self.root.join(key)),
+      false => Err(Error::new(ErrorKind::InvalidInput, format!("invalid blob key {}", key))),
+    }
+  }
+}
+
+impl BlobStore for LocalBlobStore {
+  fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+    let path = self.path(key)?;
+    if let Some(dir) = path.parent() {
+      create_dir_all(dir)?;
+    }
+    write(path, bytes)
+  }
+
+  fn get(&self, key: &str) -> std::io::Result<Vec<u8>> {
+    read(self.path(key)?)
+  }
+
+  fn delete(&self, key: &str) -> std::io::Result<()> {
+    match remove_file(self.path(key)?) {
+      Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+      _ => Ok(()),
+    }
+  }
+
+  fn list(&self) -> std::io::Result<Vec<String>> {
+    let mut keys = Vec::new();
+    if self.root.exists() {
+      list_dir(&self.root, &self.root, &mut keys)?;
+    }
+    Ok(keys)
+  }
+}
+
+fn list_dir(root: &Path, dir: &Path, keys: &mut Vec<String>) -> std::io::Result<()> {
+  for entry in read_dir(dir)? {
+    let path = entry?.path();
+    if path.is_dir() {
+      list_dir(root, &path, keys)?;
+    } else if let Ok(key) = path.strip_prefix(root) {
+      keys.push(key.to_string_lossy().replace('\\', "/"));
+    }
+  }
+  Ok(())
+}
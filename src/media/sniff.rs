@@ -0,0 +1,70 @@
+use std::io::Cursor;
+use image::{ImageFormat, ImageReader, Limits};
+
+/// Longest side of a generated thumbnail in pixels
+pub(crate) const THUMBNAIL_SIZE: u32 = 256;
+/// Images wider or higher than this get no thumbnail, a few bytes can claim
+/// an image far too large to decode
+const MAX_IMAGE_SIDE: u32 = 8192;
+/// Most memory decoding an image for its thumbnail may take
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+
+/// Media type told by the first bytes of the file, the type the client sends
+/// is not trusted. Returns the content type and the file extension.
+pub(crate) fn sniff(bytes: &[u8]) -> Option<(&'static str, &'static str)> {
+  match bytes {
+    [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => Some(("image/png", "png")),
+    [0xff, 0xd8, 0xff, ..] => Some(("image/jpeg", "jpg")),
+    [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(("image/gif", "gif")),
+    [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(("image/webp", "webp")),
+    [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some(("video/mp4", "mp4")),
+    [b'%', b'P', b'D', b'F', b'-', ..] => Some(("application/pdf", "pdf")),
+    _ => None,
+  }
+}
+
+/// Content type of a stored blob from its extension
+pub(crate) fn content_type_of(key: &str) -> &'static str {
+  match key.rsplit('.').next() {
+    Some("png") => "image/png",
+    Some("jpg") => "image/jpeg",
+    Some("gif") => "image/gif",
+    Some("webp") => "image/webp",
+    Some("mp4") => "video/mp4",
+    Some("pdf") => "application/pdf",
+    _ => "application/octet-stream",
+  }
+}
+
+pub(crate) struct Thumbnail {
+  /// Size of the original image
+  pub width: u32,
+  pub height: u32,
+  /// PNG at most `THUMBNAIL_SIZE` on its longest side
+  pub png: Vec<u8>,
+}
+
+/// Scaled down PNG of an image, `None` for other media or images that don't
+/// decode within the limits
+pub(crate) fn thumbnail(bytes: &[u8], content_type: &str) -> Option<Thumbnail> {
+  let format = match content_type {
+    "image/png" => ImageFormat::Png,
+    "image/jpeg" => ImageFormat::Jpeg,
+    "image/gif" => ImageFormat::Gif,
+    "image/webp" => ImageFormat::WebP,
+    _ => return None,
+  };
+  let mut limits = Limits::default();
+  limits.max_image_width = Some(MAX_IMAGE_SIDE);
+  limits.max_image_height = Some(MAX_IMAGE_SIDE);
+  limits.max_alloc = Some(MAX_DECODE_ALLOC);
+  let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+  reader.limits(limits);
+  let image = reader.decode().ok()?;
+  let mut png = Vec::new();
+  image
+    .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+    .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+    .ok()?;
+  Some(Thumbnail { width: image.width(), height: image.height(), png })
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::fs::{read_to_string, remove_file, rename, write};
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+use once_cell::sync::Lazy;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::RwLock;
+
+/// Path of a JSON file kept next to the items data file,
+/// e.g. `data.json` with `versions` becomes `data.versions.json`
+pub(crate) fn sidecar_path(data_path: &str, name: &str) -> String {
+  match data_path.strip_suffix(".json") {
+    Some(stem) => format!("{}.{}.json", stem, name),
+    None => format!("{}.{}.json", data_path, name),
+  }
+}
+
+/// Directory kept next to the items data file,
+/// e.g. `data.json` with `media` becomes `data.media`
+pub(crate) fn sidecar_dir(data_path: &str, name: &str) -> String {
+  format!("{}.{}", data_path.strip_suffix(".json").unwrap_or(data_path), name)
+}
+
+/// Reads a JSON file, a missing file is treated as empty data
+pub(crate) fn read_json<T: DeserializeOwned + Default>(path: &str) -> std::io::Result<T> {
+  if !std::path::Path::new(path).exists() {
+    return Ok(T::default())
+  }
+
+  let data_str = read_to_string(path)?;
+  serde_json::from_str(&data_str).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+pub(crate) fn write_json<T: Serialize>(path: &str, data: &T) -> std::io::Result<()> {
+  let data_str = serde_json::to_string_pretty(data)
+    .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+  write(path, data_str.as_bytes())
+}
+
+/// JSON files that are changed together. Each one is written next to its
+/// original first, only when all of them are written are they renamed over
+/// the originals, so a failed write leaves every file as it was.
+#[derive(Default)]
+pub(crate) struct FileBatch {
+  files: Vec<(String, String)>,
+}
+
+impl FileBatch {
+  pub(crate) fn add<T: Serialize>(&mut self, path: &str, data: &T) -> std::io::Result<()> {
+    let data_str = serde_json::to_string_pretty(data)
+      .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    self.files.push((path.to_string(), data_str));
+    Ok(())
+  }
+
+  pub(crate) fn commit(self) -> std::io::Result<()> {
+    let tmp_path = |path: &str| format!("{}.tmp", path);
+    for (path, data_str) in self.files.iter() {
+      if let Err(e) = write(tmp_path(path), data_str.as_bytes()) {
+        for (path, _) in self.files.iter() {
+          let _ = remove_file(tmp_path(path));
+        }
+        return Err(e)
+      }
+    }
+    for (path, _) in self.files.iter() {
+      rename(tmp_path(path), path)?;
+    }
+    Ok(())
+  }
+}
+
+/// One lock per data file, reads share it and writes hold it exclusively
+/// for the whole read-modify-write of a request
+pub(crate) fn data_lock(data_path: &str) -> Arc<RwLock<()>> {
+  let mut locks = DATA_LOCKS.lock().unwrap();
+  locks
+    .entry(data_path.to_string())
+    .or_insert_with(|| Arc::new(RwLock::new(())))
+    .clone()
+}
+
+static DATA_LOCKS: Lazy<Mutex<HashMap<String, Arc<RwLock<()>>>>> = Lazy::new(|| {
+  Mutex::new(HashMap::new())
+});
@@ -0,0 +1,38 @@
+use tokio::task::JoinHandle;
+
+use crate::{carts, items, Config};
+
+/// Runs the periodic housekeeping of the data file every `config.task_interval`
+pub fn spawn_background_tasks(data_path: String, config: Config) -> JoinHandle<()> {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(config.task_interval);
+    loop {
+      interval.tick().await;
+
+      if let Err(e) = items::purge_trash(&data_path, config.trash_retention).await {
+        println!("Error purging trash {:?}", e);
+      }
+
+      // After the purge so the media of purged items goes with them
+      if let Err(e) = items::purge_media(&data_path, config.blob_store(&data_path).as_ref()).await {
+        println!("Error purging media {:?}", e);
+      }
+
+      if let Err(e) = items::expire_reservations(&data_path).await {
+        println!("Error expiring reservations {:?}", e);
+      }
+
+      if let Err(e) = items::run_price_schedule(&data_path).await {
+        println!("Error running the price schedule {:?}", e);
+      }
+
+      if let Err(e) = items::run_release_schedule(&data_path).await {
+        println!("Error running the release schedule {:?}", e);
+      }
+
+      if let Err(e) = carts::expire_carts(&data_path, config.cart_ttl).await {
+        println!("Error expiring carts {:?}", e);
+      }
+    }
+  })
+}
@@ -8,7 +8,7 @@ use once_cell::sync::Lazy;
 use crate::{error_response_json, response_json, Claims, ErrorResponse, SECRET_KEY};
 
 pub fn route() -> Route {
-  return Route::new()
+  Route::new()
     .at("/signup", post(sign_up))
     .at("/login", post(login))
 }
@@ -70,7 +70,7 @@ async fn login(login: Json<User>) -> Result<Response> {
   };
 
 
-  match verify(login.pass.clone(), &hashed_pass) {
+  match verify(login.pass.clone(), hashed_pass) {
     Ok(res) => res,
     Err(_e) => return Err(error_response_json(
       StatusCode::UNAUTHORIZED,
@@ -123,11 +123,6 @@ struct User {
   pub pass: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct SignUpResponse {
-  jwt: String,
-}
-
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LoginResponse {
   pub msg: String,
@@ -1,6 +1,8 @@
-use std::{fs::{read_to_string, remove_file, OpenOptions}, io::Write, time::Duration};
-use play_asia::{all_routes, items::Item, users::LoginResponse};
-use poem::{http::{header, StatusCode}, test::TestClient, Route};
+use std::{fs::{read_dir, read_to_string, remove_dir_all, remove_file, OpenOptions}, io::{Cursor, Write}, time::Duration};
+use chrono::{SecondsFormat, Utc};
+use futures_util::future::join_all;
+use play_asia::{all_routes, all_routes_with_config, carts::expire_carts, items::{expire_reservations, purge_media, purge_trash, run_price_schedule, run_release_schedule, Item, NamePolicy, PublicIds}, media::{BlobStore, LocalBlobStore}, users::LoginResponse, Config};
+use poem::{http::{header, StatusCode}, test::{TestClient, TestForm, TestFormField, TestResponse}, Route};
 use serde_json::{from_str, json, to_string_pretty, Value};
 
 // POST
@@ -135,8 +137,162 @@ async fn test_post_item_three_items_with_data_persistence() {
   delete_file_if_exists(&data_path);
 }
 
+#[tokio::test]
+async fn test_post_item_normalized_name_conflict() {
+  let data_path = "test_post_item_normalized_name_conflict.json".to_string();
+  // A record without a name must not hide the ones after it
+  let items = vec![
+    json!({ "id": 1 }),
+    json!({ "id": 2, "name": "Zelda" }),
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  for name in ["Zelda", "zelda ", "ＺＥＬＤＡ"] {
+    let res = client
+      .post("/items")
+      .header(header::AUTHORIZATION, format!("Bearer {}", token))
+      .body_json(&json!({ "name": name }))
+      .send()
+      .await;
+    res.assert_status(StatusCode::CONFLICT);
+    res.assert_json(json!({
+      "error": "Item already exists",
+      "msg": "Please use a different name",
+      "id": 2
+    })).await;
+  }
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_post_item_exact_name_policy() {
+  let data_path = "test_post_item_exact_name_policy.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Zelda".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let config = Config { name_policy: NamePolicy::exact(), ..Config::default() };
+
+
+  let routes = all_routes_with_config(data_path.clone(), config);
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let res = client
+    .post("/items")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "zelda" }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CREATED);
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_post_item_ids_not_reused() {
+  let data_path = "test_post_item_ids_not_reused.json".to_string();
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  for name in ["NewItem1", "NewItem2"] {
+    client
+      .post("/items")
+      .header(header::AUTHORIZATION, format!("Bearer {}", token))
+      .body_json(&json!({ "name": name }))
+      .send()
+      .await
+      .assert_status(StatusCode::CREATED);
+  }
+  client
+    .delete("/items/2")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+
+  let res = client
+    .post("/items")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "NewItem3" }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CREATED);
+  res.assert_json(json!({
+    "id": 3,
+    "name": "NewItem3"
+  })).await;
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_post_item_public_id_and_slug() {
+  let data_path = "test_post_item_public_id_and_slug.json".to_string();
+  let config = Config { public_ids: PublicIds::UuidV7, slugs: true, ..Config::default() };
+  let routes = all_routes_with_config(data_path.clone(), config);
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let mut res = client
+    .post("/items")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "Pokémon Scarlet" }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CREATED);
+  let item = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(item["slug"], "pokemon-scarlet");
+  let public_id = item["public_id"].as_str().unwrap();
+
+  for key in [public_id, "pokemon-scarlet", "1"] {
+    let res = client.get(format!("/items/{}", key)).send().await;
+    res.assert_status(StatusCode::OK);
+    res.assert_json(&item).await;
+  }
+
+  let res = client.get("/items/no-such-slug").send().await;
+  res.assert_status(StatusCode::NOT_FOUND);
+
+  // Trashed items keep their slug for when they are restored
+  client
+    .delete("/items/1")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  client
+    .get("/items/pokemon-scarlet/history")
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  let mut res = client
+    .post("/items")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "Pokémon Scarlet" }))
+    .send()
+    .await;
+  let item = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(item["slug"], "pokemon-scarlet-2");
+  client
+    .patch("/items/2")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "Pokémon Scarlet (2022)" }))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  client
+    .post("/items/1/restore")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  let mut res = client.get("/items/pokemon-scarlet").send().await;
+  assert_eq!(res.0.take_body().into_json::<Value>().await.unwrap()["id"], 1);
+  delete_file_if_exists(&data_path);
+}
+
 
 // GET
+
 #[tokio::test]
 async fn test_get_items_no_item() {
   let data_path = "test_get_items_no_item.json".to_string();
@@ -207,8 +363,169 @@ async fn test_get_items_all() {
   delete_file_if_exists(&data_path);
 }
 
+#[tokio::test]
+async fn test_get_items_paginated() {
+  let data_path = "test_get_items_paginated.json".to_string();
+  let items: Vec<Item> = (1..=5)
+    .map(|id| Item { id, name: format!("Item{}", id) })
+    .collect();
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+
+  let mut ids = Vec::new();
+  let mut uri = "/items?limit=2".to_string();
+  loop {
+    let res = client.get(&uri).send().await;
+    res.assert_status(StatusCode::OK);
+    res.assert_header("X-Total-Count", "5");
+    let link = res.0.headers().get(header::LINK).map(|l| l.to_str().unwrap().to_string());
+    let mut res = res;
+    let page = res.0.take_body().into_json::<Vec<Value>>().await.unwrap();
+    ids.extend(page.iter().map(|item| item["id"].as_u64().unwrap()));
+
+    match link {
+      Some(link) => uri = link[1..link.find('>').unwrap()].to_string(),
+      None => break,
+    }
+  }
+  assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+
+  let res = client.get("/items?limit=0").send().await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_get_items_sorted_and_filtered() {
+  let data_path = "test_get_items_sorted_and_filtered.json".to_string();
+  let items = vec![
+    json!({ "id": 1, "name": "Zelda", "platform": "Switch", "price": 60 }),
+    json!({ "id": 2, "name": "Astro Bot", "platform": "PS5", "price": 50 }),
+    json!({ "id": 3, "name": "Gran Turismo", "platform": "PS5", "price": 30 }),
+    json!({ "id": 4, "name": "Ratchet & Clank", "platform": "PS5" }),
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+
+  let res = client.get("/items?sort=name").send().await;
+  res.assert_json(json!([items[1], items[2], items[3], items[0]])).await;
+
+  let res = client.get("/items?sort=-id").send().await;
+  res.assert_json(json!([items[3], items[2], items[1], items[0]])).await;
+
+  // Items without the field come last in both directions
+  let res = client.get("/items?sort=price").send().await;
+  res.assert_json(json!([items[2], items[1], items[0], items[3]])).await;
+  let res = client.get("/items?sort=-price").send().await;
+  res.assert_json(json!([items[0], items[1], items[2], items[3]])).await;
+
+  let res = client.get("/items?platform=ps5&price_min=40").send().await;
+  res.assert_header("X-Total-Count", "1");
+  res.assert_json(json!([items[1]])).await;
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_search_items() {
+  let data_path = "test_search_items.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "The Legend of Zelda: Tears of the Kingdom".to_string() },
+    Item { id: 2, name: "Pokémon Scarlet".to_string() },
+    Item { id: 3, name: "ゼルダの伝説 ティアーズ オブ ザ キングダム".to_string() },
+    Item { id: 4, name: "Zelda".to_string() },
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+
+  let res = client.get("/items/search?q=ZELDA").send().await;
+  res.assert_status(StatusCode::OK);
+  res.assert_json(json!([items[3], items[0]])).await;
+
+  let res = client.get("/items/search?q=pokemon%20scar").send().await;
+  res.assert_json(json!([items[1]])).await;
+
+  let res = client.get("/items/search?q=%E3%82%BC%E3%83%AB%E3%83%80").send().await; // ゼルダ
+  res.assert_json(json!([items[2]])).await;
+
+  let res = client.get("/items/search?q=").send().await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_search_items_follows_changes() {
+  let data_path = "test_search_items_follows_changes.json".to_string();
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  for name in ["Gran Turismo 7", "Astro Bot"] {
+    client
+      .post("/items")
+      .header(header::AUTHORIZATION, format!("Bearer {}", token))
+      .body_json(&json!({ "name": name }))
+      .send()
+      .await
+      .assert_status(StatusCode::CREATED);
+  }
+
+  let res = client.get("/items/search?q=astro").send().await;
+  res.assert_json(json!([{ "id": 2, "name": "Astro Bot" }])).await;
+
+  client
+    .put("/items/2")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "Astro Playroom" }))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  let res = client.get("/items/search?q=bot").send().await;
+  res.assert_json(json!([])).await;
+
+  client
+    .delete("/items/1")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  let res = client.get("/items/search?q=gran").send().await;
+  res.assert_json(json!([])).await;
+
+  client
+    .post("/items/1/restore")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  let res = client.get("/items/search?q=gran").send().await;
+  res.assert_json(json!([{ "id": 1, "name": "Gran Turismo 7" }])).await;
+
+  client
+    .post("/items/bulk")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "operations": [{ "op": "update", "id": 2, "name": "Astro Bot Rescue" }] }))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  let res = client.get("/items/search?q=rescue").send().await;
+  res.assert_json(json!([{ "id": 2, "name": "Astro Bot Rescue" }])).await;
+
+  // Writes of another process bump the versions like every write does
+  create_data(data_path.clone(), &json!([{ "id": 2, "name": "Astro Bot Return" }]));
+  create_data("test_search_items_follows_changes.versions.json".to_string(), &json!({ "2": 9 }));
+  let res = client.get("/items/search?q=return").send().await;
+  res.assert_json(json!([{ "id": 2, "name": "Astro Bot Return" }])).await;
+  let res = client.get("/items/search?q=gran").send().await;
+  res.assert_json(json!([])).await;
+  delete_file_if_exists(&data_path);
+}
+
 
 // PUT
+
+
 #[tokio::test]
 async fn test_put_item_no_jwt() {
   let data_path = "test_put_item_no_jwt.json".to_string();
@@ -282,6 +599,37 @@ async fn test_put_item_not_existing() {
   delete_file_if_exists(&data_path);
 }
 
+#[tokio::test]
+async fn test_put_item_name_conflict() {
+  let data_path = "test_put_item_name_conflict.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "PutItem1".to_string() },
+    Item { id: 2, name: "PutItem2".to_string() },
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let res = client
+    .put("/items/2")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "putitem1" }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+
+  // Changing the case of its own name is fine
+  let res = client
+    .put("/items/2")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "PUTITEM2" }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::OK);
+  delete_file_if_exists(&data_path);
+}
+
+
 
 // DELETE
 #[tokio::test]
@@ -346,24 +694,2821 @@ async fn test_delete_item_not_existing() {
 }
 
 
+// ETAG
+#[tokio::test]
+async fn test_get_item_if_none_match() {
+  let data_path = "test_get_item_if_none_match.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Item1".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let res = client.get("/items/1").send().await;
+  res.assert_status(StatusCode::OK);
+  res.assert_header(header::ETAG, "\"1\"");
 
-fn create_data<T: serde::Serialize>(data_path: String, data: &T) {
-  let json_str = to_string_pretty(data).expect("Unable to convert to string");
+  let res = client
+    .get("/items/1")
+    .header(header::IF_NONE_MATCH, "\"1\"")
+    .send()
+    .await;
+  res.assert_status(StatusCode::NOT_MODIFIED);
 
-  let mut file = OpenOptions::new()
-    .write(true)
-    .create(true)
-    .open(data_path)
-    .expect("Unable to create and open file");
-  file.write_all(json_str.as_bytes()).expect("Error writing to a file");
+  let res = client.get("/items").send().await;
+  res.assert_status(StatusCode::OK);
+  let list_etag = res.0.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+  let res = client
+    .get("/items")
+    .header(header::IF_NONE_MATCH, list_etag)
+    .send()
+    .await;
+  res.assert_status(StatusCode::NOT_MODIFIED);
+  delete_file_if_exists(&data_path);
 }
 
-fn delete_file_if_exists(path: &str) {
-  if std::path::Path::new(path).exists() {
-    remove_file(path).unwrap();
-  }
+#[tokio::test]
+async fn test_list_etag_follows_items_outside_the_page() {
+  let data_path = "test_list_etag_follows_items_outside_the_page.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "A".to_string() },
+    Item { id: 2, name: "B".to_string() },
+    Item { id: 3, name: "C".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let etag_of = |res: &TestResponse| res.0.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+  let first_page = etag_of(&client.get("/items?limit=2").send().await);
+
+  client
+    .post("/items")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "D" }))
+    .send()
+    .await
+    .assert_status(StatusCode::CREATED);
+  let res = client
+    .get("/items?limit=2")
+    .header(header::IF_NONE_MATCH, first_page.clone())
+    .send()
+    .await;
+  res.assert_status(StatusCode::OK);
+  res.assert_header("X-Total-Count", "4");
+  let after_create = etag_of(&res);
+  assert_ne!(after_create, first_page);
+
+  client
+    .delete("/items/3")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  let after_delete = etag_of(&client.get("/items?limit=2").send().await);
+  assert_ne!(after_delete, after_create);
+
+  // The same items reached by another query are another listing
+  let sorted = etag_of(&client.get("/items?limit=2&sort=name").send().await);
+  assert_ne!(sorted, after_delete);
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_put_item_if_match() {
+  let data_path = "test_put_item_if_match.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "PutItem1".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let res = client
+    .put("/items/1")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .header(header::IF_MATCH, "\"1\"")
+    .body_json(&json!({
+      "name": "FirstEditor"
+    }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::OK);
+  res.assert_header(header::ETAG, "\"2\"");
+
+  // Second editor still holds the old version
+  let res = client
+    .patch("/items/1")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .header(header::IF_MATCH, "\"1\"")
+    .body_json(&json!({
+      "name": "SecondEditor"
+    }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::PRECONDITION_FAILED);
+
+  let res = client.get("/items/1").send().await;
+  res.assert_json(json!({
+    "id": 1,
+    "name": "FirstEditor"
+  })).await;
+  delete_file_if_exists(&data_path);
 }
 
+#[tokio::test]
+async fn test_delete_item_if_match() {
+  let data_path = "test_delete_item_if_match.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "DeleteItem1".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let res = client
+    .delete("/items/1")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .header(header::IF_MATCH, "\"5\"")
+    .send()
+    .await;
+  res.assert_status(StatusCode::PRECONDITION_FAILED);
+
+  let res = client
+    .delete("/items/1")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .header(header::IF_MATCH, "\"1\"")
+    .send()
+    .await;
+  res.assert_status(StatusCode::OK);
+  delete_file_if_exists(&data_path);
+}
+
+
+// TRASH
+#[tokio::test]
+async fn test_delete_item_moves_to_trash_and_restore() {
+  let data_path = "test_delete_item_moves_to_trash_and_restore.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "DeleteItem1".to_string() },
+    Item { id: 2, name: "DeleteItem2".to_string() },
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  client
+    .delete("/items/1")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+
+  client.get("/items/1").send().await.assert_status(StatusCode::NOT_FOUND);
+  let res = client.get("/items").send().await;
+  res.assert_json(json!([items[1]])).await;
+
+  let mut res = client.get("/items/trash").send().await;
+  res.assert_status(StatusCode::OK);
+  let trash = res.0.take_body().into_json::<Vec<Value>>().await.unwrap();
+  assert_eq!(trash.len(), 1);
+  assert_eq!(trash[0]["id"], 1);
+  assert_eq!(trash[0]["deleted_by"], "admin1");
+  assert!(trash[0]["deleted_at"].is_string());
+
+  let res = client
+    .post("/items/1/restore")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status(StatusCode::OK);
+  res.assert_json(json!(items[0])).await;
+
+  let res = client.get("/items").send().await;
+  res.assert_json(json!(items)).await;
+  let res = client.get("/items/trash").send().await;
+  res.assert_json(json!([])).await;
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_restore_item_name_taken() {
+  let data_path = "test_restore_item_name_taken.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "DeleteItem1".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  client
+    .delete("/items/1")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  client
+    .post("/items")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "DeleteItem1" }))
+    .send()
+    .await
+    .assert_status(StatusCode::CREATED);
+  // The data was written before the sequence, the id of the trashed item is still skipped
+  client.get("/items/2").send().await.assert_status(StatusCode::OK);
+
+  let res = client
+    .post("/items/1/restore")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+
+  let res = client
+    .post("/items/3/restore")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status(StatusCode::NOT_FOUND);
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_purge_trash_after_retention() {
+  let data_path = "test_purge_trash_after_retention.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "DeleteItem1".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  client
+    .delete("/items/1")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+
+  let purged = purge_trash(&data_path, Duration::from_secs(60 * 60)).await.unwrap();
+  assert_eq!(purged, 0);
+  let purged = purge_trash(&data_path, Duration::ZERO).await.unwrap();
+  assert_eq!(purged, 1);
+
+  let res = client.get("/items/trash").send().await;
+  res.assert_json(json!([])).await;
+  for sidecar in ["versions", "history"] {
+    let path = format!("test_purge_trash_after_retention.{}.json", sidecar);
+    let entries: Value = from_str(&read_to_string(path).unwrap()).unwrap();
+    assert_eq!(entries, json!({}));
+  }
+  delete_file_if_exists(&data_path);
+}
+
+
+// HISTORY
+#[tokio::test]
+async fn test_item_history_and_revert() {
+  let data_path = "test_item_history_and_revert.json".to_string();
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  client
+    .post("/items")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "Name1" }))
+    .send()
+    .await
+    .assert_status(StatusCode::CREATED);
+  for name in ["Name2", "Name3"] {
+    client
+      .put("/items/1")
+      .header(header::AUTHORIZATION, format!("Bearer {}", token))
+      .body_json(&json!({ "name": name }))
+      .send()
+      .await
+      .assert_status(StatusCode::OK);
+  }
+
+  let mut res = client.get("/items/1/history").send().await;
+  res.assert_status(StatusCode::OK);
+  let history = res.0.take_body().into_json::<Vec<Value>>().await.unwrap();
+  let actions: Vec<&str> = history.iter().map(|r| r["action"].as_str().unwrap()).collect();
+  assert_eq!(actions, vec!["create", "update", "update"]);
+  assert_eq!(history[2]["before"], json!({ "id": 1, "name": "Name2" }));
+  assert_eq!(history[2]["after"], json!({ "id": 1, "name": "Name3" }));
+  assert_eq!(history[2]["actor"], "admin1");
+
+  let res = client
+    .post("/items/1/revert")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "revision": 1 }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::OK);
+  res.assert_header(header::ETAG, "\"4\"");
+  res.assert_json(json!({ "id": 1, "name": "Name1" })).await;
+
+  let mut res = client.get("/items/1/history").send().await;
+  let history = res.0.take_body().into_json::<Vec<Value>>().await.unwrap();
+  assert_eq!(history.len(), 4);
+  assert_eq!(history[3]["action"], "revert");
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_get_item_as_of() {
+  let data_path = "test_get_item_as_of.json".to_string();
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let before_create = Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true);
+  client
+    .post("/items")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "OldName" }))
+    .send()
+    .await
+    .assert_status(StatusCode::CREATED);
+  let after_create = Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true);
+  client
+    .put("/items/1")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "NewName" }))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+
+  let res = client.get(format!("/items/1?as_of={}", after_create)).send().await;
+  res.assert_status(StatusCode::OK);
+  res.assert_json(json!({ "id": 1, "name": "OldName" })).await;
+
+  let res = client.get(format!("/items/1?as_of={}", before_create)).send().await;
+  res.assert_status(StatusCode::NOT_FOUND);
+
+  let res = client.get("/items/1?as_of=yesterday").send().await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+  delete_file_if_exists(&data_path);
+}
+
+
+// BULK
+#[tokio::test]
+async fn test_bulk_items_atomic() {
+  let data_path = "test_bulk_items_atomic.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Old".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let mut res = client
+    .post("/items/bulk")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({
+      "operations": [
+        { "op": "create", "name": "New" },
+        { "op": "update", "id": 1, "name": "Renamed", "version": 1 },
+        { "op": "delete", "id": 2 }
+      ]
+    }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::OK);
+  let body = res.0.take_body().into_json::<Value>().await.unwrap();
+  let statuses: Vec<u64> = body["results"].as_array().unwrap().iter().map(|r| r["status"].as_u64().unwrap()).collect();
+  assert_eq!(statuses, vec![201, 200, 200]);
+
+  let res = client.get("/items").send().await;
+  res.assert_json(json!([{ "id": 1, "name": "Renamed" }])).await;
+  let mut res = client.get("/items/trash").send().await;
+  let trash = res.0.take_body().into_json::<Vec<Value>>().await.unwrap();
+  assert_eq!(trash[0]["name"], "New");
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_bulk_items_atomic_failure_applies_nothing() {
+  let data_path = "test_bulk_items_atomic_failure_applies_nothing.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Old".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let res = client
+    .post("/items/bulk")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({
+      "mode": "atomic",
+      "operations": [
+        { "op": "create", "name": "New" },
+        { "op": "create", "name": "old" },
+        { "op": "delete", "id": 1 }
+      ]
+    }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+  res.assert_json(json!({
+    "results": [
+      { "status": 424, "error": "Not applied", "msg": "Another operation in the request failed" },
+      { "status": 409, "error": "Item already exists", "msg": "Please use a different name", "id": 1 },
+      { "status": 424, "error": "Not applied", "msg": "Another operation in the request failed" }
+    ]
+  })).await;
+
+  let res = client.get("/items").send().await;
+  res.assert_json(json!(items)).await;
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_bulk_items_best_effort() {
+  let data_path = "test_bulk_items_best_effort.json".to_string();
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let res = client
+    .post("/items/bulk")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({
+      "mode": "best_effort",
+      "operations": [
+        { "op": "create", "name": "NewItem1" },
+        { "op": "create", "name": "NewItem1" },
+        { "op": "delete", "id": 7 }
+      ]
+    }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::MULTI_STATUS);
+  res.assert_json(json!({
+    "results": [
+      { "status": 201, "item": { "id": 1, "name": "NewItem1" } },
+      { "status": 409, "error": "Item already exists", "msg": "Please use a different name", "id": 1 },
+      { "status": 404, "error": "Not found", "msg": "Item does not exist" }
+    ]
+  })).await;
+
+  let res = client.get("/items/search?q=newitem1").send().await;
+  res.assert_json(json!([{ "id": 1, "name": "NewItem1" }])).await;
+  delete_file_if_exists(&data_path);
+}
+
+
+#[tokio::test]
+async fn test_bulk_items_checked_like_single_items() {
+  let data_path = "test_bulk_items_checked_like_single_items.json".to_string();
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let consoles = post_category(&client, &token, "Consoles", None).await;
+  client
+    .put(format!("/categories/{}/attributes", consoles))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "attributes": { "storage_gb": { "type": "integer", "required": true } } }))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+
+  let mut res = client
+    .post("/items/bulk")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({
+      "mode": "best_effort",
+      "operations": [
+        {
+          "op": "create",
+          "name": "PS5 Pro",
+          "status": "announced",
+          "category_ids": [consoles],
+          "attributes": { "storage_gb": 2000 },
+          "barcodes": ["4948872415934"]
+        },
+        { "op": "create", "name": "Switch 2", "category_ids": [consoles] },
+        { "op": "create", "name": "Switch", "barcodes": ["4948872415934"] },
+        { "op": "update", "id": 1, "attributes": { "storage_gb": 1000 } }
+      ]
+    }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::MULTI_STATUS);
+  let body = res.0.take_body().into_json::<Value>().await.unwrap();
+  let results = body["results"].as_array().unwrap();
+  assert_eq!(results[0]["item"], json!({
+    "id": 1,
+    "name": "PS5 Pro",
+    "status": "announced",
+    "category_ids": [consoles],
+    "attributes": { "storage_gb": 2000 },
+    "barcodes": ["4948872415934"]
+  }));
+  assert_eq!((results[1]["status"].clone(), results[1]["errors"].clone()), (json!(400), json!(["storage_gb is required"])));
+  assert_eq!((results[2]["status"].clone(), results[2]["error"].clone(), results[2]["id"].clone()), (json!(409), json!("Barcode already in use"), json!(1)));
+  assert_eq!(results[3]["item"]["attributes"], json!({ "storage_gb": 1000 }));
+
+  let res = client.get("/items/1").send().await;
+  res.assert_header(header::ETAG, "\"2\"");
+  delete_file_if_exists(&data_path);
+}
+
+
+// IMPORT EXPORT
+#[tokio::test]
+async fn test_export_items() {
+  let data_path = "test_export_items.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Item, with comma".to_string() },
+    Item { id: 2, name: "Item2".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+
+  let mut res = client.get("/items/export?format=csv").send().await;
+  res.assert_status_is_ok();
+  res.assert_header(header::CONTENT_TYPE, "text/csv");
+  res.assert_header(header::CONTENT_DISPOSITION, "attachment; filename=\"items.csv\"");
+  let body = res.0.take_body().into_string().await.unwrap();
+  assert_eq!(body, concat!(
+    "id,name,parent_id,sku,options,public_id,slug,status,release_date,description,tags,category_ids,attributes,barcodes,translations\n",
+    "1,\"Item, with comma\",,,,,,,,,,,,,\n",
+    "2,Item2,,,,,,,,,,,,,\n"
+  ));
+
+  let mut res = client.get("/items/export?format=ndjson").send().await;
+  res.assert_header(header::CONTENT_TYPE, "application/x-ndjson");
+  let body = res.0.take_body().into_string().await.unwrap();
+  assert_eq!(body, "{\"id\":1,\"name\":\"Item, with comma\"}\n{\"id\":2,\"name\":\"Item2\"}\n");
+
+  let res = client.get("/items/export?format=json").send().await;
+  res.assert_json(json!(items)).await;
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_import_items_csv() {
+  let data_path = "test_import_items_csv.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Old".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let file = "Item ID,Title,Color\n1,Renamed,Blue\n,New,\n9,Missing,\n,,\n";
+
+  // Dry run reports every row and changes nothing
+  let res = client
+    .post("/items/import?map=Item%20ID:id,Title:name&dry_run=true&mode=best_effort")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .content_type("text/csv")
+    .body(file)
+    .send()
+    .await;
+  res.assert_status(StatusCode::MULTI_STATUS);
+  res.assert_json(json!({
+    "dry_run": true,
+    "applied": false,
+    "rows": 4,
+    "created": 2,
+    "updated": 1,
+    "errors": [
+      { "row": 5, "status": 400, "error": "Invalid row", "msg": "name is required" }
+    ],
+    "warnings": [
+      { "row": 2, "msg": "Import doesn't set color", "ignored": ["color"] }
+    ]
+  })).await;
+  let res = client.get("/items").send().await;
+  res.assert_json(json!(items)).await;
+
+  // Atomic import applies nothing when a row fails
+  let res = client
+    .post("/items/import?map=Item%20ID:id,Title:name")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body(file)
+    .send()
+    .await;
+  res.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+  let res = client.get("/items").send().await;
+  res.assert_json(json!(items)).await;
+
+  let res = client
+    .post("/items/import?map=Item%20ID:id,Title:name&mode=best_effort")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body(file)
+    .send()
+    .await;
+  res.assert_status(StatusCode::MULTI_STATUS);
+  let res = client.get("/items").send().await;
+  res.assert_json(json!([
+    { "id": 1, "name": "Renamed" },
+    { "id": 2, "name": "New" },
+    { "id": 9, "name": "Missing" }
+  ])).await;
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_import_items_ndjson() {
+  let data_path = "test_import_items_ndjson.json".to_string();
+  let items: Vec<Item> = vec![];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let res = client
+    .post("/items/import")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .content_type("application/x-ndjson")
+    .body("{\"name\": \"Item1\"}\n\n{\"name\": \"Item2\"}\n")
+    .send()
+    .await;
+  res.assert_status_is_ok();
+  res.assert_json(json!({
+    "dry_run": false,
+    "applied": true,
+    "rows": 2,
+    "created": 2,
+    "updated": 0,
+    "errors": [],
+    "warnings": []
+  })).await;
+
+  let res = client.get("/items/search?q=item2").send().await;
+  res.assert_json(json!([{ "id": 2, "name": "Item2" }])).await;
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_export_imports_as_it_was() {
+  let data_path = "test_export_imports_as_it_was.json".to_string();
+  let copy_path = "test_export_imports_as_it_was_copy.json".to_string();
+  let items = json!([
+    { "id": 2, "name": "Item2" },
+    {
+      "id": 5,
+      "name": "Item5",
+      "description": "Collector's box",
+      "public_id": "01JE4ZQ6W8X5C3V9B2N7M4K1PA",
+      "slug": "item5",
+      "status": "preorder",
+      "release_date": "2030-01-01T00:00:00+00:00",
+      "tags": ["limited", "rpg"],
+      "category_ids": [1],
+      "attributes": { "region": "japan" },
+      "barcodes": ["4902370548495"],
+      "translations": { "ja": { "name": "アイテム5" } }
+    },
+    { "id": 6, "parent_id": 5, "sku": "ITEM5-JP", "options": { "region": "Japan" }, "barcodes": ["4948872415934"] }
+  ]);
+  create_data(data_path.clone(), &items);
+  create_data(copy_path.clone(), &json!([]));
+  let client = TestClient::new(all_routes(data_path.clone()));
+  let copy_client = TestClient::new(all_routes(copy_path.clone()));
+  let token = get_jwt(&copy_client).await;
+  let games = post_category(&copy_client, &token, "Games", None).await;
+  copy_client
+    .put(format!("/categories/{}/attributes", games))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "attributes": { "region": { "type": "string" } } }))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+
+  for (format, content_type) in [("csv", "text/csv"), ("ndjson", "application/x-ndjson"), ("json", "application/json")] {
+    let mut res = client.get(format!("/items/export?format={}", format)).send().await;
+    let file = res.0.take_body().into_string().await.unwrap();
+    let res = copy_client
+      .post("/items/import")
+      .header(header::AUTHORIZATION, format!("Bearer {}", token))
+      .content_type(content_type)
+      .body(file)
+      .send()
+      .await;
+    res.assert_status_is_ok();
+    let copied: Value = from_str(&read_to_string(&copy_path).unwrap()).unwrap();
+    assert_eq!(copied, items, "{}", format);
+  }
+
+  // Ids of deleted items are never given again
+  copy_client
+    .delete("/items/2")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  let mut res = copy_client
+    .post("/items/import")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .content_type("application/json")
+    .body("[{ \"id\": 2, \"name\": \"Item2 again\" }]")
+    .send()
+    .await;
+  res.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+  let body = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(body["errors"][0]["status"], 409);
+  delete_file_if_exists(&data_path);
+  delete_file_if_exists(&copy_path);
+}
+
+
+#[tokio::test]
+async fn test_import_items_row_cap() {
+  let data_path = "test_import_items_row_cap.json".to_string();
+  let items: Vec<Item> = vec![];
+  create_data(data_path.clone(), &items);
+  let client = TestClient::new(all_routes(data_path.clone()));
+  let token = get_jwt(&client).await;
+
+  let rows: Vec<String> = (0..10_001).map(|i| format!("{{\"name\": \"Item{}\"}}", i)).collect();
+  for (content_type, file) in [("application/json", format!("[{}]", rows.join(","))), ("application/x-ndjson", rows.join("\n"))] {
+    let res = client
+      .post("/items/import")
+      .header(header::AUTHORIZATION, format!("Bearer {}", token))
+      .content_type(content_type)
+      .body(file)
+      .send()
+      .await;
+    res.assert_status(StatusCode::BAD_REQUEST);
+    res.assert_json(json!({ "error": "Invalid file", "msg": "Send between 1 and 10000 rows" })).await;
+  }
+
+  // New items start as draft or announced, rows with an id bring items back as they were
+  let mut res = client
+    .post("/items/import?mode=best_effort")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .content_type("application/x-ndjson")
+    .body("{\"name\": \"Item1\", \"status\": \"released\"}\n{\"id\": 7, \"name\": \"Item7\", \"status\": \"discontinued\"}\n")
+    .send()
+    .await;
+  res.assert_status(StatusCode::MULTI_STATUS);
+  let body = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!((body["created"].clone(), body["errors"][0]["error"].clone()), (json!(1), json!("Invalid status")));
+  let res = client
+    .post("/items/import")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .content_type("application/x-ndjson")
+    .body("{\"id\": 7, \"status\": \"released\", \"name\": \"Item7\"}\n")
+    .send()
+    .await;
+  res.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+  delete_file_if_exists(&data_path);
+}
+
+
+// CATEGORIES
+async fn post_category(client: &TestClient<Route>, token: &str, name: &str, parent_id: Option<u64>) -> u64 {
+  let mut res = client
+    .post("/categories")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": name, "parent_id": parent_id }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CREATED);
+  let body = res.0.take_body().into_json::<Value>().await.unwrap();
+  body["id"].as_u64().unwrap()
+}
+
+#[tokio::test]
+async fn test_category_tree() {
+  let data_path = "test_category_tree.json".to_string();
+  let items: Vec<Item> = vec![];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let games = post_category(&client, &token, "Games", None).await;
+  let ps5 = post_category(&client, &token, "PS5", Some(games)).await;
+  let rpg = post_category(&client, &token, "RPG", Some(ps5)).await;
+
+  let res = client.get(format!("/categories/{}", rpg)).send().await;
+  res.assert_json(json!({ "id": rpg, "name": "RPG", "parent_id": ps5, "path": ["Games", "PS5", "RPG"] })).await;
+
+  // Same name is fine under another parent but not next to a sibling
+  post_category(&client, &token, "RPG", Some(games)).await;
+  let res = client
+    .post("/categories")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "rpg", "parent_id": ps5 }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+
+  let res = client
+    .put(format!("/categories/{}", games))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "Games", "parent_id": rpg }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+  res.assert_json(json!({ "error": "Invalid parent", "msg": "A category can't be moved below itself" })).await;
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_category_attribute_schema() {
+  let data_path = "test_category_attribute_schema.json".to_string();
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let hardware = post_category(&client, &token, "Hardware", None).await;
+  let consoles = post_category(&client, &token, "Consoles", Some(hardware)).await;
+  let res = client
+    .put(format!("/categories/{}/attributes", hardware))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "attributes": { "color": { "type": "string", "max_length": 20 } } }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::OK);
+  let res = client
+    .put(format!("/categories/{}/attributes", consoles))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "attributes": {
+      "storage_gb": { "type": "integer", "minimum": 1, "required": true },
+      "region": { "type": "string", "enum": ["asia", "japan", "us"] }
+    } }))
+    .send()
+    .await;
+  res.assert_json(json!({
+    "id": consoles,
+    "name": "Consoles",
+    "parent_id": hardware,
+    "attributes": {
+      "region": { "type": "string", "required": false, "enum": ["asia", "japan", "us"] },
+      "storage_gb": { "type": "integer", "required": true, "minimum": 1.0 }
+    },
+    "path": ["Hardware", "Consoles"]
+  })).await;
+
+  // Attributes of the parent category are inherited
+  let res = client
+    .post("/items")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({
+      "name": "PS5 Pro",
+      "category_ids": [consoles],
+      "attributes": { "storage_gb": 2000, "region": "japan", "color": "white" }
+    }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CREATED);
+
+  let res = client
+    .post("/items")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({
+      "name": "Switch",
+      "category_ids": [consoles],
+      "attributes": { "region": "europe", "scale": "1/7" }
+    }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+  let body = res.0.into_body().into_json::<Value>().await.unwrap();
+  assert_eq!(body["errors"], json!([
+    "scale is not an attribute of the item's categories",
+    "region must be one of the enum values",
+    "storage_gb is required"
+  ]));
+
+  let res = client
+    .post("/items")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "Switch 2", "category_ids": [consoles], "attributes": { "storage_gb": 256 } }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CREATED);
+  let res = client
+    .patch("/items/2")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "attributes": { "storage_gb": 0 } }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+
+  let mut res = client.get("/items?attributes.storage_gb_min=512").send().await;
+  let page = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(page.as_array().unwrap().len(), 1);
+  assert_eq!(page[0]["name"], "PS5 Pro");
+  let mut res = client.get("/items?attributes.region=JAPAN").send().await;
+  let page = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(page[0]["id"], 1);
+
+  // A schema the items in the category don't match is refused
+  let res = client
+    .put(format!("/categories/{}/attributes", consoles))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "attributes": { "storage_gb": { "type": "string" } } }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+  res.assert_json(json!({
+    "error": "Items don't match the schema",
+    "msg": "Please update the attributes of the items first",
+    "item_ids": [1, 2]
+  })).await;
+
+  // Items coming back from the trash are checked against the schema as it is now
+  client
+    .delete("/items/2")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  client
+    .put(format!("/categories/{}/attributes", consoles))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "attributes": {
+      "storage_gb": { "type": "integer", "minimum": 512 },
+      "region": { "type": "string" }
+    } }))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  let res = client
+    .post("/items/2/restore")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+  let body = res.0.into_body().into_json::<Value>().await.unwrap();
+  assert_eq!(body["error"], "Invalid attributes");
+  client.get("/items/2").send().await.assert_status(StatusCode::NOT_FOUND);
+
+  // and lose the categories deleted in the meantime
+  let merch = post_category(&client, &token, "Merch", None).await;
+  let res = client
+    .post("/items")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "Poster", "category_ids": [merch] }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CREATED);
+  client
+    .delete("/items/3")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  client
+    .delete(format!("/categories/{}", merch))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  let res = client
+    .post("/items/3/restore")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status(StatusCode::OK);
+  res.assert_json(json!({ "id": 3, "name": "Poster" })).await;
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_category_items() {
+  let data_path = "test_category_items.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Item1".to_string() },
+    Item { id: 2, name: "Item2".to_string() },
+    Item { id: 3, name: "Item3".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let games = post_category(&client, &token, "Games", None).await;
+  let rpg = post_category(&client, &token, "RPG", Some(games)).await;
+
+  for (id, category_ids) in [(1, json!([games])), (2, json!([rpg, games])), (3, json!([999]))] {
+    let res = client
+      .put(format!("/items/{}/categories", id))
+      .header(header::AUTHORIZATION, format!("Bearer {}", token))
+      .body_json(&json!({ "category_ids": category_ids }))
+      .send()
+      .await;
+    if id == 3 {
+      res.assert_status(StatusCode::BAD_REQUEST);
+    } else {
+      res.assert_status_is_ok();
+    }
+  }
+
+  let res = client.get(format!("/categories/{}/items", games)).send().await;
+  res.assert_json(json!([
+    { "id": 1, "name": "Item1", "category_ids": [games] },
+    { "id": 2, "name": "Item2", "category_ids": [games, rpg] }
+  ])).await;
+  let res = client.get(format!("/categories/{}/items", rpg)).send().await;
+  res.assert_json(json!([{ "id": 2, "name": "Item2", "category_ids": [games, rpg] }])).await;
+
+  // Both items are directly in Games, paging and sorting work as on /items
+  let res = client.get(format!("/categories/{}/items?descendants=false&sort=-id", games)).send().await;
+  res.assert_json(json!([
+    { "id": 2, "name": "Item2", "category_ids": [games, rpg] },
+    { "id": 1, "name": "Item1", "category_ids": [games] }
+  ])).await;
+
+  let res = client
+    .delete(format!("/categories/{}", games))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+
+  let res = client
+    .put("/items/2/categories")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "category_ids": [] }))
+    .send()
+    .await;
+  res.assert_json(json!({ "id": 2, "name": "Item2" })).await;
+  let res = client
+    .delete(format!("/categories/{}", rpg))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status_is_ok();
+  let res = client.get("/categories").send().await;
+  res.assert_json(json!([{ "id": games, "name": "Games", "parent_id": null }])).await;
+  delete_file_if_exists(&data_path);
+}
+
+
+// TAGS
+#[tokio::test]
+async fn test_item_tags() {
+  let data_path = "test_item_tags.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Item1".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let res = client
+    .put("/items/1/tags")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "tags": ["Limited Edition", "import"] }))
+    .send()
+    .await;
+  res.assert_json(json!({ "id": 1, "name": "Item1", "tags": ["import", "limited-edition"] })).await;
+
+  let res = client
+    .post("/items/1/tags")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "tags": ["preorder", "import"] }))
+    .send()
+    .await;
+  res.assert_json(json!({ "id": 1, "name": "Item1", "tags": ["import", "limited-edition", "preorder"] })).await;
+
+  let res = client
+    .delete("/items/1/tags/import")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_json(json!({ "id": 1, "name": "Item1", "tags": ["limited-edition", "preorder"] })).await;
+
+  let res = client
+    .post("/items/1/tags")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "tags": ["no/slashes"] }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_get_items_by_tags() {
+  let data_path = "test_get_items_by_tags.json".to_string();
+  let items = vec![
+    json!({ "id": 1, "name": "Item1", "tags": ["import", "limited-edition"] }),
+    json!({ "id": 2, "name": "Item2", "tags": ["import", "preorder"] }),
+    json!({ "id": 3, "name": "Item3", "tags": ["limited-edition"] }),
+    json!({ "id": 4, "name": "Item4" })
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+
+  let res = client.get("/tags").send().await;
+  res.assert_json(json!([
+    { "tag": "import", "count": 2 },
+    { "tag": "limited-edition", "count": 2 },
+    { "tag": "preorder", "count": 1 }
+  ])).await;
+
+  let ids = |body: Vec<Value>| body.iter().map(|i| i["id"].as_u64().unwrap()).collect::<Vec<u64>>();
+  for (query, expected) in [
+    ("import,limited-edition", vec![1]),
+    ("preorder|limited-edition", vec![1, 2, 3]),
+    ("!import", vec![3, 4]),
+    ("import|limited-edition,!preorder", vec![1, 3]),
+  ] {
+    let mut res = client.get(format!("/items?tags={}", query)).send().await;
+    res.assert_status_is_ok();
+    let body = res.0.take_body().into_json::<Vec<Value>>().await.unwrap();
+    assert_eq!(ids(body), expected, "tags={}", query);
+  }
+  delete_file_if_exists(&data_path);
+}
+
+
+// STOCK
+async fn adjust_stock(client: &TestClient<Route>, token: &str, id: u64, adjustment: Value) -> StatusCode {
+  let res = client
+    .post(format!("/items/{}/stock/adjustments", id))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&adjustment)
+    .send()
+    .await;
+  res.0.status()
+}
+
+#[tokio::test]
+async fn test_stock_adjustments() {
+  let data_path = "test_stock_adjustments.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Item1".to_string() },
+    Item { id: 2, name: "Item2".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+
+  let adjustments = [
+    (json!({ "warehouse": "Tokyo", "reason": "receipt", "quantity": 10 }), StatusCode::CREATED),
+    (json!({ "warehouse": "Osaka", "reason": "receipt", "quantity": 5 }), StatusCode::CREATED),
+    (json!({ "warehouse": "Tokyo", "reason": "sale", "quantity": 3 }), StatusCode::CREATED),
+    (json!({ "warehouse": "Osaka", "reason": "damage", "quantity": 6 }), StatusCode::CONFLICT),
+    (json!({ "warehouse": "Osaka", "reason": "sale", "quantity": -1 }), StatusCode::BAD_REQUEST),
+    (json!({ "warehouse": "Osaka", "reason": "correction", "quantity": -2, "note": "Stock count" }), StatusCode::CREATED),
+  ];
+  for (adjustment, status) in adjustments {
+    assert_eq!(adjust_stock(&client, &token, 1, adjustment.clone()).await, status, "{}", adjustment);
+  }
+
+  let res = client.get("/items/1/stock").send().await;
+  res.assert_json(json!({
+    "item_id": 1,
+    "warehouses": { "Osaka": 3, "Tokyo": 7 },
+    "on_hand": 10,
+    "reserved": 0,
+    "available": 10,
+    "low_stock_threshold": null,
+    "low_stock": false
+  })).await;
+
+  let mut res = client.get("/items/1/stock/ledger").send().await;
+  let ledger = res.0.take_body().into_json::<Vec<Value>>().await.unwrap();
+  let changes: Vec<(i64, u64)> = ledger.iter().map(|e| (e["change"].as_i64().unwrap(), e["on_hand"].as_u64().unwrap())).collect();
+  assert_eq!(changes, vec![(10, 10), (5, 5), (-3, 7), (-2, 3)]);
+  assert_eq!(ledger[3]["reason"], "correction");
+  assert_eq!(ledger[3]["actor"], "admin1");
+
+  // Items without stock are shown as before
+  let res = client.get("/items").send().await;
+  res.assert_json(json!([
+    { "id": 1, "name": "Item1", "available": 10, "low_stock": false },
+    { "id": 2, "name": "Item2" }
+  ])).await;
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_low_stock() {
+  let data_path = "test_low_stock.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Item1".to_string() },
+    Item { id: 2, name: "Item2".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  adjust_stock(&client, &token, 1, json!({ "warehouse": "Tokyo", "reason": "receipt", "quantity": 5 })).await;
+  adjust_stock(&client, &token, 2, json!({ "warehouse": "Tokyo", "reason": "receipt", "quantity": 2 })).await;
+  for id in [1, 2] {
+    let res = client
+      .put(format!("/items/{}/stock/threshold", id))
+      .header(header::AUTHORIZATION, format!("Bearer {}", token))
+      .body_json(&json!({ "threshold": 3 }))
+      .send()
+      .await;
+    res.assert_status_is_ok();
+  }
+
+  let res = client.get("/stock/low").send().await;
+  res.assert_json(json!([{ "id": 2, "name": "Item2", "available": 2, "low_stock": true }])).await;
+
+  // Stock changes show up in the item's ETag
+  let res = client.get("/items/1").send().await;
+  res.assert_header(header::ETAG, "\"3\"");
+  adjust_stock(&client, &token, 1, json!({ "warehouse": "Tokyo", "reason": "sale", "quantity": 2 })).await;
+  let res = client.get("/items/1").send().await;
+  res.assert_header(header::ETAG, "\"4\"");
+  res.assert_json(json!({ "id": 1, "name": "Item1", "available": 3, "low_stock": true })).await;
+  delete_file_if_exists(&data_path);
+}
+
+
+// RESERVATIONS
+async fn reserve(client: &TestClient<Route>, token: &str, id: u64, reservation: Value) -> (StatusCode, Value) {
+  let mut res = client
+    .post(format!("/items/{}/reservations", id))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&reservation)
+    .send()
+    .await;
+  let status = res.0.status();
+  (status, res.0.take_body().into_json::<Value>().await.unwrap())
+}
+
+#[tokio::test]
+async fn test_reservations() {
+  let data_path = "test_reservations.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Item1".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  adjust_stock(&client, &token, 1, json!({ "warehouse": "Tokyo", "reason": "receipt", "quantity": 5 })).await;
+
+  let (status, first) = reserve(&client, &token, 1, json!({ "warehouse": "Tokyo", "quantity": 3 })).await;
+  assert_eq!(status, StatusCode::CREATED);
+  assert_eq!(first["status"], "active");
+  let (status, _) = reserve(&client, &token, 1, json!({ "warehouse": "Tokyo", "quantity": 3 })).await;
+  assert_eq!(status, StatusCode::CONFLICT);
+  let (_, second) = reserve(&client, &token, 1, json!({ "warehouse": "Tokyo", "quantity": 2 })).await;
+
+  let mut res = client.get("/items/1/stock").send().await;
+  let stock = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!((stock["on_hand"].as_u64(), stock["reserved"].as_u64(), stock["available"].as_u64()), (Some(5), Some(5), Some(0)));
+
+  // Reserved units can't be sold or written off another way
+  let status = adjust_stock(&client, &token, 1, json!({ "warehouse": "Tokyo", "reason": "sale", "quantity": 1 })).await;
+  assert_eq!(status, StatusCode::CONFLICT);
+  let status = adjust_stock(&client, &token, 1, json!({ "warehouse": "Tokyo", "reason": "correction", "quantity": -1 })).await;
+  assert_eq!(status, StatusCode::CONFLICT);
+
+  let res = client
+    .post(format!("/reservations/{}/confirm", first["id"]))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status_is_ok();
+  let res = client
+    .post(format!("/reservations/{}/release", second["id"]))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status_is_ok();
+  let res = client
+    .post(format!("/reservations/{}/release", first["id"]))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+  res.assert_json(json!({ "error": "Reservation is not active", "msg": "Reservation is already confirmed" })).await;
+
+  let res = client.get("/items/1").send().await;
+  res.assert_json(json!({ "id": 1, "name": "Item1", "available": 2, "low_stock": false })).await;
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_reservations_expire() {
+  let data_path = "test_reservations_expire.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Item1".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  adjust_stock(&client, &token, 1, json!({ "warehouse": "Tokyo", "reason": "receipt", "quantity": 1 })).await;
+  let (_, reservation) = reserve(&client, &token, 1, json!({ "warehouse": "Tokyo", "quantity": 1, "ttl_seconds": 1 })).await;
+
+  tokio::time::sleep(Duration::from_millis(1100)).await;
+  // Expired holds stop counting before the sweeper runs
+  let res = client.get("/items/1").send().await;
+  res.assert_json(json!({ "id": 1, "name": "Item1", "available": 1, "low_stock": false })).await;
+
+  assert_eq!(expire_reservations(&data_path).await.unwrap(), 1);
+  let res = client.get(format!("/reservations/{}", reservation["id"])).send().await;
+  let mut expected = reservation.clone();
+  expected["status"] = json!("expired");
+  res.assert_json(expected).await;
+
+  let res = client
+    .post(format!("/reservations/{}/confirm", reservation["id"]))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_concurrent_reservations() {
+  let data_path = "test_concurrent_reservations.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Item1".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  adjust_stock(&client, &token, 1, json!({ "warehouse": "Tokyo", "reason": "receipt", "quantity": 5 })).await;
+
+  let attempts = (0..10).map(|_| reserve(&client, &token, 1, json!({ "warehouse": "Tokyo", "quantity": 1 })));
+  let results = join_all(attempts).await;
+  let created = results.iter().filter(|(status, _)| *status == StatusCode::CREATED).count();
+  assert_eq!(created, 5);
+
+  let res = client.get("/items/1").send().await;
+  res.assert_json(json!({ "id": 1, "name": "Item1", "available": 0, "low_stock": false })).await;
+  delete_file_if_exists(&data_path);
+}
+
+
+// PRICES
+async fn post_price(client: &TestClient<Route>, token: &str, id: u64, price: Value) -> (StatusCode, Value) {
+  let mut res = client
+    .post(format!("/items/{}/prices", id))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&price)
+    .send()
+    .await;
+  let status = res.0.status();
+  (status, res.0.take_body().into_json::<Value>().await.unwrap())
+}
+
+async fn get_price(client: &TestClient<Route>, query: &str) -> Value {
+  let mut res = client.get(format!("/items/1?{}", query)).send().await;
+  res.assert_status_is_ok();
+  let body = res.0.take_body().into_json::<Value>().await.unwrap();
+  body["price"].clone()
+}
+
+#[tokio::test]
+async fn test_item_prices() {
+  let data_path = "test_item_prices.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Item1".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  create_data("test_item_prices.rates.json".to_string(), &json!({
+    "base": "USD",
+    "rates": { "JPY": 151.37, "EUR": 0.9213 }
+  }));
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+
+  let (status, _) = post_price(&client, &token, 1, json!({ "currency": "USD", "amount": "19.99" })).await;
+  assert_eq!(status, StatusCode::CREATED);
+  post_price(&client, &token, 1, json!({ "region": "JP", "currency": "JPY", "amount": 2980 })).await;
+  let (status, _) = post_price(&client, &token, 1, json!({ "region": "JP", "currency": "JPY", "amount": "2980.5" })).await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  let price = get_price(&client, "currency=usd").await;
+  assert_eq!((price["amount"].clone(), price["derived"].clone()), (json!("19.99"), json!(false)));
+
+  // 19.99 USD * 0.9213 = 18.416787 EUR
+  let price = get_price(&client, "currency=EUR&region=US").await;
+  assert_eq!(price["amount"], "18.42");
+  assert_eq!(price["region"], "global");
+  assert_eq!(price["derived"], true);
+
+  let price = get_price(&client, "currency=JPY&region=JP").await;
+  assert_eq!((price["amount"].clone(), price["amount_minor"].clone()), (json!("2980"), json!(2980)));
+
+  // 19.99 USD * 151.37 = 3025.8863 JPY, yen has no minor unit
+  let price = get_price(&client, "currency=JPY").await;
+  assert_eq!(price["amount"], "3026");
+
+  let price = get_price(&client, "currency=GBP").await;
+  assert_eq!(price, Value::Null);
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_get_items_by_price_and_stock() {
+  let data_path = "test_get_items_by_price_and_stock.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Item1".to_string() },
+    Item { id: 2, name: "Item2".to_string() },
+    Item { id: 3, name: "Item3".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  post_price(&client, &token, 1, json!({ "currency": "USD", "amount": "19.99" })).await;
+  post_price(&client, &token, 2, json!({ "currency": "USD", "amount": "5.00" })).await;
+  adjust_stock(&client, &token, 2, json!({ "warehouse": "Tokyo", "reason": "receipt", "quantity": 3 })).await;
+
+  let ids = |items: Value| -> Vec<u64> {
+    items.as_array().unwrap().iter().map(|item| item["id"].as_u64().unwrap()).collect()
+  };
+  let mut res = client.get("/items?currency=USD&price_min=10").send().await;
+  let body = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(ids(body.clone()), vec![1]);
+  assert_eq!(body[0]["price"]["amount"], "19.99");
+
+  // Items without a price go last
+  let mut res = client.get("/items?currency=USD&sort=price").send().await;
+  assert!(res.0.headers().get(header::ETAG).is_none());
+  assert_eq!(ids(res.0.take_body().into_json::<Value>().await.unwrap()), vec![2, 1, 3]);
+
+  let mut res = client.get("/items?available_min=1").send().await;
+  assert_eq!(ids(res.0.take_body().into_json::<Value>().await.unwrap()), vec![2]);
+
+  let res = client.get("/items?currency=dollars&sort=price").send().await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_item_price_effective_dates() {
+  let data_path = "test_item_price_effective_dates.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Item1".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let past = (Utc::now() - chrono::Duration::days(1)).to_rfc3339_opts(SecondsFormat::Secs, true);
+  let future = (Utc::now() + chrono::Duration::days(1)).to_rfc3339_opts(SecondsFormat::Secs, true);
+
+  post_price(&client, &token, 1, json!({ "currency": "USD", "amount": "10", "effective_from": past })).await;
+  let (_, scheduled) = post_price(&client, &token, 1, json!({ "currency": "USD", "amount": "12.50", "effective_from": future })).await;
+
+  let price = get_price(&client, "currency=USD").await;
+  assert_eq!(price["amount"], "10.00");
+
+  let mut res = client.get("/items/1/prices").send().await;
+  let prices = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(prices["current"].as_array().unwrap().len(), 1);
+  assert_eq!(prices["history"][0]["amount"], "10.00");
+  assert_eq!(prices["upcoming"][0]["amount"], "12.50");
+
+  let res = client
+    .delete(format!("/items/1/prices/{}", prices["current"][0]["id"]))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+  let res = client
+    .delete(format!("/items/1/prices/{}", scheduled["id"]))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status_is_ok();
+
+  // The id of the cancelled price isn't given again
+  let (_, rescheduled) = post_price(&client, &token, 1, json!({ "currency": "USD", "amount": "13", "effective_from": future })).await;
+  assert_ne!(rescheduled["id"], scheduled["id"]);
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_scheduled_sales() {
+  let data_path = "test_scheduled_sales.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Item1".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  post_price(&client, &token, 1, json!({ "currency": "USD", "amount": "20" })).await;
+  post_price(&client, &token, 1, json!({ "currency": "JPY", "amount": "3000" })).await;
+
+  let starts_at = (Utc::now() + chrono::Duration::seconds(1)).to_rfc3339_opts(SecondsFormat::Millis, true);
+  let ends_at = (Utc::now() + chrono::Duration::seconds(2)).to_rfc3339_opts(SecondsFormat::Millis, true);
+  let mut res = client
+    .post("/items/1/sales")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "percent_off": 25, "starts_at": starts_at, "ends_at": ends_at }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CREATED);
+  let sale = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(sale["status"], "scheduled");
+
+  let res = client
+    .post("/items/1/sales")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "region": " ", "percent_off": 25, "ends_at": ends_at }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+
+  let price = get_price(&client, "currency=USD").await;
+  assert_eq!(price["amount"], "20.00");
+
+  // Prices follow the scheduler, not the clock
+  tokio::time::sleep(Duration::from_millis(1100)).await;
+  let price = get_price(&client, "currency=USD").await;
+  assert_eq!(price["amount"], "20.00");
+  assert_eq!(run_price_schedule(&data_path).await.unwrap(), 1);
+  let price = get_price(&client, "currency=JPY").await;
+  assert_eq!((price["amount"].clone(), price["regular_amount"].clone()), (json!("2250"), json!("3000")));
+  let mut res = client.get("/items/1/prices").send().await;
+  let prices = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(prices["sales"]["active"][0]["status"], "active");
+
+  tokio::time::sleep(Duration::from_millis(1000)).await;
+  assert_eq!(run_price_schedule(&data_path).await.unwrap(), 1);
+  let price = get_price(&client, "currency=USD").await;
+  assert_eq!(price["amount"], "20.00");
+  assert_eq!(price.get("sale"), None);
+  let mut res = client.get("/items/1/prices").send().await;
+  let prices = res.0.take_body().into_json::<Value>().await.unwrap();
+  let ended = &prices["sales"]["ended"][0];
+  assert_eq!(ended["status"], "ended");
+  assert!(ended["started_at"].is_string() && ended["ended_at"].is_string());
+  delete_file_if_exists(&data_path);
+}
+
+
+// TRANSLATIONS
+#[tokio::test]
+async fn test_item_translations() {
+  let data_path = "test_item_translations.json".to_string();
+  let items = vec![
+    json!({ "id": 1, "name": "Zelda", "description": "Adventure" }),
+    json!({ "id": 2, "name": "Mario" })
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let res = client
+    .put("/items/1/translations/ja")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "ゼルダの伝説" }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::OK);
+  res.assert_json(json!({
+    "id": 1,
+    "name": "Zelda",
+    "description": "Adventure",
+    "translations": { "ja": { "name": "ゼルダの伝説" } }
+  })).await;
+
+  let res = client.get("/items/1?lang=ja").send().await;
+  res.assert_header(header::CONTENT_LANGUAGE, "ja");
+  let item = res.0.into_body().into_json::<Value>().await.unwrap();
+  assert_eq!(item["name"], "ゼルダの伝説");
+  assert_eq!(item["description"], "Adventure");
+
+  // ja-JP falls back to ja, unknown languages to the default locale
+  let mut res = client.get("/items/1").header(header::ACCEPT_LANGUAGE, "ja-JP, en;q=0.5").send().await;
+  let item = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(item["name"], "ゼルダの伝説");
+  let res = client.get("/items/1").header(header::ACCEPT_LANGUAGE, "fr").send().await;
+  res.assert_header(header::CONTENT_LANGUAGE, "en");
+  let en_etag = res.0.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+  let item = res.0.into_body().into_json::<Value>().await.unwrap();
+  assert_eq!(item["name"], "Zelda");
+
+  // Every locale is tagged on its own, the tags still work for writes
+  let res = client.get("/items/1?lang=ja").send().await;
+  let ja_etag = res.0.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+  assert_ne!(ja_etag, en_etag);
+  let res = client.get("/items/1?lang=ja").header(header::IF_NONE_MATCH, &ja_etag).send().await;
+  res.assert_status(StatusCode::NOT_MODIFIED);
+  let res = client.get("/items/1?lang=en").header(header::IF_NONE_MATCH, &ja_etag).send().await;
+  res.assert_status(StatusCode::OK);
+  let res = client
+    .patch("/items/1")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .header(header::IF_MATCH, &ja_etag)
+    .body_json(&json!({ "description": "Adventure" }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::OK);
+
+  let mut res = client.get("/items?lang=ja").send().await;
+  let page = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(page[0]["name"], "ゼルダの伝説");
+  assert_eq!(page[1], json!({ "id": 2, "name": "Mario" }));
+
+  // Names are unique across locales
+  let res = client
+    .put("/items/2/translations/fr")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "ゼルダの伝説" }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+
+  let res = client
+    .put("/items/2/translations/123")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "Mario" }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+
+  // A translated name taken while the item was in the trash blocks the restore
+  client
+    .delete("/items/1")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  let translate = |name: &'static str| client
+    .put("/items/2/translations/fr")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": name }))
+    .send();
+  translate("ゼルダの伝説").await.assert_status(StatusCode::OK);
+  let restore = || client
+    .post("/items/1/restore")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send();
+  restore().await.assert_status(StatusCode::CONFLICT);
+  translate("Mario").await.assert_status(StatusCode::OK);
+  restore().await.assert_status(StatusCode::OK);
+
+  let res = client
+    .delete("/items/1/translations/ja")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_json(json!({ "id": 1, "name": "Zelda", "description": "Adventure" })).await;
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_search_items_in_every_locale() {
+  let data_path = "test_search_items_in_every_locale.json".to_string();
+  let items = vec![
+    json!({ "id": 1, "name": "Zelda", "translations": { "ja": { "name": "ゼルダの伝説" } } }),
+    json!({ "id": 2, "name": "Mario" })
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+
+  let mut res = client.get("/items/search?q=%E3%82%BC%E3%83%AB%E3%83%80").send().await; // ゼルダ
+  let results = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(results.as_array().unwrap().len(), 1);
+  assert_eq!(results[0]["id"], 1);
+
+  let mut res = client.get("/items/search?q=zelda&lang=ja").send().await;
+  let results = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(results[0]["name"], "ゼルダの伝説");
+  delete_file_if_exists(&data_path);
+}
+
+
+// VARIANTS
+#[tokio::test]
+async fn test_item_variants() {
+  let data_path = "test_item_variants.json".to_string();
+  let items = vec![
+    json!({ "id": 1, "name": "Elden Ring", "description": "Action RPG" })
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  for (sku, options) in [
+    ("elden-std-jp", json!({ "edition": "Standard", "region": "Japan" })),
+    ("ELDEN-CE-US", json!({ "edition": "Collector's", "region": "US" }))
+  ] {
+    client
+      .post("/items/1/variants")
+      .header(header::AUTHORIZATION, format!("Bearer {}", token))
+      .body_json(&json!({ "sku": sku, "options": options }))
+      .send()
+      .await
+      .assert_status(StatusCode::CREATED);
+  }
+
+  let res = client.get("/items/1/variants").send().await;
+  res.assert_json(json!([
+    {
+      "id": 2,
+      "parent_id": 1,
+      "sku": "ELDEN-STD-JP",
+      "options": { "edition": "Standard", "region": "Japan" },
+      "name": "Elden Ring",
+      "description": "Action RPG"
+    },
+    {
+      "id": 3,
+      "parent_id": 1,
+      "sku": "ELDEN-CE-US",
+      "options": { "edition": "Collector's", "region": "US" },
+      "name": "Elden Ring",
+      "description": "Action RPG"
+    }
+  ])).await;
+
+  // Variants have their own stock
+  let status = adjust_stock(&client, &token, 3, json!({ "warehouse": "us", "reason": "receipt", "quantity": 5 })).await;
+  assert_eq!(status, StatusCode::CREATED);
+  let mut res = client.get("/items/3").send().await;
+  let variant = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(variant["name"], "Elden Ring");
+  assert_eq!(variant["available"], 5);
+  let mut res = client.get("/items/2").send().await;
+  let variant = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(variant.get("available"), None);
+
+  let res = client
+    .post("/items/1/variants")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "sku": "ELDEN-STD-JP", "options": { "region": "Asia" } }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+  let res = client
+    .post("/items/1/variants")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "sku": "ELDEN-STD-JP-2", "options": { "edition": "standard", "region": "japan" } }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+  let res = client
+    .post("/items/1/variants")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "sku": "ELDEN-X", "options": { "platform": "PS5" } }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+  let res = client
+    .post("/items/2/variants")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "sku": "ELDEN-Y", "options": { "language": "English" } }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+
+  let res = client
+    .put("/items/1/variants/2")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "options": { "edition": "Standard", "region": "Asia", "language": "English" } }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::OK);
+  let variant = res.0.into_body().into_json::<Value>().await.unwrap();
+  assert_eq!(variant["sku"], "ELDEN-STD-JP");
+  assert_eq!(variant["options"]["region"], "Asia");
+
+  // Variants take the name of their parent, their other fields can change
+  let res = client
+    .patch("/items/2")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "Elden Ring Asia" }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+  let res = client
+    .patch("/items/2")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "Elden Ring", "barcodes": ["4902370548495"] }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::OK);
+  let variant = res.0.into_body().into_json::<Value>().await.unwrap();
+  assert_eq!((variant["name"].clone(), variant["barcodes"].clone()), (json!("Elden Ring"), json!(["4902370548495"])));
+  let mut res = client
+    .post("/items/bulk")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "operations": [{ "op": "update", "id": 2, "name": "Elden Ring Asia" }] }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+  let body = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(body["results"][0]["status"], 400);
+  let mut res = client
+    .post("/items/bulk")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "operations": [{ "op": "update", "id": 2, "name": "Elden Ring" }] }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::OK);
+  let body = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(body["results"][0]["item"]["sku"], "ELDEN-STD-JP");
+
+  // Reverting can't bring back options another variant took in the meantime
+  client
+    .post("/items/1/variants")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "sku": "ELDEN-STD-JP-NEW", "options": { "edition": "Standard", "region": "Japan" } }))
+    .send()
+    .await
+    .assert_status(StatusCode::CREATED);
+  let res = client
+    .post("/items/2/revert")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "revision": 2 }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+
+  // The parent stays until its variants are gone
+  let res = client
+    .delete("/items/1")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+
+  // A restored variant needs its SKU free and its parent back
+  let delete = |id: u64| client
+    .delete(format!("/items/{}", id))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send();
+  let restore = |id: u64| client
+    .post(format!("/items/{}/restore", id))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send();
+  delete(4).await.assert_status(StatusCode::OK);
+  client
+    .post("/items/1/variants")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "sku": "ELDEN-STD-JP-NEW", "options": { "edition": "Deluxe" } }))
+    .send()
+    .await
+    .assert_status(StatusCode::CREATED);
+  restore(4).await.assert_status(StatusCode::CONFLICT);
+  for id in [5, 3, 2, 1] {
+    delete(id).await.assert_status(StatusCode::OK);
+  }
+  restore(2).await.assert_status(StatusCode::CONFLICT);
+  restore(1).await.assert_status(StatusCode::OK);
+  restore(2).await.assert_status(StatusCode::OK);
+  delete_file_if_exists(&data_path);
+}
+
+
+// BUNDLES
+#[tokio::test]
+async fn test_item_bundles() {
+  let data_path = "test_item_bundles.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Console".to_string() },
+    Item { id: 2, name: "Controller".to_string() },
+    Item { id: 3, name: "Console Bundle".to_string() },
+    Item { id: 4, name: "Holiday Bundle".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  adjust_stock(&client, &token, 1, json!({ "warehouse": "main", "reason": "receipt", "quantity": 5 })).await;
+  adjust_stock(&client, &token, 2, json!({ "warehouse": "main", "reason": "receipt", "quantity": 6 })).await;
+
+  let res = client
+    .put("/items/3/components")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "components": [{ "item_id": 1, "quantity": 1 }, { "item_id": 2, "quantity": 2 }] }))
+    .send()
+    .await;
+  res.assert_json(json!({
+    "id": 3,
+    "name": "Console Bundle",
+    "components": [{ "item_id": 1, "quantity": 1 }, { "item_id": 2, "quantity": 2 }],
+    "available": 3
+  })).await;
+
+  client
+    .put("/items/4/components")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "components": [{ "item_id": 3, "quantity": 2 }] }))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+
+  // Availability follows the stock of the components
+  adjust_stock(&client, &token, 2, json!({ "warehouse": "main", "reason": "sale", "quantity": 4 })).await;
+  let mut res = client.get("/items/3").send().await;
+  let bundle = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(bundle["available"], 1);
+  let mut res = client.get("/items/4").send().await;
+  let bundle = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(bundle["available"], 0);
+
+  // Cycles and missing items are refused
+  let res = client
+    .put("/items/3/components")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "components": [{ "item_id": 4, "quantity": 1 }] }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+  let res = client
+    .put("/items/1/components")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "components": [{ "item_id": 9, "quantity": 1 }] }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+  let res = client
+    .put("/items/1/components")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "components": [{ "item_id": 2, "quantity": u64::MAX }, { "item_id": 2, "quantity": u64::MAX }] }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+
+  let res = client
+    .delete("/items/2")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+  res.assert_json(json!({
+    "error": "Item is in bundles",
+    "msg": "Please remove it from the bundles first",
+    "bundle_ids": [3]
+  })).await;
+
+  // Bulk deletes are held to the same rules
+  let res = client
+    .post("/items/bulk")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "operations": [{ "op": "delete", "id": 2 }] }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+  res.assert_json(json!({
+    "results": [{
+      "status": 409,
+      "error": "Item is in bundles",
+      "msg": "Please remove it from the bundles first",
+      "bundle_ids": [3]
+    }]
+  })).await;
+  let res = client.get("/items/2").send().await;
+  res.assert_status_is_ok();
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_bundle_reservations() {
+  let data_path = "test_bundle_reservations.json".to_string();
+  let data = json!([
+    { "id": 1, "name": "Console" },
+    { "id": 2, "name": "Controller" },
+    { "id": 3, "name": "Console Bundle", "components": [{ "item_id": 1, "quantity": 1 }, { "item_id": 2, "quantity": 2 }] },
+    { "id": 4, "name": "Holiday Bundle", "components": [{ "item_id": 3, "quantity": 2 }] },
+    { "id": 5, "name": "Starter Pack", "components": [{ "item_id": 1, "quantity": 1 }] },
+    { "id": 6, "name": "Starter Bundle", "components": [{ "item_id": 1, "quantity": 1 }, { "item_id": 5, "quantity": 1 }] }
+  ]);
+  create_data(data_path.clone(), &data);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  adjust_stock(&client, &token, 1, json!({ "warehouse": "main", "reason": "receipt", "quantity": 5 })).await;
+  adjust_stock(&client, &token, 2, json!({ "warehouse": "main", "reason": "receipt", "quantity": 6 })).await;
+
+  // The components are held, bundles in the bundle included
+  let (status, reservations) = reserve(&client, &token, 4, json!({ "warehouse": "main", "quantity": 1 })).await;
+  assert_eq!(status, StatusCode::CREATED);
+  let held: Vec<(&Value, &Value)> = reservations.as_array().unwrap().iter().map(|r| (&r["item_id"], &r["quantity"])).collect();
+  assert_eq!(held, vec![(&json!(1), &json!(2)), (&json!(2), &json!(4))]);
+  let mut res = client.get("/items/3").send().await;
+  assert_eq!(res.0.take_body().into_json::<Value>().await.unwrap()["available"], 1);
+
+  let (status, error) = reserve(&client, &token, 4, json!({ "warehouse": "main", "quantity": 1 })).await;
+  assert_eq!(status, StatusCode::CONFLICT);
+  assert_eq!(error["msg"], "Only 0 available in the warehouse");
+  let mut res = client.get("/items/1/stock").send().await;
+  assert_eq!(res.0.take_body().into_json::<Value>().await.unwrap()["available"], 3);
+
+  // Expiring the holds changes the bundles above too
+  let (status, _reservations) = reserve(&client, &token, 3, json!({ "warehouse": "main", "quantity": 1, "ttl_seconds": 1 })).await;
+  assert_eq!(status, StatusCode::CREATED);
+  let res = client.get("/items/4").send().await;
+  let etag = res.0.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+  tokio::time::sleep(Duration::from_millis(1100)).await;
+  assert_eq!(expire_reservations(&data_path).await.unwrap(), 2);
+  let res = client.get("/items/4").send().await;
+  assert_ne!(res.0.headers().get(header::ETAG).unwrap().to_str().unwrap(), etag);
+
+  // A component in the bundle directly and through another bundle counts twice
+  let mut res = client.get("/items/6").send().await;
+  assert_eq!(res.0.take_body().into_json::<Value>().await.unwrap()["available"], 1);
+  let (status, error) = reserve(&client, &token, 6, json!({ "warehouse": "main", "quantity": 2 })).await;
+  assert_eq!(status, StatusCode::CONFLICT);
+  assert_eq!(error["msg"], "Only 1 available in the warehouse");
+
+  // A bundle whose components are gone stays in the trash
+  for id in [6, 5] {
+    client
+      .delete(format!("/items/{}", id))
+      .header(header::AUTHORIZATION, format!("Bearer {}", token))
+      .send()
+      .await
+      .assert_status(StatusCode::OK);
+  }
+  let res = client
+    .post("/items/6/restore")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+  delete_file_if_exists(&data_path);
+}
+
+
+// MEDIA
+fn png(width: u32, height: u32) -> Vec<u8> {
+  let mut bytes = Vec::new();
+  image::RgbImage::new(width, height)
+    .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+    .unwrap();
+  bytes
+}
+
+async fn upload(client: &TestClient<Route>, token: &str, id: u64, bytes: Vec<u8>) -> (StatusCode, Value) {
+  let mut res = client
+    .post(format!("/items/{}/media", id))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .multipart(TestForm::new().field(TestFormField::bytes(bytes).name("file").filename("upload")))
+    .send()
+    .await;
+  let status = res.0.status();
+  (status, res.0.take_body().into_json::<Value>().await.unwrap_or_default())
+}
+
+#[tokio::test]
+async fn test_item_media() {
+  let data_path = "test_item_media.json".to_string();
+  let items: Vec<Item> = vec![
+    Item { id: 1, name: "Item1".to_string() }
+  ];
+  create_data(data_path.clone(), &items);
+  let config = Config { max_media_size: 64 * 1024, ..Config::default() };
+  let routes = all_routes_with_config(data_path.clone(), config);
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+
+  let (status, media) = upload(&client, &token, 1, png(600, 300)).await;
+  assert_eq!(status, StatusCode::CREATED);
+  assert_eq!(media["content_type"], "image/png");
+  assert_eq!((media["width"].as_u64(), media["height"].as_u64()), (Some(600), Some(300)));
+
+  let mut res = client.get("/items/1").send().await;
+  let item = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(item["media"][0]["url"], media["url"]);
+
+  let res = client.get(media["thumbnail_url"].as_str().unwrap()).send().await;
+  res.assert_status(StatusCode::OK);
+  res.assert_content_type("image/png");
+  let thumbnail = image::load_from_memory(&res.0.into_body().into_vec().await.unwrap()).unwrap();
+  assert_eq!((thumbnail.width(), thumbnail.height()), (256, 128));
+
+  // The type comes from the bytes, not the upload
+  let (status, _) = upload(&client, &token, 1, b"hello".to_vec()).await;
+  assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+  let mut too_large = png(1, 1);
+  too_large.resize(64 * 1024 + 1, 0);
+  let (status, _) = upload(&client, &token, 1, too_large).await;
+  assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+  let (_, second) = upload(&client, &token, 1, png(10, 10)).await;
+  let res = client
+    .delete(format!("/items/1/media/{}", second["id"].as_str().unwrap()))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status(StatusCode::OK);
+  client.get(second["url"].as_str().unwrap()).send().await.assert_status(StatusCode::NOT_FOUND);
+
+  // Reverting keeps the media as it is, the deleted blobs are gone
+  let res = client
+    .post("/items/1/revert")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "revision": 3 }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::OK);
+  let item = res.0.into_body().into_json::<Value>().await.unwrap();
+  assert_eq!(item["media"].as_array().unwrap().len(), 1);
+
+  // A refused upload leaves no blobs behind
+  let store = LocalBlobStore::new("test_item_media.media");
+  let blobs = store.list().unwrap().len();
+  let res = client
+    .post("/items/1/media")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .header(header::IF_MATCH, "\"1\"")
+    .multipart(TestForm::new().field(TestFormField::bytes(png(10, 10)).name("file").filename("upload")))
+    .send()
+    .await;
+  res.assert_status(StatusCode::PRECONDITION_FAILED);
+  assert_eq!(store.list().unwrap().len(), blobs);
+
+  // The blobs go once the item is purged from the trash
+  client
+    .delete("/items/1")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  assert_eq!(purge_media(&data_path, &store).await.unwrap(), 0);
+  purge_trash(&data_path, Duration::ZERO).await.unwrap();
+  assert_eq!(purge_media(&data_path, &store).await.unwrap(), 2);
+  client.get(media["url"].as_str().unwrap()).send().await.assert_status(StatusCode::NOT_FOUND);
+  remove_dir_all("test_item_media.media").unwrap();
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_item_media_too_large_to_decode() {
+  let data_path = "test_item_media_too_large_to_decode.json".to_string();
+  create_data(data_path.clone(), &vec![Item { id: 1, name: "Item1".to_string() }]);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+
+  // A few bytes can claim a huge image, it is kept without a thumbnail
+  let (status, media) = upload(&client, &token, 1, png(8193, 1)).await;
+  assert_eq!(status, StatusCode::CREATED);
+  assert_eq!(media["content_type"], "image/png");
+  assert_eq!(media["thumbnail_url"], Value::Null);
+  assert_eq!(media["width"], Value::Null);
+  delete_file_if_exists(&data_path);
+}
+
+
+// LIFECYCLE
+async fn put_status(client: &TestClient<Route>, token: &str, id: u64, status: Value) -> StatusCode {
+  client
+    .put(format!("/items/{}/status", id))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&status)
+    .send()
+    .await
+    .0
+    .status()
+}
+
+#[tokio::test]
+async fn test_item_lifecycle() {
+  let data_path = "test_item_lifecycle.json".to_string();
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let res = client
+    .post("/items")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "Ghost of Yotei", "status": "draft" }))
+    .send()
+    .await;
+  res.assert_json(json!({ "id": 1, "name": "Ghost of Yotei", "status": "draft" })).await;
+  let res = client
+    .post("/items")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "name": "Ghost of Tsushima", "status": "discontinued" }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+  let mut res = client
+    .post("/items/bulk")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "mode": "best_effort", "operations": [
+      { "op": "create", "name": "Ghost of Tsushima", "status": "released" },
+      { "op": "create", "name": "Ghost of Tsushima", "status": "announced" }
+    ] }))
+    .send()
+    .await;
+  let body = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(body["results"][0]["status"], 400);
+  assert_eq!(body["results"][1]["item"]["status"], "announced");
+
+  // Drafts have to be announced before preorders open
+  assert_eq!(put_status(&client, &token, 1, json!({ "status": "preorder" })).await, StatusCode::CONFLICT);
+  assert_eq!(put_status(&client, &token, 1, json!({ "status": "announced" })).await, StatusCode::OK);
+  let release_date = (Utc::now() + chrono::Duration::seconds(1)).to_rfc3339_opts(SecondsFormat::Secs, true);
+  let status = json!({ "status": "preorder", "release_date": release_date, "note": "Preorders open" });
+  assert_eq!(put_status(&client, &token, 1, status).await, StatusCode::OK);
+
+  let mut res = client.get("/items?status=preorder").send().await;
+  let page = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(page[0]["id"], 1);
+
+  // Released by the schedule once the release date has come
+  assert_eq!(run_release_schedule(&data_path).await.unwrap(), 0);
+  tokio::time::sleep(Duration::from_millis(2000)).await;
+  assert_eq!(run_release_schedule(&data_path).await.unwrap(), 1);
+
+  let mut res = client.get("/items/1/status").send().await;
+  let status = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(status["status"], "released");
+  assert_eq!(status["next"], json!(["discontinued"]));
+  let transitions: Vec<(&str, &str)> = status["transitions"]
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|t| (t["from"].as_str().unwrap(), t["to"].as_str().unwrap()))
+    .collect();
+  assert_eq!(transitions, vec![("draft", "announced"), ("announced", "preorder"), ("preorder", "released")]);
+  assert_eq!(status["transitions"][1]["note"], "Preorders open");
+  assert_eq!(status["transitions"][2]["actor"], Value::Null);
+
+  assert_eq!(put_status(&client, &token, 1, json!({ "status": "preorder" })).await, StatusCode::CONFLICT);
+  assert_eq!(put_status(&client, &token, 1, json!({ "status": "discontinued" })).await, StatusCode::OK);
+
+  // Reverting goes back on the fields, not on the lifecycle
+  let mut res = client
+    .post("/items/1/revert")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "revision": 1 }))
+    .send()
+    .await;
+  res.assert_status_is_ok();
+  let item = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(item["status"], "discontinued");
+  assert!(item["release_date"].is_string());
+  delete_file_if_exists(&data_path);
+}
+
+
+// RELATIONS
+async fn post_relation(client: &TestClient<Route>, token: &str, id: u64, relation: Value) -> TestResponse {
+  client
+    .post(format!("/items/{}/relations", id))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&relation)
+    .send()
+    .await
+}
+
+#[tokio::test]
+async fn test_item_relations() {
+  let data_path = "test_item_relations.json".to_string();
+  let data = json!([
+    { "id": 1, "name": "Hades II (PS5)" },
+    { "id": 2, "name": "Hades II (Switch 2)" },
+    { "id": 3, "name": "DualSense Edge" },
+    { "id": 4, "name": "Hades" }
+  ]);
+  create_data(data_path.clone(), &data);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+
+  let mut res = post_relation(&client, &token, 1, json!({ "item_id": 2, "kind": "also_available_on" })).await;
+  res.assert_status(StatusCode::CREATED);
+  let relation = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!((&relation["id"], &relation["from"], &relation["to"]), (&json!(1), &json!(1), &json!(2)));
+  post_relation(&client, &token, 3, json!({ "item_id": 1, "kind": "accessory_for" })).await.assert_status(StatusCode::CREATED);
+  post_relation(&client, &token, 1, json!({ "item_id": 4, "kind": "upgrade_of" })).await.assert_status(StatusCode::CREATED);
+
+  // Links are checked before they are saved
+  let mut res = post_relation(&client, &token, 1, json!({ "item_id": 2, "kind": "also_available_on" })).await;
+  res.assert_status(StatusCode::CONFLICT);
+  assert_eq!(res.0.take_body().into_json::<Value>().await.unwrap()["id"], 1);
+  post_relation(&client, &token, 1, json!({ "item_id": 1, "kind": "similar_to" })).await.assert_status(StatusCode::BAD_REQUEST);
+  post_relation(&client, &token, 1, json!({ "item_id": 99, "kind": "similar_to" })).await.assert_status(StatusCode::BAD_REQUEST);
+
+  let mut res = client.get("/items/1/relations").send().await;
+  let relations = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(relations["outgoing"].as_array().unwrap().len(), 2);
+  assert_eq!(relations["incoming"][0]["from"], 3);
+
+  let mut res = client.get("/items/1?expand=related").send().await;
+  res.assert_status_is_ok();
+  let item = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(item["name"], "Hades II (PS5)");
+  assert_eq!(item["related"]["also_available_on"][0]["name"], "Hades II (Switch 2)");
+  assert_eq!(item["related"]["upgrade_of"][0]["id"], 4);
+  assert_eq!(item["related"]["upgrade_of"][0]["relation_id"], 3);
+  client.get("/items/1?expand=reviews").send().await.assert_status(StatusCode::BAD_REQUEST);
+
+  let res = client
+    .delete("/items/1/relations/3")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status_is_ok();
+  let res = client
+    .delete("/items/1/relations/3")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status(StatusCode::NOT_FOUND);
+
+  // Links are changes of the item they start from
+  let res = client.get("/items/1").send().await;
+  let etag = res.0.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+  assert_eq!(etag, "\"4\"");
+  let res = client
+    .post("/items/1/relations")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .header(header::IF_MATCH, "\"1\"")
+    .body_json(&json!({ "item_id": 4, "kind": "upgrade_of" }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::PRECONDITION_FAILED);
+  let mut res = client
+    .post("/items/1/relations")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .header(header::IF_MATCH, &etag)
+    .body_json(&json!({ "item_id": 4, "kind": "upgrade_of" }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CREATED);
+  res.assert_header(header::ETAG, "\"5\"");
+  let relation = res.0.take_body().into_json::<Value>().await.unwrap();
+  let res = client
+    .delete(format!("/items/1/relations/{}", relation["id"]))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .header(header::IF_MATCH, &etag)
+    .send()
+    .await;
+  res.assert_status(StatusCode::PRECONDITION_FAILED);
+  let res = client
+    .delete(format!("/items/1/relations/{}", relation["id"]))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .header(header::IF_MATCH, "\"5\"")
+    .send()
+    .await;
+  res.assert_header(header::ETAG, "\"6\"");
+  let mut res = client.get("/items/1/history").send().await;
+  let history = res.0.take_body().into_json::<Vec<Value>>().await.unwrap();
+  let actions: Vec<&str> = history.iter().map(|r| r["action"].as_str().unwrap()).collect();
+  assert_eq!(actions, vec!["relate", "relate", "unrelate", "relate", "unrelate"]);
+
+  // Links of an item in the trash are hidden, they come back with it and go once it is purged
+  let delete = || client
+    .delete("/items/2")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send();
+  delete().await.assert_status_is_ok();
+  let mut res = client.get("/items/1/relations").send().await;
+  let relations = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(relations["outgoing"], json!([]));
+  assert_eq!(relations["incoming"].as_array().unwrap().len(), 1);
+  client
+    .post("/items/2/restore")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status_is_ok();
+  let mut res = client.get("/items/1/relations").send().await;
+  let relations = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(relations["outgoing"].as_array().unwrap().len(), 1);
+
+  delete().await.assert_status_is_ok();
+  purge_trash(&data_path, Duration::ZERO).await.unwrap();
+  let stored: Value = from_str(&read_to_string("test_item_relations.relations.json").unwrap()).unwrap();
+  assert!(stored["relations"].as_array().unwrap().iter().all(|r| r["from"] != 2 && r["to"] != 2));
+  delete_file_if_exists(&data_path);
+}
+
+
+// DUPLICATES
+#[tokio::test]
+async fn test_merge_duplicates() {
+  let data_path = "test_merge_duplicates.json".to_string();
+  let data = json!([
+    { "id": 1, "name": "The Legend of Zelda: Tears of the Kingdom", "tags": ["switch"] },
+    { "id": 2, "name": "Legend of Zelda - Tears of the Kingdom", "sku": "HAC-P-AXN7A", "tags": ["nintendo"] },
+    { "id": 3, "name": "Mario Kart World", "sku": "HAC-P-AXN7A" },
+    { "id": 4, "name": "Zelda Starter Pack", "components": [{ "item_id": 2, "quantity": 1 }] }
+  ]);
+  create_data(data_path.clone(), &data);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+
+  let mut res = client
+    .get("/items/duplicates")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status_is_ok();
+  let duplicates = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(duplicates[0]["item_ids"], json!([1, 2]));
+  assert_eq!(duplicates[0]["reasons"], json!(["name"]));
+  assert_eq!(duplicates[1]["item_ids"], json!([2, 3]));
+  assert_eq!(duplicates[1]["reasons"], json!(["sku"]));
+  assert_eq!(duplicates.as_array().unwrap().len(), 2);
+  let res = client
+    .get("/items/duplicates?threshold=2")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status(StatusCode::BAD_REQUEST);
+
+  let stock = json!({ "warehouse": "tokyo", "reason": "receipt", "quantity": 3 });
+  assert_eq!(adjust_stock(&client, &token, 1, stock).await, StatusCode::CREATED);
+  let stock = json!({ "warehouse": "tokyo", "reason": "receipt", "quantity": 2 });
+  assert_eq!(adjust_stock(&client, &token, 2, stock).await, StatusCode::CREATED);
+  let (status, _price) = post_price(&client, &token, 2, json!({ "currency": "USD", "amount": "69.99" })).await;
+  assert_eq!(status, StatusCode::CREATED);
+  post_relation(&client, &token, 3, json!({ "item_id": 2, "kind": "similar_to" })).await.assert_status(StatusCode::CREATED);
+  assert_eq!(put_status(&client, &token, 2, json!({ "status": "discontinued" })).await, StatusCode::OK);
+
+  let merge = |id: u64, from: u64| client
+    .post(format!("/items/{}/merge", id))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "item_id": from }))
+    .send();
+  merge(1, 1).await.assert_status(StatusCode::BAD_REQUEST);
+  merge(1, 99).await.assert_status(StatusCode::BAD_REQUEST);
+  merge(4, 2).await.assert_status(StatusCode::CONFLICT);
+
+  let mut res = merge(1, 2).await;
+  res.assert_status_is_ok();
+  let item = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(item["name"], "The Legend of Zelda: Tears of the Kingdom");
+  assert_eq!(item["sku"], "HAC-P-AXN7A");
+  assert_eq!(item["tags"], json!(["nintendo", "switch"]));
+
+  // The duplicate is gone and its id leads to the item
+  let res = client.get("/items/2").send().await;
+  res.assert_status(StatusCode::PERMANENT_REDIRECT);
+  res.assert_header(header::LOCATION, "/items/1");
+  let res = client.get("/items/2/stock").send().await;
+  res.assert_status(StatusCode::PERMANENT_REDIRECT);
+  res.assert_header(header::LOCATION, "/items/1/stock");
+  let res = client.get("/items/2?currency=USD").send().await;
+  res.assert_header(header::LOCATION, "/items/1?currency=USD");
+  let res = client
+    .patch("/items/2")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "sku": "HAC-P-AXN7B" }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::PERMANENT_REDIRECT);
+  res.assert_header(header::LOCATION, "/items/1");
+
+  let mut res = client.get("/items/1/stock").send().await;
+  let stock = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(stock["on_hand"], 5);
+  let mut res = client.get("/items/1/prices").send().await;
+  let prices = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(prices["current"][0]["amount"], "69.99");
+  let mut res = client.get("/items/4").send().await;
+  let bundle = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(bundle["components"], json!([{ "item_id": 1, "quantity": 1 }]));
+  let mut res = client.get("/items/1/relations").send().await;
+  let relations = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(relations["incoming"][0]["from"], 3);
+
+  // The duplicate's history and transitions move to the item
+  let res = client
+    .get("/items/2/history")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_header(header::LOCATION, "/items/1/history");
+  let mut res = client
+    .get("/items/1/history")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  let history = res.0.take_body().into_json::<Value>().await.unwrap();
+  let actions: Vec<(Value, Value)> = history
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|r| (r["action"].clone(), r.get("merged_from").cloned().unwrap_or(Value::Null)))
+    .collect();
+  assert_eq!(actions.last().unwrap(), &(json!("merge"), Value::Null));
+  assert!(actions.contains(&(json!("status"), json!(2))));
+  assert!(actions.contains(&(json!("merge"), json!(2))));
+  let mut res = client.get("/items/1/status").send().await;
+  let status = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(status["status"], "released");
+  assert_eq!((status["transitions"][0]["to"].clone(), status["transitions"][0]["merged_from"].clone()), (json!("discontinued"), json!(2)));
+
+  // The merged item has to stay within the limits of one item
+  let tags: Vec<String> = (0..50).map(|n| format!("tag{}", n)).collect();
+  client
+    .put("/items/3/tags")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "tags": tags }))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  let res = merge(1, 3).await;
+  res.assert_status(StatusCode::CONFLICT);
+  res.assert_json(json!({
+    "error": "Items can't be merged",
+    "msg": "Together the items have more than 50 tags"
+  })).await;
+  client.get("/items/3").send().await.assert_status(StatusCode::OK);
+  client
+    .put("/items/3/tags")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "tags": [] }))
+    .send()
+    .await
+    .assert_status(StatusCode::OK);
+  let stock = json!({ "warehouse": "tokyo", "reason": "receipt", "quantity": i64::MAX });
+  assert_eq!(adjust_stock(&client, &token, 1, stock.clone()).await, StatusCode::CREATED);
+  assert_eq!(adjust_stock(&client, &token, 3, stock).await, StatusCode::CREATED);
+  let res = merge(1, 3).await;
+  res.assert_status(StatusCode::CONFLICT);
+  res.assert_json(json!({
+    "error": "Items can't be merged",
+    "msg": "Item 1 can't take the stock of item 3, the stock in tokyo doesn't fit in item 1"
+  })).await;
+  let mut res = client.get("/items/3/stock").send().await;
+  let stock = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(stock["on_hand"], i64::MAX);
+  delete_file_if_exists(&data_path);
+}
+
+
+// BARCODES
+#[tokio::test]
+async fn test_item_barcodes() {
+  let data_path = "test_item_barcodes.json".to_string();
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let post = |item: Value| client
+    .post("/items")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&item)
+    .send();
+
+  // UPC-A is kept as EAN-13 with a leading 0
+  let mut res = post(json!({ "name": "Splatoon 3", "barcodes": ["4902370548495", "0 45496 59052 9"] })).await;
+  res.assert_status(StatusCode::CREATED);
+  let item = res.0.take_body().into_json::<Value>().await.unwrap();
+  assert_eq!(item["barcodes"], json!(["4902370548495", "0045496590529"]));
+
+  post(json!({ "name": "Kirby", "barcodes": ["4902370548496"] })).await.assert_status(StatusCode::BAD_REQUEST);
+  post(json!({ "name": "Kirby", "barcodes": ["49023705"] })).await.assert_status(StatusCode::BAD_REQUEST);
+  let res = post(json!({ "name": "Kirby", "barcodes": ["045496590529"] })).await;
+  res.assert_status(StatusCode::CONFLICT);
+  res.assert_json(json!({ "error": "Barcode already in use", "msg": "0045496590529 belongs to another item", "id": 1 })).await;
+  post(json!({ "name": "Kirby", "barcodes": ["4539810123457"] })).await.assert_status(StatusCode::CREATED);
+
+  // Scanners find the item by any of its codes
+  let mut res = client.get("/items/by-barcode/045496590529").send().await;
+  res.assert_status_is_ok();
+  assert_eq!(res.0.take_body().into_json::<Value>().await.unwrap()["name"], "Splatoon 3");
+  client.get("/items/by-barcode/4006381333931").send().await.assert_status(StatusCode::NOT_FOUND);
+  client.get("/items/by-barcode/abc").send().await.assert_status(StatusCode::BAD_REQUEST);
+
+  let patch = |id: u64, barcodes: Value| client
+    .patch(format!("/items/{}", id))
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "barcodes": barcodes }))
+    .send();
+  patch(2, json!(["4902370548495"])).await.assert_status(StatusCode::CONFLICT);
+  patch(1, json!(["4902370548495"])).await.assert_status_is_ok();
+  patch(2, json!(["4539810123457", "0045496590529"])).await.assert_status_is_ok();
+  let mut res = client.get("/items/by-barcode/0045496590529").send().await;
+  assert_eq!(res.0.take_body().into_json::<Value>().await.unwrap()["name"], "Kirby");
+
+  // Reverting and restoring can't bring back a code another item has taken
+  let res = client
+    .post("/items/1/revert")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "revision": 1 }))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+  res.assert_json(json!({ "error": "Barcode already in use", "msg": "0045496590529 belongs to another item", "id": 2 })).await;
+  client
+    .delete("/items/2")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await
+    .assert_status_is_ok();
+  patch(1, json!(["4902370548495", "4539810123457"])).await.assert_status_is_ok();
+  let res = client
+    .post("/items/2/restore")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .send()
+    .await;
+  res.assert_status(StatusCode::CONFLICT);
+  res.assert_json(json!({ "error": "Barcode already in use", "msg": "4539810123457 belongs to another item", "id": 1 })).await;
+  delete_file_if_exists(&data_path);
+}
+
+
+// CARTS
+async fn cart_request(client: &TestClient<Route>, method: &str, path: &str, auth: (&str, &str), body: Value) -> (StatusCode, Value) {
+  let req = match method {
+    "GET" => client.get(path),
+    "PUT" => client.put(path),
+    "DELETE" => client.delete(path),
+    _ => client.post(path),
+  };
+  let mut res = req.header(auth.0, auth.1).body_json(&body).send().await;
+  let status = res.0.status();
+  (status, res.0.take_body().into_json::<Value>().await.unwrap())
+}
+
+#[tokio::test]
+async fn test_carts() {
+  let data_path = "test_carts.json".to_string();
+  let data = json!([
+    { "id": 1, "name": "Splatoon 3" },
+    { "id": 2, "name": "Metroid Prime 4", "status": "announced" },
+    { "id": 3, "name": "Kirby" }
+  ]);
+  create_data(data_path.clone(), &data);
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let bearer = format!("Bearer {}", token);
+  let user = ("Authorization", bearer.as_str());
+  assert_eq!(post_price(&client, &token, 1, json!({ "currency": "USD", "amount": "59.99" })).await.0, StatusCode::CREATED);
+  let stock = json!({ "warehouse": "tokyo", "reason": "receipt", "quantity": 2 });
+  assert_eq!(adjust_stock(&client, &token, 1, stock).await, StatusCode::CREATED);
+
+  let (status, _cart) = cart_request(&client, "GET", "/cart", ("Accept", "*/*"), json!({})).await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+  // Customers shop before logging in with an anonymous cart
+  let (status, cart) = cart_request(&client, "POST", "/cart/anonymous", ("Accept", "*/*"), json!({})).await;
+  assert_eq!(status, StatusCode::CREATED);
+  let cart_token = cart["cart_token"].as_str().unwrap().to_string();
+  let anonymous = ("X-Cart-Token", cart_token.as_str());
+  let (status, cart) = cart_request(&client, "POST", "/cart/items", anonymous, json!({ "item_id": 1 })).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(cart["total"], "59.99");
+  assert_eq!(cart["valid"], true);
+
+  let (status, _cart) = cart_request(&client, "POST", "/cart/items", anonymous, json!({ "item_id": 2 })).await;
+  assert_eq!(status, StatusCode::CONFLICT);
+  let (status, _cart) = cart_request(&client, "POST", "/cart/items", anonymous, json!({ "item_id": 99 })).await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  let (status, error) = cart_request(&client, "POST", "/cart/items", anonymous, json!({ "item_id": 1, "quantity": 5 })).await;
+  assert_eq!(status, StatusCode::CONFLICT);
+  assert_eq!(error["available"], 2);
+  let (status, _cart) = cart_request(&client, "POST", "/cart/items", anonymous, json!({ "item_id": 1, "quantity": u64::MAX })).await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  let (status, cart) = cart_request(&client, "POST", "/cart/items", user, json!({ "item_id": 3, "quantity": 2 })).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(cart["lines"][0]["problems"][0]["code"], "no_price");
+
+  // Reads check the cart against the catalogue as it is now
+  assert_eq!(post_price(&client, &token, 1, json!({ "currency": "USD", "amount": "69.99" })).await.0, StatusCode::CREATED);
+  let (_status, cart) = cart_request(&client, "GET", "/cart", anonymous, json!({})).await;
+  assert_eq!(cart["lines"][0]["unit_price"], "69.99");
+  assert_eq!(cart["lines"][0]["problems"][0]["code"], "price_changed");
+  assert_eq!(cart["valid"], false);
+
+  // Logging in merges the anonymous cart into the user's
+  let (status, cart) = cart_request(&client, "POST", "/cart/merge", user, json!({ "cart_token": cart_token })).await;
+  assert_eq!(status, StatusCode::OK);
+  let item_ids: Vec<u64> = cart["lines"].as_array().unwrap().iter().map(|l| l["item_id"].as_u64().unwrap()).collect();
+  assert_eq!(item_ids, vec![3, 1]);
+  let (status, _cart) = cart_request(&client, "GET", "/cart", anonymous, json!({})).await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+
+  let (status, cart) = cart_request(&client, "PUT", "/cart/items/1", user, json!({ "quantity": 2 })).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(cart["lines"][1]["problems"], json!([]));
+  let (status, cart) = cart_request(&client, "DELETE", "/cart/items/3", user, json!({})).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(cart["total"], "139.98");
+  assert_eq!(cart["valid"], true);
+  let (status, _cart) = cart_request(&client, "DELETE", "/cart/items/3", user, json!({})).await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+
+  // Lines of a merged item follow it into the item it was merged into
+  client
+    .post("/items/3/merge")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body_json(&json!({ "item_id": 1 }))
+    .send()
+    .await
+    .assert_status_is_ok();
+  let (_status, cart) = cart_request(&client, "GET", "/cart", user, json!({})).await;
+  assert_eq!(cart["lines"][0]["item_id"], 3);
+  assert_eq!(cart["lines"][0]["name"], "Kirby");
+  assert_eq!(cart["total"], "139.98");
+  assert_eq!(cart["valid"], true);
+  let (status, cart) = cart_request(&client, "PUT", "/cart/items/1", user, json!({ "quantity": 1 })).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(cart["lines"].as_array().unwrap().len(), 1);
+  assert_eq!(cart["total"], "69.99");
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_cart_before_any_item() {
+  let data_path = "test_cart_before_any_item.json".to_string();
+  let routes = all_routes(data_path.clone());
+  let client = TestClient::new(routes);
+
+  let (status, cart) = cart_request(&client, "POST", "/cart/anonymous", ("Accept", "*/*"), json!({})).await;
+  assert_eq!(status, StatusCode::CREATED);
+  assert_eq!(cart["lines"], json!([]));
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_cart_expiry() {
+  let data_path = "test_cart_expiry.json".to_string();
+  create_data(data_path.clone(), &json!([{ "id": 1, "name": "Splatoon 3" }]));
+  let config = Config { cart_ttl: Duration::from_secs(1), ..Config::default() };
+  let routes = all_routes_with_config(data_path.clone(), config.clone());
+  let client = TestClient::new(routes);
+
+  let (_status, cart) = cart_request(&client, "POST", "/cart/anonymous", ("Accept", "*/*"), json!({})).await;
+  let cart_token = cart["cart_token"].as_str().unwrap().to_string();
+  assert!(cart["expires_at"].is_string());
+  tokio::time::sleep(Duration::from_millis(1100)).await;
+
+  let (status, _cart) = cart_request(&client, "GET", "/cart", ("X-Cart-Token", &cart_token), json!({})).await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+  assert_eq!(expire_carts(&data_path, config.cart_ttl).await.unwrap(), 1);
+  assert_eq!(expire_carts(&data_path, config.cart_ttl).await.unwrap(), 0);
+  delete_file_if_exists(&data_path);
+}
+
+#[tokio::test]
+async fn test_anonymous_cart_limit() {
+  let data_path = "test_anonymous_cart_limit.json".to_string();
+  create_data(data_path.clone(), &json!([{ "id": 1, "name": "Splatoon 3" }]));
+  let config = Config { max_anonymous_carts: 2, ..Config::default() };
+  let routes = all_routes_with_config(data_path.clone(), config);
+  let client = TestClient::new(routes);
+  let token = get_jwt(&client).await;
+  let bearer = format!("Bearer {}", token);
+  let user = ("Authorization", bearer.as_str());
+
+  let (status, cart) = cart_request(&client, "POST", "/cart/anonymous", ("Accept", "*/*"), json!({})).await;
+  assert_eq!(status, StatusCode::CREATED);
+  let cart_token = cart["cart_token"].as_str().unwrap().to_string();
+  let (status, _cart) = cart_request(&client, "POST", "/cart/anonymous", ("Accept", "*/*"), json!({})).await;
+  assert_eq!(status, StatusCode::CREATED);
+  let (status, error) = cart_request(&client, "POST", "/cart/anonymous", ("Accept", "*/*"), json!({})).await;
+  assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+  assert_eq!(error["error"], "Too many carts");
+
+  // Carts of users don't count, merging an anonymous cart frees its place
+  let (status, _cart) = cart_request(&client, "POST", "/cart/items", user, json!({ "item_id": 1 })).await;
+  assert_eq!(status, StatusCode::OK);
+  let (status, _cart) = cart_request(&client, "POST", "/cart/merge", user, json!({ "cart_token": cart_token })).await;
+  assert_eq!(status, StatusCode::OK);
+  let (status, _cart) = cart_request(&client, "POST", "/cart/anonymous", ("Accept", "*/*"), json!({})).await;
+  assert_eq!(status, StatusCode::CREATED);
+  delete_file_if_exists(&data_path);
+}
+
+
+
+fn create_data<T: serde::Serialize>(data_path: String, data: &T) {
+  let json_str = to_string_pretty(data).expect("Unable to convert to string");
+
+  let mut file = OpenOptions::new()
+    .write(true)
+    .create(true)
+    .truncate(true)
+    .open(data_path)
+    .expect("Unable to create and open file");
+  file.write_all(json_str.as_bytes()).expect("Error writing to a file");
+}
+
+fn delete_file_if_exists(path: &str) {
+  if std::path::Path::new(path).exists() {
+    remove_file(path).unwrap();
+  }
+
+  // Sidecar files and directories stored next to the data file,
+  // e.g. test.versions.json and test.media
+  let prefix = format!("{}.", path.trim_end_matches(".json"));
+  for entry in read_dir(".").unwrap().flatten() {
+    let name = entry.file_name().to_string_lossy().to_string();
+    if !name.starts_with(&prefix) {
+      continue;
+    }
+    if entry.path().is_dir() {
+      remove_dir_all(entry.path()).unwrap();
+    } else if name.ends_with(".json") {
+      remove_file(entry.path()).unwrap();
+    }
+  }
+}
+
+
 async fn get_jwt(client: &TestClient<Route>) -> String {
   // Login user: admin1 ps: admin1
 